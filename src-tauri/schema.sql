@@ -30,6 +30,8 @@ CREATE TABLE IF NOT EXISTS doc (
   title TEXT NOT NULL,
   lang TEXT DEFAULT 'en',
   is_deleted INTEGER NOT NULL DEFAULT 0,
+  deleted_at TEXT,
+  trashed_slug TEXT, -- original slug while trashed; `slug` becomes '.trash/<id>' so it can be reused
   current_version_id TEXT REFERENCES doc_version(id),
   size_bytes INTEGER DEFAULT 0,
   line_count INTEGER DEFAULT 0,
@@ -65,8 +67,9 @@ CREATE TABLE IF NOT EXISTS doc_version (
   author TEXT,
   message TEXT,
   created_at TEXT NOT NULL DEFAULT (datetime('now')),
-  hash TEXT NOT NULL UNIQUE
+  hash TEXT NOT NULL -- doc_id:blake3(body); repeats when a doc returns to an earlier body
 );
+CREATE INDEX IF NOT EXISTS idx_doc_version_doc ON doc_version(doc_id, created_at);
 
 CREATE TABLE IF NOT EXISTS link (
   id TEXT PRIMARY KEY,
@@ -90,6 +93,77 @@ CREATE TABLE IF NOT EXISTS provenance (
   created_at TEXT NOT NULL DEFAULT (datetime('now'))
 );
 
+-- Line-range anchors; text/prefix/suffix let writes re-place them (status 'orphaned' when lost)
+CREATE TABLE IF NOT EXISTS anchor (
+  id TEXT PRIMARY KEY,
+  doc_id TEXT NOT NULL REFERENCES doc(id) ON DELETE CASCADE,
+  version_id TEXT,
+  line_start INTEGER NOT NULL,
+  line_end INTEGER NOT NULL,
+  text TEXT NOT NULL DEFAULT '',
+  prefix TEXT NOT NULL DEFAULT '',
+  suffix TEXT NOT NULL DEFAULT '',
+  status TEXT NOT NULL DEFAULT 'ok' CHECK (status IN ('ok','orphaned')),
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_anchor_doc ON anchor(doc_id);
+
+-- Review threads on a doc, usually pinned to an anchor; AI traces sharing the anchor show up in the thread
+CREATE TABLE IF NOT EXISTS comment_thread (
+  id TEXT PRIMARY KEY,
+  doc_id TEXT NOT NULL REFERENCES doc(id) ON DELETE CASCADE,
+  anchor_id TEXT REFERENCES anchor(id) ON DELETE SET NULL,
+  created_by TEXT NOT NULL DEFAULT '',
+  resolved INTEGER NOT NULL DEFAULT 0,
+  resolved_by TEXT,
+  resolved_at TEXT,
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_comment_thread_doc ON comment_thread(doc_id);
+
+CREATE TABLE IF NOT EXISTS comment (
+  id TEXT PRIMARY KEY,
+  thread_id TEXT NOT NULL REFERENCES comment_thread(id) ON DELETE CASCADE,
+  author TEXT NOT NULL DEFAULT '',
+  body TEXT NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_comment_thread ON comment(thread_id, created_at);
+
+-- Proposed edits: a unified diff against base_version_id, applied through the normal update path on accept
+CREATE TABLE IF NOT EXISTS suggestion (
+  id TEXT PRIMARY KEY,
+  doc_id TEXT NOT NULL REFERENCES doc(id) ON DELETE CASCADE,
+  base_version_id TEXT NOT NULL,
+  patch TEXT NOT NULL,
+  message TEXT,
+  author TEXT NOT NULL DEFAULT '',
+  source TEXT NOT NULL DEFAULT 'user' CHECK (source IN ('user','ai','plugin')),
+  trace_id TEXT,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending','accepted','rejected')),
+  version_id TEXT, -- version written on accept
+  reason TEXT,
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_suggestion_doc ON suggestion(doc_id, status);
+
+CREATE TABLE IF NOT EXISTS prompt_template (
+  id TEXT PRIMARY KEY,
+  repo_id TEXT REFERENCES repo(id) ON DELETE CASCADE, -- NULL: app-wide
+  name TEXT NOT NULL,
+  description TEXT,
+  system TEXT,
+  user TEXT NOT NULL,
+  context JSON, -- context strategies for runs using the template
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_prompt_template_name ON prompt_template(COALESCE(repo_id,''), name);
+
 CREATE TABLE IF NOT EXISTS scan_job (
   id TEXT PRIMARY KEY,
   repo_id TEXT NOT NULL REFERENCES repo(id) ON DELETE CASCADE,
@@ -114,6 +188,38 @@ CREATE TABLE IF NOT EXISTS ai_trace (
   created_at TEXT NOT NULL DEFAULT (datetime('now'))
 );
 
+-- Provider requests that failed (each retry is one); answered ones are traces
+CREATE TABLE IF NOT EXISTS ai_attempt (
+  id INTEGER PRIMARY KEY,
+  repo_id TEXT NOT NULL REFERENCES repo(id) ON DELETE CASCADE,
+  provider TEXT NOT NULL,
+  error TEXT NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_ai_attempt_provider ON ai_attempt(provider, created_at);
+CREATE INDEX IF NOT EXISTS idx_ai_attempt_repo ON ai_attempt(repo_id, created_at);
+
+CREATE TABLE IF NOT EXISTS ai_conversation (
+  id TEXT PRIMARY KEY,
+  repo_id TEXT NOT NULL REFERENCES repo(id) ON DELETE CASCADE,
+  doc_id TEXT NOT NULL REFERENCES doc(id) ON DELETE CASCADE,
+  anchor_id TEXT,
+  title TEXT,
+  forked_from TEXT, -- conversation this one was forked from
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_ai_conversation_doc ON ai_conversation(doc_id, updated_at);
+
+-- Turns of a conversation in order; forks share the traces of their common turns
+CREATE TABLE IF NOT EXISTS ai_conversation_message (
+  conversation_id TEXT NOT NULL REFERENCES ai_conversation(id) ON DELETE CASCADE,
+  seq INTEGER NOT NULL,
+  trace_id TEXT NOT NULL REFERENCES ai_trace(id) ON DELETE CASCADE,
+  PRIMARY KEY (conversation_id, seq)
+);
+CREATE INDEX IF NOT EXISTS idx_ai_conversation_message_trace ON ai_conversation_message(trace_id);
+
 CREATE TABLE IF NOT EXISTS plugin (
   id TEXT PRIMARY KEY,
   name TEXT NOT NULL UNIQUE,
@@ -138,6 +244,51 @@ CREATE VIRTUAL TABLE IF NOT EXISTS doc_fts USING fts5(
   tokenize='unicode61 remove_diacritics 2'
 );
 
+-- Trigram index for substring and typo-tolerant search (stores its own text; rowid = doc.rowid)
+CREATE VIRTUAL TABLE IF NOT EXISTS doc_fts_tri USING fts5(
+  title, body, slug, tokenize='trigram'
+);
+
+-- Opt-in index over every version body (app_setting 'history_index'); rowid = doc_version.rowid
+CREATE VIRTUAL TABLE IF NOT EXISTS doc_version_fts USING fts5(
+  body, tokenize='unicode61 remove_diacritics 2'
+);
+
+-- Tags per doc (inline `#tag` and frontmatter `tags:`), maintained with the FTS rows; feeds search facets
+CREATE TABLE IF NOT EXISTS doc_tag (
+  doc_id TEXT NOT NULL REFERENCES doc(id) ON DELETE CASCADE,
+  tag TEXT NOT NULL,
+  PRIMARY KEY (doc_id, tag)
+);
+CREATE INDEX IF NOT EXISTS idx_doc_tag_tag ON doc_tag(tag);
+
+-- Named search parameters; watched searches remember their last result ids to detect changes after scans
+CREATE TABLE IF NOT EXISTS saved_search (
+  id TEXT PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  params JSON NOT NULL,
+  watch INTEGER NOT NULL DEFAULT 0,
+  last_ids JSON,
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+-- Chunk embeddings for semantic search (vector = little-endian f32; model identifies the backend)
+CREATE TABLE IF NOT EXISTS doc_embedding (
+  doc_id TEXT NOT NULL REFERENCES doc(id) ON DELETE CASCADE,
+  chunk_index INTEGER NOT NULL,
+  line_start INTEGER NOT NULL,
+  line_end INTEGER NOT NULL,
+  content_hash TEXT NOT NULL,
+  model TEXT NOT NULL,
+  dim INTEGER NOT NULL,
+  preview TEXT,
+  vector BLOB NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  PRIMARY KEY (doc_id, chunk_index)
+);
+CREATE INDEX IF NOT EXISTS idx_doc_embedding_model ON doc_embedding(model);
+
 -- App-wide settings key/value store (JSON values)
 CREATE TABLE IF NOT EXISTS app_setting (
   key TEXT PRIMARY KEY,
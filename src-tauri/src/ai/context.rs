@@ -0,0 +1,532 @@
+//! Context assembly for `ai_run`.
+//!
+//! Sources come from strategies, in priority order (`ai_context` repo setting, else the
+//! app setting, else [`ContextSettings::default`]):
+//! - `selection`: the text selected in the editor
+//! - `anchor`: lines around the anchor; `section`: the heading section holding it; `doc`: the whole doc
+//! - `embeds`: docs transcluded with `![[slug]]` or `![[slug#Heading]]`
+//! - `backlinks`: lines around links to the doc; `outbound`: the opening of docs it links to
+//! - `search`: top full-text hits for the prompt
+//!
+//! The default is `selection` and `anchor`, the run's own doc only. The strategies that
+//! read other docs are opt-in per repo (or per run): the app setting can't turn them on.
+//!
+//! Sources are taken in that order while they fit the token budget of the provider's model
+//! (`config.context_tokens`, `{"<model>": n, "*": n}`, default 4000). The first that does not
+//! fit is cut to what is left, and everything after it is dropped. Each doc goes in once.
+
+use super::usage::estimate_tokens;
+use crate::db::Db;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const STRATEGIES: &[&str] = &["selection", "anchor", "section", "doc", "embeds", "backlinks", "outbound", "search"];
+/// Strategies that send text from other docs
+pub const CROSS_DOC: &[&str] = &["embeds", "backlinks", "outbound", "search"];
+const DEFAULT_CONTEXT_TOKENS: i64 = 4000;
+/// A source cut shorter than this is dropped instead
+const MIN_PARTIAL_TOKENS: i64 = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ContextSettings {
+    /// Strategy names, highest priority first
+    pub strategies: Vec<String>,
+    /// Lines either side of the anchor for `anchor`
+    pub anchor_lines: usize,
+    /// Most docs taken by each of `embeds`, `backlinks`, `outbound` and `search`
+    pub max_docs: usize,
+    /// Lines taken from a linked doc or around a backlink
+    pub excerpt_lines: usize,
+}
+
+impl Default for ContextSettings {
+    fn default() -> Self {
+        ContextSettings {
+            strategies: ["selection", "anchor"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            anchor_lines: 12,
+            max_docs: 5,
+            excerpt_lines: 20,
+        }
+    }
+}
+
+pub fn validate_strategies(strategies: &[String]) -> Result<(), String> {
+    match strategies.iter().find(|s| !STRATEGIES.contains(&s.as_str())) {
+        Some(s) => Err(format!("invalid_context_strategy: {}", s)),
+        None => Ok(()),
+    }
+}
+
+/// Effective settings: the repo's `ai_context`, else the app setting (without `CROSS_DOC`
+/// strategies), else defaults.
+pub fn settings(conn: &Connection, repo_id: Option<&str>) -> ContextSettings {
+    let repo: Option<String> = repo_id.and_then(|r| {
+        conn.query_row(
+            "SELECT json_extract(settings,'$.ai_context') FROM repo WHERE id=?1 OR name=?1",
+            params![r],
+            |row| row.get(0),
+        )
+        .ok()
+        .flatten()
+    });
+    if let Some(cfg) = repo.and_then(|s| serde_json::from_str(&s).ok()) {
+        return cfg;
+    }
+    let mut cfg: ContextSettings = conn
+        .query_row("SELECT value FROM app_setting WHERE key='ai_context'", [], |r| r.get::<_, String>(0))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    cfg.strategies.retain(|s| !CROSS_DOC.contains(&s.as_str()));
+    cfg
+}
+
+/// Context budget in tokens for the provider's configured model.
+pub fn token_budget(conn: &Connection, provider: &str) -> i64 {
+    let raw: Option<String> = conn
+        .query_row(
+            "SELECT json_extract(config,'$.context_tokens') FROM provider WHERE name=?1",
+            params![provider],
+            |r| r.get(0),
+        )
+        .ok()
+        .flatten();
+    let budgets: HashMap<String, i64> = raw.and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default();
+    super::provider::configured_model(conn, provider)
+        .and_then(|m| budgets.get(&m).copied())
+        .or_else(|| budgets.get("*").copied())
+        .unwrap_or(DEFAULT_CONTEXT_TOKENS)
+}
+
+/// The doc a run is about.
+pub struct Target<'a> {
+    pub doc_id: &'a str,
+    pub repo_id: &'a str,
+    pub slug: &'a str,
+    pub title: &'a str,
+    pub body: &'a str,
+    /// 1-based line of the anchor
+    pub line: usize,
+    pub selection: Option<&'a str>,
+    pub prompt: &'a str,
+}
+
+#[derive(Clone, Debug)]
+pub struct Source {
+    pub kind: &'static str,
+    pub doc_id: String,
+    pub slug: String,
+    pub title: String,
+    /// 1-based inclusive line range in that doc
+    pub lines: Option<(usize, usize)>,
+    pub text: String,
+}
+
+impl Source {
+    fn label(&self) -> String {
+        match self.kind {
+            "selection" => "Selection".into(),
+            "anchor" => format!("Excerpt of {}", self.title),
+            "section" => format!("Section of {}", self.title),
+            "doc" => format!("Document {}", self.title),
+            "embeds" => format!("Embedded: {} ({})", self.title, self.slug),
+            "backlinks" => format!("Links here: {} ({})", self.title, self.slug),
+            "outbound" => format!("Linked: {} ({})", self.title, self.slug),
+            _ => format!("Search hit: {} ({})", self.title, self.slug),
+        }
+    }
+
+    fn own_doc(&self) -> bool {
+        matches!(self.kind, "selection" | "anchor" | "section" | "doc")
+    }
+}
+
+fn heading_level(line: &str) -> Option<usize> {
+    let t = line.trim_start_matches(' ');
+    if line.len() - t.len() > 3 {
+        return None;
+    }
+    let level = t.chars().take_while(|c| *c == '#').count();
+    let rest = &t[level..];
+    ((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' '))).then_some(level)
+}
+
+/// `(0-based line, level, text)` of each ATX heading outside code fences.
+fn headings(lines: &[&str]) -> Vec<(usize, usize, String)> {
+    let mut out = Vec::new();
+    let mut in_fence = false;
+    for (i, line) in lines.iter().enumerate() {
+        let t = line.trim_start();
+        if t.starts_with("```") || t.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        if let Some(level) = heading_level(line) {
+            out.push((i, level, line.trim().trim_start_matches('#').trim().to_string()));
+        }
+    }
+    out
+}
+
+/// 1-based inclusive lines of the section starting at heading `idx` of `heads`.
+fn section_from(heads: &[(usize, usize, String)], idx: usize, len: usize) -> (usize, usize) {
+    let (start, level, _) = heads[idx];
+    let end = heads[idx + 1..]
+        .iter()
+        .find(|(_, l, _)| *l <= level)
+        .map(|(i, _, _)| *i)
+        .unwrap_or(len);
+    (start + 1, end)
+}
+
+/// Lines of the heading section holding `line` (the lines before the first heading count as one).
+pub fn section_at(body: &str, line: usize) -> (usize, usize) {
+    let lines: Vec<&str> = body.lines().collect();
+    let heads = headings(&lines);
+    match heads.iter().rposition(|(i, _, _)| *i < line.max(1)) {
+        Some(idx) => section_from(&heads, idx, lines.len()),
+        None => (1, heads.first().map(|(i, _, _)| *i).unwrap_or(lines.len())),
+    }
+}
+
+/// Lines of the section under the heading named `name` (case-insensitive).
+pub fn section_named(body: &str, name: &str) -> Option<(usize, usize)> {
+    let lines: Vec<&str> = body.lines().collect();
+    let heads = headings(&lines);
+    let idx = heads.iter().position(|(_, _, t)| t.eq_ignore_ascii_case(name.trim()))?;
+    Some(section_from(&heads, idx, lines.len()))
+}
+
+/// Lines `start..=end` (1-based) of `body`.
+pub fn slice(body: &str, (start, end): (usize, usize)) -> String {
+    body.lines()
+        .skip(start.saturating_sub(1))
+        .take((end + 1).saturating_sub(start.max(1)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `(slug, heading)` of each `![[...]]` embed outside code, in order, without repeats.
+pub fn embeds(body: &str) -> Vec<(String, Option<String>)> {
+    let mut out: Vec<(String, Option<String>)> = Vec::new();
+    let mut in_fence = false;
+    for line in body.lines() {
+        let t = line.trim_start();
+        if t.starts_with("```") || t.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let mut rest = line;
+        while let Some(start) = rest.find("![[") {
+            let after = &rest[start + 3..];
+            let Some(end) = after.find("]]") else { break };
+            let target = after[..end].split('|').next().unwrap_or_default();
+            let (slug, heading) = match target.split_once('#') {
+                Some((s, h)) => (s.trim(), Some(h.trim().to_string()).filter(|h| !h.is_empty())),
+                None => (target.trim(), None),
+            };
+            let item = (slug.to_string(), heading);
+            if !slug.is_empty() && !out.contains(&item) {
+                out.push(item);
+            }
+            rest = &after[end + 2..];
+        }
+    }
+    out
+}
+
+/// Body of a doc's current version.
+fn current_body(conn: &Connection, version_id: Option<String>) -> String {
+    version_id
+        .and_then(|v| crate::commands::version_body(conn, &v).ok())
+        .unwrap_or_default()
+}
+
+type DocRow = (String, String, String, Option<String>, Option<i64>);
+
+fn doc_rows(conn: &Connection, sql: &str, doc_id: &str, limit: usize) -> Result<Vec<DocRow>, String> {
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![doc_id, limit as i64], |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+/// Sources for `target` from each strategy, in priority order. Empty ones are left out.
+pub fn gather(db: &Db, target: &Target, cfg: &ContextSettings) -> Result<Vec<Source>, String> {
+    let own = |kind: &'static str, lines: Option<(usize, usize)>, text: String| Source {
+        kind,
+        doc_id: target.doc_id.to_string(),
+        slug: target.slug.to_string(),
+        title: target.title.to_string(),
+        lines,
+        text,
+    };
+    let line_count = target.body.lines().count();
+    let excerpt = |body: &str, around: Option<usize>| -> ((usize, usize), String) {
+        let n = cfg.excerpt_lines.max(1);
+        let total = body.lines().count().max(1);
+        let start = match around {
+            Some(l) => l.saturating_sub(n / 2).max(1),
+            None => 1,
+        };
+        let range = (start, (start + n - 1).min(total));
+        (range, slice(body, range))
+    };
+    let mut out: Vec<Source> = Vec::new();
+    // Docs already in, so graph and search strategies don't repeat them
+    let mut seen: HashSet<String> = HashSet::from([target.doc_id.to_string()]);
+    for strategy in &cfg.strategies {
+        match strategy.as_str() {
+            "selection" => {
+                if let Some(sel) = target.selection.filter(|s| !s.trim().is_empty()) {
+                    out.push(own("selection", None, sel.to_string()));
+                }
+            }
+            "anchor" => {
+                let idx = target.line.max(1) - 1;
+                let range = (idx.saturating_sub(cfg.anchor_lines) + 1, (idx + cfg.anchor_lines + 1).min(line_count));
+                out.push(own("anchor", Some(range), slice(target.body, range)));
+            }
+            "section" => {
+                let range = section_at(target.body, target.line);
+                out.push(own("section", Some(range), slice(target.body, range)));
+            }
+            "doc" => out.push(own("doc", Some((1, line_count)), target.body.to_string())),
+            "embeds" => {
+                let conn = db.0.lock();
+                for (slug, heading) in embeds(target.body).into_iter().take(cfg.max_docs) {
+                    let row: Option<(String, String, Option<String>)> = conn
+                        .query_row(
+                            "SELECT id, title, current_version_id FROM doc WHERE repo_id=?1 AND slug=?2 AND is_deleted=0",
+                            params![target.repo_id, slug],
+                            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+                        )
+                        .ok();
+                    let Some((id, title, version)) = row else { continue };
+                    let body = current_body(&conn, version);
+                    let range = match heading.as_deref() {
+                        Some(h) => match section_named(&body, h) {
+                            Some(r) => r,
+                            None => continue,
+                        },
+                        None => (1, body.lines().count()),
+                    };
+                    // The same doc may be embedded by section more than once
+                    seen.insert(id.clone());
+                    out.push(Source {
+                        kind: "embeds",
+                        doc_id: id,
+                        slug,
+                        title,
+                        lines: Some(range),
+                        text: slice(&body, range),
+                    });
+                }
+            }
+            kind @ ("backlinks" | "outbound") => {
+                let sql = if kind == "backlinks" {
+                    "SELECT d.id, d.slug, d.title, d.current_version_id, MIN(l.line_start) FROM link l JOIN doc d ON d.id=l.from_doc_id \
+                     WHERE l.to_doc_id=?1 AND d.is_deleted=0 GROUP BY d.id ORDER BY d.updated_at DESC LIMIT ?2"
+                } else {
+                    "SELECT d.id, d.slug, d.title, d.current_version_id, NULL FROM link l JOIN doc d ON d.id=l.to_doc_id \
+                     WHERE l.from_doc_id=?1 AND d.is_deleted=0 GROUP BY d.id ORDER BY MIN(l.line_start) LIMIT ?2"
+                };
+                let conn = db.0.lock();
+                // Over-fetch: some rows may already be in
+                for (id, slug, title, version, line) in doc_rows(&conn, sql, target.doc_id, cfg.max_docs * 2)? {
+                    if out.iter().filter(|s| s.kind == kind).count() >= cfg.max_docs || !seen.insert(id.clone()) {
+                        continue;
+                    }
+                    let body = current_body(&conn, version);
+                    let (range, text) = excerpt(&body, line.map(|l| l.max(1) as usize));
+                    out.push(Source {
+                        kind: if kind == "backlinks" { "backlinks" } else { "outbound" },
+                        doc_id: id,
+                        slug,
+                        title,
+                        lines: Some(range),
+                        text,
+                    });
+                }
+            }
+            "search" => {
+                // Search is best effort: a prompt that makes no valid query adds nothing
+                let hits = crate::commands::search_core(
+                    db,
+                    crate::commands::SearchRequest {
+                        repo_id: Some(target.repo_id.to_string()),
+                        query: target.prompt.to_string(),
+                        limit: Some((cfg.max_docs * 2) as i64),
+                        offset: None,
+                        mode: None,
+                    },
+                )
+                .unwrap_or_default();
+                let conn = db.0.lock();
+                let mut taken = 0;
+                for hit in hits {
+                    if taken >= cfg.max_docs || !seen.insert(hit.id.clone()) {
+                        continue;
+                    }
+                    let row: Option<(String, Option<String>)> = conn
+                        .query_row(
+                            "SELECT title, current_version_id FROM doc WHERE id=?1 AND is_deleted=0",
+                            params![hit.id],
+                            |r| Ok((r.get(0)?, r.get(1)?)),
+                        )
+                        .ok();
+                    let Some((title, version)) = row else { continue };
+                    let (range, text) = excerpt(&current_body(&conn, version), None);
+                    taken += 1;
+                    out.push(Source {
+                        kind: "search",
+                        doc_id: hit.id,
+                        slug: hit.slug,
+                        title,
+                        lines: Some(range),
+                        text,
+                    });
+                }
+            }
+            other => return Err(format!("invalid_context_strategy: {}", other)),
+        }
+    }
+    out.retain(|s| !s.text.trim().is_empty());
+    Ok(out)
+}
+
+/// Context text that went into the prompt, with what went in and what was left out.
+#[derive(Debug, Default)]
+pub struct Assembled {
+    pub text: String,
+    /// `{ kind, doc_id, slug, lines?, tokens, truncated? }` per source used
+    pub sources: Vec<serde_json::Value>,
+    /// `{ kind, doc_id, slug }` per source over the budget
+    pub dropped: Vec<serde_json::Value>,
+}
+
+fn describe(s: &Source) -> serde_json::Value {
+    let mut v = serde_json::json!({"kind": s.kind, "doc_id": s.doc_id, "slug": s.slug});
+    if let Some((start, end)) = s.lines {
+        v["lines"] = serde_json::json!([start, end]);
+    }
+    v
+}
+
+/// Render sources in order within `budget` tokens. The run's own text goes first without
+/// a label when it leads; every other source is introduced by a `--- <label>` line.
+pub fn fit(sources: Vec<Source>, budget: i64) -> Assembled {
+    let mut out = Assembled::default();
+    let mut left = budget;
+    let mut full = false;
+    for (i, mut source) in sources.into_iter().enumerate() {
+        if full {
+            out.dropped.push(describe(&source));
+            continue;
+        }
+        let header = if i == 0 && source.own_doc() {
+            String::new()
+        } else {
+            format!("--- {}\n", source.label())
+        };
+        // The blank line before a source counts as a token
+        let overhead = estimate_tokens(&header) + i64::from(!out.text.is_empty());
+        let cost = overhead + estimate_tokens(&source.text);
+        let mut truncated = false;
+        if cost > left {
+            full = true;
+            let room = left - overhead;
+            if room < MIN_PARTIAL_TOKENS {
+                out.dropped.push(describe(&source));
+                continue;
+            }
+            // Keep whole lines up to the room left
+            let mut kept = String::new();
+            let mut n = 0;
+            for line in source.text.lines() {
+                if estimate_tokens(&kept) + estimate_tokens(line) + 1 > room {
+                    break;
+                }
+                if n > 0 {
+                    kept.push('\n');
+                }
+                kept.push_str(line);
+                n += 1;
+            }
+            if n == 0 {
+                out.dropped.push(describe(&source));
+                continue;
+            }
+            source.lines = source.lines.map(|(start, _)| (start, start + n - 1));
+            source.text = kept;
+            truncated = true;
+        }
+        let tokens = overhead + estimate_tokens(&source.text);
+        left -= tokens;
+        if !out.text.is_empty() {
+            out.text.push_str("\n\n");
+        }
+        out.text.push_str(&header);
+        out.text.push_str(&source.text);
+        let mut v = describe(&source);
+        v["tokens"] = serde_json::json!(tokens);
+        if truncated {
+            v["truncated"] = serde_json::Value::Bool(true);
+        }
+        out.sources.push(v);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sections_embeds_and_budget() {
+        let body = "intro\n# A\na1\n```\n# not a heading\n```\n## A.1\na11\n# B\nb1\n";
+        assert_eq!(section_at(body, 1), (1, 1));
+        assert_eq!(section_at(body, 3), (2, 8));
+        assert_eq!(section_at(body, 8), (7, 8));
+        assert_eq!(section_at(body, 10), (9, 10));
+        assert_eq!(section_named(body, "a.1"), Some((7, 8)));
+        assert_eq!(slice(body, (7, 8)), "## A.1\na11");
+
+        let md = "See ![[one]] and ![[two#Part|alias]] and [[three]]\n```\n![[code]]\n```\n![[one]]";
+        assert_eq!(embeds(md), vec![("one".to_string(), None), ("two".to_string(), Some("Part".to_string()))]);
+
+        let source = |kind, text: &str| Source {
+            kind,
+            doc_id: "d".into(),
+            slug: "s".into(),
+            title: "T".into(),
+            lines: Some((1, text.lines().count())),
+            text: text.into(),
+        };
+        let long = (0..100).map(|i| format!("line {:03} of the linked doc", i)).collect::<Vec<_>>().join("\n");
+        let out = fit(
+            vec![source("section", "# T\nbody"), source("outbound", &long), source("search", "never")],
+            120,
+        );
+        assert!(out.text.starts_with("# T\nbody\n\n--- Linked: T (s)\nline 000"));
+        assert_eq!(out.sources.len(), 2);
+        assert_eq!(out.sources[1]["truncated"], true);
+        assert!(out.sources[1]["lines"][1].as_u64().unwrap() < 100);
+        assert_eq!(out.dropped, vec![serde_json::json!({"kind": "search", "doc_id": "d", "slug": "s", "lines": [1, 1]})]);
+        assert!(estimate_tokens(&out.text) <= 120);
+    }
+}
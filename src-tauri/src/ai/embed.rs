@@ -0,0 +1,601 @@
+//! Doc chunk embeddings for semantic search.
+//!
+//! Backends are selected by the `embedding` app setting (JSON):
+//! - `{"backend":"hash","dim":256}` — deterministic local feature hashing (default; offline/tests)
+//! - `{"backend":"openai","base_url":"…/v1","model":"…","provider":"openrouter"}` — OpenAI-compatible `/embeddings`
+//! - `{"backend":"plugin","plugin":"name"}` — core plugin answering `embed {texts}` with `{vectors}`
+//! - `{"backend":"off"}` — disable indexing
+//!
+//! Vectors are stored per chunk in `doc_embedding` (little-endian f32) together with the model id,
+//! so switching backends never mixes incompatible vectors.
+
+use crate::db::Db;
+use crate::secrets;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+
+const DEFAULT_HASH_DIM: usize = 256;
+const CHUNK_TARGET_CHARS: usize = 800;
+const CHUNK_MAX_CHARS: usize = 2000;
+const PREVIEW_CHARS: usize = 200;
+
+pub trait Embedder {
+    /// Stable identity stored alongside vectors, e.g. `hash-256` or `openai:text-embedding-3-small`.
+    fn model_id(&self) -> String;
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String>;
+}
+
+#[derive(Deserialize, Default)]
+struct EmbeddingConfig {
+    backend: Option<String>,
+    dim: Option<usize>,
+    base_url: Option<String>,
+    model: Option<String>,
+    provider: Option<String>,
+    plugin: Option<String>,
+}
+
+pub struct HashEmbedder {
+    pub dim: usize,
+}
+
+impl Embedder for HashEmbedder {
+    fn model_id(&self) -> String {
+        format!("hash-{}", self.dim)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        Ok(texts.iter().map(|t| hash_embed(t, self.dim)).collect())
+    }
+}
+
+fn hash_embed(text: &str, dim: usize) -> Vec<f32> {
+    let mut v = vec![0f32; dim.max(1)];
+    let terms = crate::fts::query_terms(text);
+    let mut add = |feature: &str, weight: f32| {
+        let h = blake3::hash(feature.as_bytes());
+        let b = h.as_bytes();
+        let idx = u64::from_le_bytes(b[0..8].try_into().unwrap()) as usize % v.len();
+        let sign = if b[8] & 1 == 0 { 1.0 } else { -1.0 };
+        v[idx] += sign * weight;
+    };
+    for (i, t) in terms.iter().enumerate() {
+        add(t, 1.0);
+        if let Some(next) = terms.get(i + 1) {
+            add(&format!("{t} {next}"), 0.5);
+        }
+    }
+    normalize(&mut v);
+    v
+}
+
+pub struct OpenAiEmbedder {
+    pub base_url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OaEmbeddingItem {
+    embedding: Vec<f32>,
+    index: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct OaEmbeddingResponse {
+    data: Vec<OaEmbeddingItem>,
+}
+
+impl Embedder for OpenAiEmbedder {
+    fn model_id(&self) -> String {
+        format!("openai:{}", self.model)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let url = format!("{}/embeddings", self.base_url.trim_end_matches('/'));
+        let body = serde_json::json!({"model": self.model, "input": texts});
+        let key = self.api_key.clone();
+        // Run the blocking client on its own thread so async callers (IPC/RPC) are safe
+        let res: OaEmbeddingResponse = std::thread::spawn(move || {
+            let client = reqwest::blocking::Client::builder()
+                .user_agent("agent-editor/0.0.0 (+https://example.local)")
+                .build()
+                .map_err(|e| format!("http_client_error: {}", e))?;
+            let mut req = client.post(url).json(&body);
+            if let Some(k) = key {
+                req = req.bearer_auth(k);
+            }
+            req.send()
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| format!("http_error: {}", e))?
+                .json::<OaEmbeddingResponse>()
+                .map_err(|e| format!("decode_error: {}", e))
+        })
+        .join()
+        .map_err(|_| "embed_thread_panicked".to_string())??;
+        let mut data = res.data;
+        data.sort_by_key(|d| d.index.unwrap_or(0));
+        Ok(data.into_iter().map(|d| d.embedding).collect())
+    }
+}
+
+pub struct PluginEmbedder {
+    pub plugin: String,
+}
+
+impl Embedder for PluginEmbedder {
+    fn model_id(&self) -> String {
+        format!("plugin:{}", self.plugin)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let res = crate::plugins::call_core_plugin(
+            &self.plugin,
+            "embed",
+            serde_json::json!({ "texts": texts }),
+        )?;
+        let vectors = res
+            .get("result")
+            .and_then(|r| r.get("vectors"))
+            .cloned()
+            .ok_or_else(|| "invalid_plugin_response".to_string())?;
+        serde_json::from_value(vectors).map_err(|e| e.to_string())
+    }
+}
+
+/// Build the configured embedder; `None` when indexing is turned off.
+pub fn embedder_from_settings(db: &Db) -> Result<Option<Box<dyn Embedder>>, String> {
+    let raw: Option<String> = {
+        let conn = db.0.lock();
+        conn.query_row(
+            "SELECT value FROM app_setting WHERE key='embedding'",
+            [],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+    };
+    let cfg: EmbeddingConfig = raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    match cfg.backend.as_deref().unwrap_or("hash") {
+        "off" => Ok(None),
+        "hash" => Ok(Some(Box::new(HashEmbedder {
+            dim: cfg.dim.unwrap_or(DEFAULT_HASH_DIM),
+        }))),
+        "openai" => {
+            let api_key = match cfg.provider.as_deref() {
+                Some(p) if !p.is_empty() => Some(secrets::provider_key_get(db, p)?),
+                _ => None,
+            };
+            Ok(Some(Box::new(OpenAiEmbedder {
+                base_url: cfg
+                    .base_url
+                    .unwrap_or_else(|| "https://api.openai.com/v1".into()),
+                model: cfg
+                    .model
+                    .unwrap_or_else(|| "text-embedding-3-small".into()),
+                api_key,
+            })))
+        }
+        "plugin" => {
+            let plugin = cfg.plugin.ok_or_else(|| "embedding_plugin_missing".to_string())?;
+            Ok(Some(Box::new(PluginEmbedder { plugin })))
+        }
+        other => Err(format!("unknown_embedding_backend: {}", other)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub line_start: usize,
+    pub line_end: usize,
+    pub text: String,
+}
+
+/// Split a markdown body into chunks on headings and paragraph breaks (1-based inclusive line ranges).
+pub fn chunk_body(body: &str) -> Vec<Chunk> {
+    let mut out = Vec::new();
+    let mut cur: Vec<&str> = Vec::new();
+    let mut cur_len = 0usize;
+    let mut start = 1usize;
+    let mut flush = |cur: &mut Vec<&str>, cur_len: &mut usize, start: usize, end: usize| {
+        let text = cur.join("\n");
+        if !text.trim().is_empty() {
+            out.push(Chunk {
+                line_start: start,
+                line_end: end,
+                text,
+            });
+        }
+        cur.clear();
+        *cur_len = 0;
+    };
+    for (i, line) in body.lines().enumerate() {
+        let lineno = i + 1;
+        let is_heading = line.starts_with('#');
+        if !cur.is_empty()
+            && ((is_heading && cur_len > 0)
+                || (line.trim().is_empty() && cur_len >= CHUNK_TARGET_CHARS)
+                || cur_len + line.len() > CHUNK_MAX_CHARS)
+        {
+            flush(&mut cur, &mut cur_len, start, lineno - 1);
+        }
+        if cur.is_empty() {
+            start = lineno;
+        }
+        cur.push(line);
+        cur_len += line.len() + 1;
+    }
+    let end = start + cur.len().saturating_sub(1);
+    flush(&mut cur, &mut cur_len, start, end);
+    out
+}
+
+pub fn normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for x in v.iter_mut() {
+            *x /= norm;
+        }
+    }
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let na = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let nb = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na * nb)
+    }
+}
+
+fn vector_to_bytes(v: &[f32]) -> Vec<u8> {
+    v.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn vector_from_bytes(b: &[u8]) -> Vec<f32> {
+    b.chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Re-embed a doc's chunks; unchanged chunks (same text hash and model) reuse their stored vectors.
+pub fn refresh_doc_embeddings(db: &Db, doc_id: &str, body: &str) -> Result<usize, String> {
+    let Some(embedder) = embedder_from_settings(db)? else {
+        return Ok(0);
+    };
+    let model = embedder.model_id();
+    let chunks = chunk_body(body);
+    let hashes: Vec<String> = chunks
+        .iter()
+        .map(|c| blake3::hash(c.text.as_bytes()).to_hex().to_string())
+        .collect();
+    let mut existing: HashMap<String, Vec<u8>> = {
+        let conn = db.0.lock();
+        let mut stmt = conn
+            .prepare("SELECT content_hash, vector FROM doc_embedding WHERE doc_id=?1 AND model=?2")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![doc_id, model], |r| Ok((r.get(0)?, r.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        let mut map = HashMap::new();
+        for r in rows {
+            let (h, v): (String, Vec<u8>) = r.map_err(|e| e.to_string())?;
+            map.insert(h, v);
+        }
+        map
+    };
+    let missing: Vec<usize> = (0..chunks.len())
+        .filter(|i| !existing.contains_key(&hashes[*i]))
+        .collect();
+    if !missing.is_empty() {
+        let texts: Vec<String> = missing.iter().map(|i| chunks[*i].text.clone()).collect();
+        let vectors = embedder.embed(&texts)?;
+        if vectors.len() != texts.len() {
+            return Err("embedding_count_mismatch".into());
+        }
+        for (i, v) in missing.iter().zip(vectors) {
+            existing.insert(hashes[*i].clone(), vector_to_bytes(&v));
+        }
+    }
+    let mut conn = db.0.lock();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM doc_embedding WHERE doc_id=?1", params![doc_id])
+        .map_err(|e| e.to_string())?;
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let vector = &existing[&hashes[idx]];
+        let preview: String = chunk.text.chars().take(PREVIEW_CHARS).collect();
+        tx.execute(
+            "INSERT INTO doc_embedding(doc_id,chunk_index,line_start,line_end,content_hash,model,dim,preview,vector) VALUES(?,?,?,?,?,?,?,?,?)",
+            params![
+                doc_id,
+                idx as i64,
+                chunk.line_start as i64,
+                chunk.line_end as i64,
+                hashes[idx],
+                model,
+                (vector.len() / 4) as i64,
+                preview,
+                vector
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(chunks.len())
+}
+
+#[derive(Serialize, Clone)]
+pub struct SemanticHit {
+    pub id: String,
+    pub slug: String,
+    pub title: String,
+    pub score: f32,
+    pub line_start: i64,
+    pub line_end: i64,
+    pub preview: String,
+}
+
+/// Rank docs by their best-matching chunk against the query embedding.
+pub fn semantic_search(
+    db: &Db,
+    repo_id: Option<&str>,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SemanticHit>, String> {
+    let embedder = embedder_from_settings(db)?.ok_or_else(|| "embeddings_disabled".to_string())?;
+    let model = embedder.model_id();
+    let qv = embedder
+        .embed(&[query.to_string()])?
+        .pop()
+        .ok_or_else(|| "empty_embedding".to_string())?;
+    let conn = db.0.lock();
+    let mut stmt = conn
+        .prepare(
+            "SELECT d.id, d.slug, d.title, e.line_start, e.line_end, COALESCE(e.preview,''), e.vector \
+             FROM doc_embedding e JOIN doc d ON d.id=e.doc_id \
+             WHERE e.model=?1 AND d.is_deleted=0 AND (?2 IS NULL OR d.repo_id=?2)",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![model, repo_id], |r| {
+            let bytes: Vec<u8> = r.get(6)?;
+            Ok(SemanticHit {
+                id: r.get(0)?,
+                slug: r.get(1)?,
+                title: r.get(2)?,
+                line_start: r.get(3)?,
+                line_end: r.get(4)?,
+                preview: r.get(5)?,
+                score: cosine(&qv, &vector_from_bytes(&bytes)),
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    let mut best: HashMap<String, SemanticHit> = HashMap::new();
+    for r in rows {
+        let hit = r.map_err(|e| e.to_string())?;
+        match best.get(&hit.id) {
+            Some(prev) if prev.score >= hit.score => {}
+            _ => {
+                best.insert(hit.id.clone(), hit);
+            }
+        }
+    }
+    let mut out: Vec<SemanticHit> = best.into_values().filter(|h| h.score > 0.0).collect();
+    out.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    out.truncate(limit);
+    Ok(out)
+}
+
+/// Embedding refreshes of saved docs, run off the save path once [`start_refresh_worker`] is
+/// called; until then (tests, one-off tools) they run inline. Lives on the [`Db`].
+#[derive(Default)]
+pub struct RefreshQueue {
+    tx: parking_lot::Mutex<Option<Sender<(String, String)>>>,
+    status: parking_lot::Mutex<RefreshStatus>,
+}
+
+/// `pending`: queued docs not refreshed yet; `failed`: docs whose last refresh failed, with the
+/// error (cleared by the next successful refresh or `embeddings_reindex`).
+#[derive(Serialize, Default, Clone)]
+pub struct RefreshStatus {
+    pub pending: usize,
+    pub failed: BTreeMap<String, String>,
+}
+
+impl RefreshQueue {
+    fn record(&self, doc_id: &str, error: Option<String>) {
+        let mut status = self.status.lock();
+        match error {
+            None => {
+                status.failed.remove(doc_id);
+            }
+            Some(e) => {
+                eprintln!("[embed] refresh failed for {}: {}", doc_id, e);
+                status.failed.insert(doc_id.to_string(), e);
+            }
+        }
+    }
+}
+
+/// Start the thread that runs queued refreshes. It holds the database only while working and
+/// exits once the database is dropped.
+pub fn start_refresh_worker(db: &Arc<Db>) {
+    let (tx, rx) = channel::<(String, String)>();
+    let weak = Arc::downgrade(db);
+    std::thread::spawn(move || {
+        while let Ok(first) = rx.recv() {
+            // Saves in quick succession: only the latest body of each doc matters
+            let mut latest = HashMap::new();
+            let mut taken = 0;
+            for (doc_id, body) in std::iter::once(first).chain(rx.try_iter()) {
+                latest.insert(doc_id, body);
+                taken += 1;
+            }
+            let Some(db) = weak.upgrade() else { break };
+            for (doc_id, body) in latest {
+                let res = refresh_doc_embeddings(&db, &doc_id, &body);
+                db.1.record(&doc_id, res.err());
+            }
+            let mut status = db.1.status.lock();
+            status.pending = status.pending.saturating_sub(taken);
+        }
+    });
+    *db.1.tx.lock() = Some(tx);
+}
+
+/// Queue a doc's refresh after a write, or run it now without a worker; never fails the write.
+pub fn queue_refresh(db: &Db, doc_id: &str, body: &str) {
+    let job = (doc_id.to_string(), body.to_string());
+    let job = match &*db.1.tx.lock() {
+        Some(tx) => {
+            // Counted before sending so the worker never takes it off first
+            db.1.status.lock().pending += 1;
+            match tx.send(job) {
+                Ok(()) => return,
+                Err(e) => {
+                    db.1.status.lock().pending -= 1;
+                    e.0
+                }
+            }
+        }
+        None => job,
+    };
+    db.1.record(&job.0, refresh_doc_embeddings(db, &job.0, &job.1).err());
+}
+
+pub fn refresh_status(db: &Db) -> RefreshStatus {
+    db.1.status.lock().clone()
+}
+
+/// Re-embed every doc (optionally one repo) from its current version; used after backend changes or imports.
+pub fn reindex_all(db: &Db, repo_id: Option<&str>) -> Result<serde_json::Value, String> {
+    let docs: Vec<(String, String)> = {
+        let conn = db.0.lock();
+        let mut stmt = conn
+            .prepare(
+                "SELECT d.id, b.content, COALESCE(b.encoding,'utf8') FROM doc d \
+                 JOIN doc_version v ON v.id=d.current_version_id JOIN doc_blob b ON b.id=v.blob_id \
+                 WHERE d.is_deleted=0 AND (?1 IS NULL OR d.repo_id=?1)",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![repo_id], |r| Ok((r.get(0)?, r.get(1)?, r.get::<_, String>(2)?)))
+            .map_err(|e| e.to_string())?;
+        let mut out = Vec::new();
+        for r in rows {
+            let (id, stored, encoding) = r.map_err(|e| e.to_string())?;
+            out.push((id, crate::blob::decode_text(stored, &encoding)?))
+        }
+        out
+    };
+    let mut chunks = 0usize;
+    for (doc_id, body) in &docs {
+        chunks += refresh_doc_embeddings(db, doc_id, body)?;
+        db.1.record(doc_id, None);
+    }
+    Ok(serde_json::json!({"docs": docs.len(), "chunks": chunks}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    fn seed_doc(db: &Db, id: &str, slug: &str, body: &str) {
+        let conn = db.0.lock();
+        conn.execute(
+            "INSERT INTO doc(id,repo_id,folder_id,slug,title) VALUES(?1,'r','f',?2,?2)",
+            params![id, slug],
+        )
+        .unwrap();
+        drop(conn);
+        refresh_doc_embeddings(db, id, body).unwrap();
+    }
+
+    #[test]
+    fn test_hash_embedder_is_deterministic_and_normalized() {
+        let e = HashEmbedder { dim: 64 };
+        let a = e.embed(&["Kubernetes cluster upgrade".into()]).unwrap();
+        let b = e.embed(&["Kubernetes cluster upgrade".into()]).unwrap();
+        assert_eq!(a, b);
+        let norm: f32 = a[0].iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_chunk_body_splits_on_headings() {
+        let body = "# One\nalpha\nbeta\n# Two\ngamma";
+        let chunks = chunk_body(body);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].line_start, chunks[0].line_end), (1, 3));
+        assert_eq!((chunks[1].line_start, chunks[1].line_end), (4, 5));
+    }
+
+    #[test]
+    fn test_semantic_search_ranks_related_doc_first() {
+        let db = db_with_docs("embed-test", &[]);
+        seed_doc(&db, "d1", "k8s", "# Cluster\nUpgrading the kubernetes cluster nodes");
+        seed_doc(&db, "d2", "bread", "# Baking\nSourdough bread needs a starter");
+        let hits = semantic_search(&db, None, "kubernetes cluster", 5).unwrap();
+        assert_eq!(hits[0].id, "d1");
+        let n: i64 = db.0.lock().query_row("SELECT COUNT(*) FROM doc_embedding", [], |r| r.get(0)).unwrap();
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn test_openai_embedder_against_mock_server() {
+        let body = r#"{"data":[{"index":1,"embedding":[0.0,1.0]},{"index":0,"embedding":[1.0,0.0]}]}"#;
+        let (base, rx) = crate::ai::test_http::serve(vec![(200, "application/json", body.into())]);
+        let e = OpenAiEmbedder {
+            base_url: format!("{}/v1", base),
+            model: "m".into(),
+            api_key: None,
+        };
+        let v = e.embed(&["a".into(), "b".into()]).unwrap();
+        assert_eq!(v, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+        let req = rx.recv().unwrap();
+        assert_eq!(req.request_line, "POST /v1/embeddings HTTP/1.1");
+        assert!(req.body.contains("\"model\":\"m\""));
+    }
+
+    #[test]
+    fn test_refresh_worker_runs_queued_refreshes() {
+        let db = Arc::new(db_with_docs("embed-worker", &[("d", "n")]));
+        start_refresh_worker(&db);
+        let settle = || {
+            for _ in 0..200 {
+                if refresh_status(&db).pending == 0 {
+                    return refresh_status(&db);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("refresh never ran");
+        };
+        db.0.lock()
+            .execute("INSERT INTO app_setting(key,value) VALUES('embedding','{\"backend\":\"nope\"}')", [])
+            .unwrap();
+        queue_refresh(&db, "d", "# Notes\nfirst");
+        assert_eq!(settle().failed["d"], "unknown_embedding_backend: nope");
+
+        db.0.lock().execute("DELETE FROM app_setting WHERE key='embedding'", []).unwrap();
+        queue_refresh(&db, "d", "# Notes\nfirst");
+        queue_refresh(&db, "d", "# Notes\nsecond");
+        assert!(settle().failed.is_empty());
+        let preview: String = db
+            .0
+            .lock()
+            .query_row("SELECT preview FROM doc_embedding WHERE doc_id='d'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(preview, "# Notes\nsecond");
+    }
+}
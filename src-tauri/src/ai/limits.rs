@@ -0,0 +1,183 @@
+//! Request rate and spend limits for AI runs.
+//!
+//! Limits are set per provider (app setting `ai_limits`, a map of provider name to limits)
+//! and per repo (`repo.settings.ai_limits`), and measured against `ai_trace` and
+//! `ai_attempt`: requests in the last 60 seconds (answered and failed ones, retries
+//! included), tokens since the start of the UTC day and cost since the start of the UTC
+//! month (failed requests report none). Times are SQLite `datetime` strings (UTC).
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct AiLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_day: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usd_per_month: Option<f64>,
+}
+
+impl AiLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == AiLimits::default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope<'a> {
+    Provider(&'a str),
+    Repo(&'a str),
+}
+
+impl Scope<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Scope::Provider(_) => "provider",
+            Scope::Repo(_) => "repo",
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Scope::Provider(n) | Scope::Repo(n) => n,
+        }
+    }
+}
+
+/// Provider limits by provider name.
+pub fn provider_limits(conn: &Connection) -> BTreeMap<String, AiLimits> {
+    conn.query_row("SELECT value FROM app_setting WHERE key='ai_limits'", [], |r| r.get::<_, String>(0))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Limits configured for one scope (all unset when none are).
+pub fn limits_for(conn: &Connection, scope: Scope) -> AiLimits {
+    match scope {
+        Scope::Provider(name) => provider_limits(conn).remove(name).unwrap_or_default(),
+        Scope::Repo(id) => conn
+            .query_row(
+                "SELECT json_extract(settings,'$.ai_limits') FROM repo WHERE id=?1 OR name=?1",
+                params![id],
+                |r| r.get::<_, Option<String>>(0),
+            )
+            .ok()
+            .flatten()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default(),
+    }
+}
+
+/// What a scope has used in each window, with the time each window frees up.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Used {
+    pub requests_last_minute: i64,
+    /// When the oldest request of the last minute leaves the window; None without requests
+    pub requests_reset_at: Option<String>,
+    pub tokens_today: i64,
+    pub tokens_reset_at: String,
+    pub usd_this_month: f64,
+    pub usd_reset_at: String,
+}
+
+pub fn used(conn: &Connection, scope: Scope) -> Result<Used, String> {
+    let col = match scope {
+        Scope::Provider(_) => "provider",
+        Scope::Repo(_) => "repo_id",
+    };
+    let recent = format!(
+        "SELECT created_at FROM ai_trace WHERE {col}=?1 AND created_at > datetime('now','-60 seconds') \
+         UNION ALL SELECT created_at FROM ai_attempt WHERE {col}=?1 AND created_at > datetime('now','-60 seconds')"
+    );
+    let sql = format!(
+        "SELECT \
+           (SELECT COUNT(*) FROM ({recent})), \
+           (SELECT datetime(MIN(created_at),'+60 seconds') FROM ({recent})), \
+           (SELECT COALESCE(SUM(COALESCE(input_tokens,0)+COALESCE(output_tokens,0)),0) FROM ai_trace WHERE {col}=?1 AND created_at >= date('now')), \
+           datetime('now','start of day','+1 day'), \
+           (SELECT COALESCE(SUM(cost_usd),0.0) FROM ai_trace WHERE {col}=?1 AND created_at >= date('now','start of month')), \
+           datetime('now','start of month','+1 month')"
+    );
+    conn.query_row(&sql, params![scope.name()], |r| {
+        Ok(Used {
+            requests_last_minute: r.get(0)?,
+            requests_reset_at: r.get(1)?,
+            tokens_today: r.get(2)?,
+            tokens_reset_at: r.get(3)?,
+            usd_this_month: r.get(4)?,
+            usd_reset_at: r.get(5)?,
+        })
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// Each configured limit of a scope: `{ max, used, resets_at }` keyed by limit name.
+pub fn status(conn: &Connection, scope: Scope) -> Result<serde_json::Value, String> {
+    let limits = limits_for(conn, scope);
+    let used = used(conn, scope)?;
+    let mut out = serde_json::Map::new();
+    if let Some(max) = limits.requests_per_minute {
+        out.insert(
+            "requests_per_minute".into(),
+            serde_json::json!({"max": max, "used": used.requests_last_minute, "resets_at": used.requests_reset_at}),
+        );
+    }
+    if let Some(max) = limits.tokens_per_day {
+        out.insert(
+            "tokens_per_day".into(),
+            serde_json::json!({"max": max, "used": used.tokens_today, "resets_at": used.tokens_reset_at}),
+        );
+    }
+    if let Some(max) = limits.usd_per_month {
+        out.insert(
+            "usd_per_month".into(),
+            serde_json::json!({"max": max, "used": used.usd_this_month, "resets_at": used.usd_reset_at}),
+        );
+    }
+    Ok(serde_json::json!({"scope": scope.kind(), "name": scope.name(), "limits": out}))
+}
+
+fn limit_error(kind: &str, scope: Scope, limit: &str, resets_at: &str) -> String {
+    format!(
+        "{}: {}={} limit={} resets_at={}",
+        kind,
+        scope.kind(),
+        scope.name(),
+        limit,
+        resets_at
+    )
+}
+
+/// Refuse a run once any limit of the provider or the repo is used up. Errors look like
+/// `rate_limited: provider=openrouter limit=requests_per_minute resets_at=2025-01-01 12:00:30`
+/// (`budget_exceeded` for the token and spend limits).
+pub fn check(conn: &Connection, provider: &str, repo_id: &str) -> Result<(), String> {
+    for scope in [Scope::Provider(provider), Scope::Repo(repo_id)] {
+        let limits = limits_for(conn, scope);
+        if limits.is_empty() {
+            continue;
+        }
+        let used = used(conn, scope)?;
+        if let Some(max) = limits.requests_per_minute {
+            if used.requests_last_minute >= max {
+                let at = used.requests_reset_at.as_deref().unwrap_or_default();
+                return Err(limit_error("rate_limited", scope, "requests_per_minute", at));
+            }
+        }
+        if let Some(max) = limits.tokens_per_day {
+            if used.tokens_today >= max {
+                return Err(limit_error("budget_exceeded", scope, "tokens_per_day", &used.tokens_reset_at));
+            }
+        }
+        if let Some(max) = limits.usd_per_month {
+            if used.usd_this_month >= max {
+                return Err(limit_error("budget_exceeded", scope, "usd_per_month", &used.usd_reset_at));
+            }
+        }
+    }
+    Ok(())
+}
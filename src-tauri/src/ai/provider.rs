@@ -0,0 +1,888 @@
+//! Chat completion backends for `ai_run`.
+//!
+//! Each row of the `provider` table is turned into an [`AiProvider`] from its `config` JSON:
+//! - `api`: `openai` (OpenAI-compatible `/chat/completions`), `anthropic` (Messages API),
+//!   `ollama` (`/api/chat`) or `echo` (offline, returns the prompt)
+//! - `base_url`, `model`: endpoint and model; `headers`: extra request headers
+//! - `max_tokens`: output limit (Anthropic requires one; default 1024)
+//! - `timeout_secs` (default 60; for streams, the longest wait for the reply and between
+//!   chunks), `retries` (default 2) and
+//!   `retry_backoff_ms` (default 500, doubled per attempt): 429 and 5xx replies, connection
+//!   failures and timeouts are retried, honouring `Retry-After`. Every request sent is
+//!   logged for [`AiProvider::take_attempts`], so retries count against the AI limits
+//!
+//! Seeded providers get defaults by name (`openrouter`/`codex` → openai, `claude-code` →
+//! anthropic, `local` → ollama on localhost); anything else must set `api` and `base_url`.
+//! Remote providers authenticate with the key from the secrets store, unless `headers`
+//! already carries one (`Authorization`, `x-api-key`).
+//!
+//! `chat_stream` reads SSE (OpenAI, Anthropic) or newline-delimited JSON (Ollama) and hands
+//! out text deltas as they arrive; a stream stops as soon as its cancel flag is set, even
+//! while waiting on a stalled connection.
+
+use crate::db::Db;
+use crate::secrets;
+use parking_lot::Mutex;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 500;
+/// Longest wait between attempts, whatever `Retry-After` asks for
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(30);
+/// How often a stream waiting for its next line checks the cancel flag
+const STREAM_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    /// `user` or `assistant`
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChatRequest {
+    pub system: Option<String>,
+    pub messages: Vec<ChatMessage>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChatResponse {
+    pub text: String,
+    /// Model reported by the backend, else the configured one
+    pub model: String,
+    /// Token usage as reported by the backend, when it does
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+}
+
+pub trait AiProvider {
+    /// Provider name (the `provider` table key)
+    fn name(&self) -> &str;
+    /// Configured model id
+    fn model(&self) -> &str;
+    fn chat(&self, req: &ChatRequest) -> Result<ChatResponse, String>;
+    /// Like `chat`, passing text deltas to `on_delta` as they arrive. Returning false from
+    /// `on_delta` drops that delta and aborts the request, as does setting `cancel`; the text
+    /// accepted so far is returned.
+    fn chat_stream(
+        &self,
+        req: &ChatRequest,
+        _cancel: &AtomicBool,
+        on_delta: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<ChatResponse, String> {
+        let res = self.chat(req)?;
+        on_delta(&res.text);
+        Ok(res)
+    }
+    /// Outcome of each request sent since the last take, oldest first: the error of each
+    /// failed one (a retry is another request), `Ok` once a reply came back.
+    fn take_attempts(&self) -> Vec<Result<(), String>> {
+        Vec::new()
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct ProviderConfig {
+    pub api: Option<String>,
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub max_tokens: Option<u32>,
+    pub timeout_secs: Option<u64>,
+    pub retries: Option<u32>,
+    pub retry_backoff_ms: Option<u64>,
+}
+
+/// Timeout and retries of one backend.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            retries: DEFAULT_RETRIES,
+            backoff: Duration::from_millis(DEFAULT_RETRY_BACKOFF_MS),
+        }
+    }
+}
+
+/// Connection details shared by the HTTP backends.
+pub struct Endpoint {
+    pub name: String,
+    pub base_url: String,
+    pub model: String,
+    pub headers: Vec<(String, String)>,
+    pub max_tokens: u32,
+    pub retry: RetryPolicy,
+    /// Requests sent, for `take_attempts`
+    pub attempts: Arc<Mutex<Vec<Result<(), String>>>>,
+}
+
+impl Endpoint {
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url.trim_end_matches('/'), path)
+    }
+
+    fn take_attempts(&self) -> Vec<Result<(), String>> {
+        std::mem::take(&mut *self.attempts.lock())
+    }
+}
+
+/// `(api, base_url, model)` used when the config leaves them out.
+fn defaults(name: &str) -> (Option<&'static str>, Option<&'static str>, Option<&'static str>) {
+    match name {
+        "openrouter" => (Some("openai"), Some("https://openrouter.ai/api/v1"), Some("openrouter/auto")),
+        "codex" => (Some("openai"), Some("https://api.openai.com/v1"), Some("gpt-4o-mini")),
+        "claude-code" => (Some("anthropic"), Some("https://api.anthropic.com"), Some("claude-3-5-haiku-latest")),
+        "local" => (Some("ollama"), Some("http://127.0.0.1:11434"), Some("llama3.2")),
+        _ => (None, None, None),
+    }
+}
+
+/// Send the request, retrying 429/5xx replies, connection failures and timeouts with
+/// exponential backoff (or the server's `Retry-After`). Returns the successful response.
+/// Each request sent is logged in `attempts`.
+fn send_with_retry(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    headers: &[(String, String)],
+    body: &serde_json::Value,
+    policy: RetryPolicy,
+    attempts: &Mutex<Vec<Result<(), String>>>,
+) -> Result<reqwest::blocking::Response, String> {
+    let mut attempt = 0;
+    loop {
+        let mut req = client.post(url).json(body);
+        for (k, v) in headers {
+            req = req.header(k, v);
+        }
+        let (err, retry_after) = match req.send() {
+            Ok(res) if res.status().is_success() => {
+                attempts.lock().push(Ok(()));
+                return Ok(res);
+            }
+            Ok(res) => {
+                let status = res.status();
+                let retry_after = res
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                let text = res.text().unwrap_or_default();
+                let err = format!("http_error: {} {}", status.as_u16(), text.chars().take(200).collect::<String>());
+                if !(status.as_u16() == 429 || status.is_server_error()) {
+                    attempts.lock().push(Err(err.clone()));
+                    return Err(err);
+                }
+                (err, retry_after)
+            }
+            Err(e) if e.is_timeout() => (format!("timeout: {}", e), None),
+            Err(e) if e.is_connect() => (format!("http_error: {}", e), None),
+            Err(e) => {
+                let err = format!("http_error: {}", e);
+                attempts.lock().push(Err(err.clone()));
+                return Err(err);
+            }
+        };
+        attempts.lock().push(Err(err.clone()));
+        if attempt >= policy.retries {
+            return Err(err);
+        }
+        let wait = retry_after.unwrap_or(policy.backoff * 2u32.saturating_pow(attempt));
+        std::thread::sleep(wait.min(MAX_RETRY_WAIT));
+        attempt += 1;
+    }
+}
+
+/// POST JSON and decode the JSON reply. The blocking client runs on its own thread so
+/// async callers (IPC/RPC) are safe.
+fn post_json(
+    url: String,
+    headers: Vec<(String, String)>,
+    body: serde_json::Value,
+    policy: RetryPolicy,
+    attempts: Arc<Mutex<Vec<Result<(), String>>>>,
+) -> Result<serde_json::Value, String> {
+    std::thread::spawn(move || {
+        let client = reqwest::blocking::Client::builder()
+            .user_agent("agent-editor/0.0.0 (+https://example.local)")
+            .timeout(policy.timeout)
+            .build()
+            .map_err(|e| format!("http_client_error: {}", e))?;
+        let res = send_with_retry(&client, &url, &headers, &body, policy, &attempts)?;
+        res.json::<serde_json::Value>()
+            .map_err(|e| format!("decode_error: {}", e))
+    })
+    .join()
+    .map_err(|_| "provider_thread_panicked".to_string())?
+}
+
+/// POST JSON and pass each line of the streamed reply to `on_line` until it returns false
+/// or `cancel` is set (either drops the connection). Only getting the response is retried;
+/// a reply that sends nothing for `policy.timeout` fails with `timeout: …`.
+fn post_lines(
+    url: String,
+    headers: Vec<(String, String)>,
+    body: serde_json::Value,
+    policy: RetryPolicy,
+    attempts: &Mutex<Vec<Result<(), String>>>,
+    cancel: &AtomicBool,
+    on_line: &mut (dyn FnMut(&str) -> bool + Send),
+) -> Result<(), String> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(move || {
+                // The blocking client's timeout covers each read, not the whole answer
+                let client = reqwest::blocking::Client::builder()
+                    .user_agent("agent-editor/0.0.0 (+https://example.local)")
+                    .timeout(policy.timeout)
+                    .build()
+                    .map_err(|e| format!("http_client_error: {}", e))?;
+                let res = send_with_retry(&client, &url, &headers, &body, policy, attempts)?;
+                // Lines are read on their own thread so a stalled stream can still be cancelled;
+                // once the receiver is gone it ends with the next line or read timeout
+                let (tx, rx) = std::sync::mpsc::channel();
+                std::thread::spawn(move || {
+                    for line in BufReader::new(res).lines() {
+                        if tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                });
+                while !cancel.load(Ordering::SeqCst) {
+                    match rx.recv_timeout(STREAM_POLL) {
+                        Ok(line) => {
+                            let line = line.map_err(|e| {
+                                let timed_out = e
+                                    .get_ref()
+                                    .and_then(|r| r.downcast_ref::<reqwest::Error>())
+                                    .is_some_and(|r| r.is_timeout());
+                                if timed_out {
+                                    format!("timeout: {}", e)
+                                } else {
+                                    format!("http_error: {}", e)
+                                }
+                            })?;
+                            if !on_line(&line) {
+                                break;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                Ok(())
+            })
+            .join()
+            .map_err(|_| "provider_thread_panicked".to_string())?
+    })
+}
+
+/// Payload of an SSE `data:` line, parsed as JSON.
+fn sse_data(line: &str) -> Option<serde_json::Value> {
+    let data = line.strip_prefix("data:")?.trim_start();
+    serde_json::from_str(data).ok()
+}
+
+/// System prompt as a leading `system` message (OpenAI and Ollama style).
+fn messages_with_system(req: &ChatRequest) -> Vec<serde_json::Value> {
+    let mut messages = Vec::new();
+    if let Some(system) = &req.system {
+        messages.push(serde_json::json!({"role": "system", "content": system}));
+    }
+    messages.extend(req.messages.iter().map(|m| serde_json::json!(m)));
+    messages
+}
+
+/// Integer at a JSON pointer (usage counters).
+fn count(v: &serde_json::Value, pointer: &str) -> Option<i64> {
+    v.pointer(pointer).and_then(|n| n.as_i64())
+}
+
+fn reported_model(res: &serde_json::Value, configured: &str) -> String {
+    res.get("model")
+        .and_then(|m| m.as_str())
+        .unwrap_or(configured)
+        .to_string()
+}
+
+pub struct OpenAiProvider(pub Endpoint);
+
+impl OpenAiProvider {
+    fn body(&self, req: &ChatRequest, stream: bool) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": self.0.model,
+            "messages": messages_with_system(req),
+            "max_tokens": self.0.max_tokens,
+            "stream": stream,
+        });
+        if stream {
+            body["stream_options"] = serde_json::json!({"include_usage": true});
+        }
+        body
+    }
+}
+
+impl AiProvider for OpenAiProvider {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn model(&self) -> &str {
+        &self.0.model
+    }
+
+    fn take_attempts(&self) -> Vec<Result<(), String>> {
+        self.0.take_attempts()
+    }
+
+    fn chat(&self, req: &ChatRequest) -> Result<ChatResponse, String> {
+        let res = post_json(self.0.url("/chat/completions"), self.0.headers.clone(), self.body(req, false), self.0.retry, self.0.attempts.clone())?;
+        let text = res
+            .pointer("/choices/0/message/content")
+            .and_then(|c| c.as_str())
+            .ok_or("empty_response")?;
+        Ok(ChatResponse {
+            text: text.to_string(),
+            model: reported_model(&res, &self.0.model),
+            input_tokens: count(&res, "/usage/prompt_tokens"),
+            output_tokens: count(&res, "/usage/completion_tokens"),
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        req: &ChatRequest,
+        cancel: &AtomicBool,
+        on_delta: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<ChatResponse, String> {
+        let mut out = ChatResponse {
+            model: self.0.model.clone(),
+            ..Default::default()
+        };
+        post_lines(self.0.url("/chat/completions"), self.0.headers.clone(), self.body(req, true), self.0.retry, &self.0.attempts, cancel, &mut |line| {
+            if line.trim() == "data: [DONE]" {
+                return false;
+            }
+            let Some(event) = sse_data(line) else { return true };
+            out.model = reported_model(&event, &out.model);
+            // With include_usage the last chunk carries the totals
+            if event.get("usage").is_some_and(|u| u.is_object()) {
+                out.input_tokens = count(&event, "/usage/prompt_tokens");
+                out.output_tokens = count(&event, "/usage/completion_tokens");
+            }
+            match event.pointer("/choices/0/delta/content").and_then(|c| c.as_str()) {
+                Some(delta) if !delta.is_empty() => {
+                    let keep = on_delta(delta);
+                    if keep {
+                        out.text.push_str(delta);
+                    }
+                    keep
+                }
+                _ => true,
+            }
+        })?;
+        Ok(out)
+    }
+}
+
+pub struct AnthropicProvider(pub Endpoint);
+
+impl AnthropicProvider {
+    fn body(&self, req: &ChatRequest, stream: bool) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": self.0.model,
+            "max_tokens": self.0.max_tokens,
+            "messages": req.messages,
+            "stream": stream,
+        });
+        if let Some(system) = &req.system {
+            body["system"] = serde_json::Value::String(system.clone());
+        }
+        body
+    }
+}
+
+impl AiProvider for AnthropicProvider {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn model(&self) -> &str {
+        &self.0.model
+    }
+
+    fn take_attempts(&self) -> Vec<Result<(), String>> {
+        self.0.take_attempts()
+    }
+
+    fn chat(&self, req: &ChatRequest) -> Result<ChatResponse, String> {
+        let res = post_json(self.0.url("/v1/messages"), self.0.headers.clone(), self.body(req, false), self.0.retry, self.0.attempts.clone())?;
+        let text: String = res
+            .get("content")
+            .and_then(|c| c.as_array())
+            .ok_or("empty_response")?
+            .iter()
+            .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some("text"))
+            .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+            .collect();
+        Ok(ChatResponse {
+            text,
+            model: reported_model(&res, &self.0.model),
+            input_tokens: count(&res, "/usage/input_tokens"),
+            output_tokens: count(&res, "/usage/output_tokens"),
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        req: &ChatRequest,
+        cancel: &AtomicBool,
+        on_delta: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<ChatResponse, String> {
+        let mut out = ChatResponse {
+            model: self.0.model.clone(),
+            ..Default::default()
+        };
+        let mut error: Option<String> = None;
+        post_lines(self.0.url("/v1/messages"), self.0.headers.clone(), self.body(req, true), self.0.retry, &self.0.attempts, cancel, &mut |line| {
+            let Some(event) = sse_data(line) else { return true };
+            match event.get("type").and_then(|t| t.as_str()) {
+                Some("message_start") => {
+                    if let Some(message) = event.get("message") {
+                        out.model = reported_model(message, &out.model);
+                        out.input_tokens = count(message, "/usage/input_tokens");
+                    }
+                    true
+                }
+                Some("message_delta") => {
+                    out.output_tokens = count(&event, "/usage/output_tokens").or(out.output_tokens);
+                    true
+                }
+                Some("content_block_delta") => match event.pointer("/delta/text").and_then(|t| t.as_str()) {
+                    Some(delta) => {
+                        let keep = on_delta(delta);
+                        if keep {
+                            out.text.push_str(delta);
+                        }
+                        keep
+                    }
+                    None => true,
+                },
+                Some("message_stop") => false,
+                Some("error") => {
+                    error = Some(format!("http_error: {}", event["error"]["message"].as_str().unwrap_or("stream error")));
+                    false
+                }
+                _ => true,
+            }
+        })?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(out),
+        }
+    }
+}
+
+pub struct OllamaProvider(pub Endpoint);
+
+impl OllamaProvider {
+    fn body(&self, req: &ChatRequest, stream: bool) -> serde_json::Value {
+        serde_json::json!({
+            "model": self.0.model,
+            "messages": messages_with_system(req),
+            "stream": stream,
+            "options": {"num_predict": self.0.max_tokens},
+        })
+    }
+}
+
+impl AiProvider for OllamaProvider {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn model(&self) -> &str {
+        &self.0.model
+    }
+
+    fn take_attempts(&self) -> Vec<Result<(), String>> {
+        self.0.take_attempts()
+    }
+
+    fn chat(&self, req: &ChatRequest) -> Result<ChatResponse, String> {
+        let res = post_json(self.0.url("/api/chat"), self.0.headers.clone(), self.body(req, false), self.0.retry, self.0.attempts.clone())?;
+        let text = res
+            .pointer("/message/content")
+            .and_then(|c| c.as_str())
+            .ok_or("empty_response")?;
+        Ok(ChatResponse {
+            text: text.to_string(),
+            model: reported_model(&res, &self.0.model),
+            input_tokens: count(&res, "/prompt_eval_count"),
+            output_tokens: count(&res, "/eval_count"),
+        })
+    }
+
+    /// Ollama streams newline-delimited JSON rather than SSE.
+    fn chat_stream(
+        &self,
+        req: &ChatRequest,
+        cancel: &AtomicBool,
+        on_delta: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<ChatResponse, String> {
+        let mut out = ChatResponse {
+            model: self.0.model.clone(),
+            ..Default::default()
+        };
+        post_lines(self.0.url("/api/chat"), self.0.headers.clone(), self.body(req, true), self.0.retry, &self.0.attempts, cancel, &mut |line| {
+            let Ok(chunk) = serde_json::from_str::<serde_json::Value>(line) else { return true };
+            out.model = reported_model(&chunk, &out.model);
+            // Counters come with the final (`done`) chunk
+            out.input_tokens = count(&chunk, "/prompt_eval_count").or(out.input_tokens);
+            out.output_tokens = count(&chunk, "/eval_count").or(out.output_tokens);
+            let mut go_on = true;
+            if let Some(delta) = chunk.pointer("/message/content").and_then(|c| c.as_str()) {
+                if !delta.is_empty() {
+                    go_on = on_delta(delta);
+                    if go_on {
+                        out.text.push_str(delta);
+                    }
+                }
+            }
+            go_on && chunk.get("done").and_then(|d| d.as_bool()) != Some(true)
+        })?;
+        Ok(out)
+    }
+}
+
+/// Offline backend (`api: "echo"`): answers with the last user message.
+pub struct EchoProvider {
+    pub name: String,
+}
+
+impl AiProvider for EchoProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> &str {
+        "echo"
+    }
+
+    fn chat(&self, req: &ChatRequest) -> Result<ChatResponse, String> {
+        let last = req.messages.iter().rev().find(|m| m.role == "user");
+        Ok(ChatResponse {
+            text: last.map(|m| m.content.clone()).unwrap_or_default(),
+            model: "echo".into(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Model a provider is configured with: `config.model`, else the default for its name.
+pub fn configured_model(conn: &rusqlite::Connection, name: &str) -> Option<String> {
+    conn.query_row(
+        "SELECT json_extract(config,'$.model') FROM provider WHERE name=?1",
+        params![name],
+        |r| r.get::<_, Option<String>>(0),
+    )
+    .ok()
+    .flatten()
+    .or_else(|| defaults(name).2.map(String::from))
+}
+
+/// Build the backend for a provider row. Errors: `not_found`, `provider_not_configured`
+/// (no api/base_url), `unknown_provider_api`, `no_key` and key lookup errors.
+pub fn provider_from_config(db: &Db, name: &str) -> Result<Box<dyn AiProvider>, String> {
+    let (kind, raw): (String, Option<String>) = {
+        let conn = db.0.lock();
+        conn.query_row(
+            "SELECT kind, config FROM provider WHERE name=?1",
+            params![name],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?
+    };
+    let cfg: ProviderConfig = raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    let (default_api, default_url, default_model) = defaults(name);
+    let api = cfg
+        .api
+        .clone()
+        .or(default_api.map(String::from))
+        .ok_or("provider_not_configured")?;
+    if api == "echo" {
+        return Ok(Box::new(EchoProvider { name: name.to_string() }));
+    }
+    let base_url = cfg
+        .base_url
+        .clone()
+        .or(default_url.map(String::from))
+        .ok_or("provider_not_configured")?;
+    let model = cfg
+        .model
+        .clone()
+        .or(default_model.map(String::from))
+        .ok_or("provider_not_configured")?;
+    // Remote providers need a stored key unless the config brings its own auth header
+    let has_auth_header = cfg
+        .headers
+        .keys()
+        .any(|k| matches!(k.to_ascii_lowercase().as_str(), "authorization" | "x-api-key" | "api-key"));
+    let key = if kind == "remote" && !has_auth_header {
+        if !secrets::provider_key_exists(db, name)? {
+            return Err("no_key".into());
+        }
+        Some(secrets::provider_key_get(db, name)?)
+    } else {
+        None
+    };
+    let mut headers: Vec<(String, String)> = Vec::new();
+    match (api.as_str(), key) {
+        ("anthropic", Some(k)) => headers.push(("x-api-key".into(), k)),
+        (_, Some(k)) => headers.push(("authorization".into(), format!("Bearer {}", k))),
+        _ => {}
+    }
+    if api == "anthropic" {
+        headers.push(("anthropic-version".into(), ANTHROPIC_VERSION.into()));
+    }
+    // Config headers win over the defaults above
+    for (k, v) in cfg.headers {
+        let k = k.to_ascii_lowercase();
+        headers.retain(|(h, _)| *h != k);
+        headers.push((k, v));
+    }
+    let endpoint = Endpoint {
+        name: name.to_string(),
+        base_url,
+        model,
+        headers,
+        max_tokens: cfg.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        retry: RetryPolicy {
+            timeout: Duration::from_secs(cfg.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            retries: cfg.retries.unwrap_or(DEFAULT_RETRIES),
+            backoff: Duration::from_millis(cfg.retry_backoff_ms.unwrap_or(DEFAULT_RETRY_BACKOFF_MS)),
+        },
+        attempts: Arc::default(),
+    };
+    match api.as_str() {
+        "openai" => Ok(Box::new(OpenAiProvider(endpoint))),
+        "anthropic" => Ok(Box::new(AnthropicProvider(endpoint))),
+        "ollama" => Ok(Box::new(OllamaProvider(endpoint))),
+        other => Err(format!("unknown_provider_api: {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::test_http::serve;
+    use crate::test_util::temp_db;
+
+    fn configure(db: &Db, name: &str, config: serde_json::Value) {
+        let conn = db.0.lock();
+        conn.execute(
+            "UPDATE provider SET config=?2, enabled=1 WHERE name=?1",
+            params![name, config.to_string()],
+        )
+        .unwrap();
+    }
+
+    fn ask(db: &Db, name: &str) -> ChatResponse {
+        provider_from_config(db, name)
+            .unwrap()
+            .chat(&ChatRequest {
+                system: Some("be brief".into()),
+                messages: vec![ChatMessage {
+                    role: "user".into(),
+                    content: "hi".into(),
+                }],
+            })
+            .unwrap()
+    }
+
+    fn header<'a>(c: &'a crate::ai::test_http::Captured, name: &str) -> Option<&'a str> {
+        c.headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_backends_against_mock_server() {
+        let db = temp_db("provider");
+        assert_eq!(provider_from_config(&db, "openrouter").err().unwrap(), "no_key");
+
+        let (url, rx) = serve(vec![(
+            200,
+            "application/json",
+            r#"{"model":"m-1","choices":[{"message":{"role":"assistant","content":"hello"}}]}"#.into(),
+        )]);
+        configure(&db, "openrouter", serde_json::json!({"base_url": url, "model": "m", "headers": {"Authorization": "Bearer sk-or", "X-Title": "ae"}}));
+        let res = ask(&db, "openrouter");
+        assert_eq!((res.text.as_str(), res.model.as_str()), ("hello", "m-1"));
+        let c = rx.recv().unwrap();
+        assert_eq!(c.request_line, "POST /chat/completions HTTP/1.1");
+        assert_eq!(header(&c, "authorization"), Some("Bearer sk-or"));
+        assert_eq!(header(&c, "x-title"), Some("ae"));
+        let body: serde_json::Value = serde_json::from_str(&c.body).unwrap();
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "hi");
+
+        let (url, rx) = serve(vec![(
+            200,
+            "application/json",
+            r#"{"model":"c-1","content":[{"type":"text","text":"hel"},{"type":"text","text":"lo"}]}"#.into(),
+        )]);
+        configure(&db, "claude-code", serde_json::json!({"base_url": url, "max_tokens": 50, "headers": {"x-api-key": "sk-ant"}}));
+        assert_eq!(ask(&db, "claude-code").text, "hello");
+        let c = rx.recv().unwrap();
+        assert_eq!(c.request_line, "POST /v1/messages HTTP/1.1");
+        assert_eq!(header(&c, "x-api-key"), Some("sk-ant"));
+        assert_eq!(header(&c, "anthropic-version"), Some(ANTHROPIC_VERSION));
+        let body: serde_json::Value = serde_json::from_str(&c.body).unwrap();
+        assert_eq!((body["system"].as_str(), body["max_tokens"].as_u64()), (Some("be brief"), Some(50)));
+
+        let (url, rx) = serve(vec![
+            (200, "application/json", r#"{"model":"llama","message":{"role":"assistant","content":"hi back"}}"#.into()),
+            (500, "text/plain", "boom".into()),
+        ]);
+        configure(&db, "local", serde_json::json!({"base_url": url, "retries": 0}));
+        assert_eq!(ask(&db, "local").text, "hi back");
+        assert_eq!(rx.recv().unwrap().request_line, "POST /api/chat HTTP/1.1");
+        let err = provider_from_config(&db, "local")
+            .unwrap()
+            .chat(&ChatRequest::default())
+            .unwrap_err();
+        assert!(err.starts_with("http_error: 500"), "{}", err);
+
+        assert_eq!(provider_from_config(&db, "opencode").err().unwrap(), "provider_not_configured");
+        configure(&db, "opencode", serde_json::json!({"api": "echo"}));
+        assert_eq!(ask(&db, "opencode").text, "hi");
+    }
+
+    #[test]
+    fn test_retries_on_429_and_5xx_only() {
+        let db = temp_db("provider-retry");
+        let ok = r#"{"model":"llama","message":{"role":"assistant","content":"third time"}}"#;
+        let (url, rx) = serve(vec![
+            (429, "text/plain", "slow down".into()),
+            (503, "text/plain", "busy".into()),
+            (200, "application/json", ok.into()),
+            (400, "text/plain", "bad request".into()),
+        ]);
+        configure(&db, "local", serde_json::json!({"base_url": url, "retries": 2, "retry_backoff_ms": 1}));
+        assert_eq!(ask(&db, "local").text, "third time");
+        assert_eq!(rx.try_iter().count(), 3);
+        // Client errors are not retried
+        let err = provider_from_config(&db, "local")
+            .unwrap()
+            .chat(&ChatRequest::default())
+            .unwrap_err();
+        assert!(err.starts_with("http_error: 400"), "{}", err);
+
+        // Retries exhausted: the last failure comes back
+        let (url, _rx) = serve(vec![(502, "text/plain", "a".into()), (502, "text/plain", "b".into())]);
+        configure(&db, "local", serde_json::json!({"base_url": url, "retries": 1, "retry_backoff_ms": 1}));
+        let backend = provider_from_config(&db, "local").unwrap();
+        let err = backend.chat(&ChatRequest::default()).unwrap_err();
+        assert_eq!(err, "http_error: 502 b");
+        // Each request sent is logged, retries included
+        assert_eq!(
+            backend.take_attempts(),
+            vec![Err("http_error: 502 a".to_string()), Err("http_error: 502 b".to_string())]
+        );
+        assert!(backend.take_attempts().is_empty());
+    }
+
+    #[test]
+    fn test_streaming_anthropic_and_ollama() {
+        let db = temp_db("provider-stream");
+        let anthropic = [
+            "event: message_start",
+            r#"data: {"type":"message_start","message":{"model":"c-1"}}"#,
+            "",
+            "event: content_block_delta",
+            r#"data: {"type":"content_block_delta","delta":{"type":"text_delta","text":"hi "}}"#,
+            "",
+            r#"data: {"type":"content_block_delta","delta":{"type":"text_delta","text":"there"}}"#,
+            r#"data: {"type":"message_stop"}"#,
+        ]
+        .join("\n");
+        let ollama = [
+            r#"{"model":"llama","message":{"content":"a"},"done":false}"#,
+            r#"{"model":"llama","message":{"content":"b"},"done":false}"#,
+            r#"{"model":"llama","message":{"content":""},"done":true}"#,
+        ]
+        .join("\n");
+        let (a_url, _a) = serve(vec![(200, "text/event-stream", anthropic)]);
+        let (o_url, _o) = serve(vec![(200, "application/x-ndjson", ollama)]);
+        configure(&db, "claude-code", serde_json::json!({"base_url": a_url, "headers": {"x-api-key": "k"}}));
+        configure(&db, "local", serde_json::json!({"base_url": o_url}));
+        for (name, expect, model) in [("claude-code", "hi there", "c-1"), ("local", "ab", "llama")] {
+            let mut seen = String::new();
+            let res = provider_from_config(&db, name)
+                .unwrap()
+                .chat_stream(&ChatRequest::default(), &AtomicBool::new(false), &mut |d| {
+                    seen.push_str(d);
+                    true
+                })
+                .unwrap();
+            assert_eq!((seen.as_str(), res.text.as_str(), res.model.as_str()), (expect, expect, model));
+        }
+    }
+
+    #[test]
+    fn test_stalled_stream_times_out_or_cancels() {
+        let db = temp_db("provider-stall");
+        let head = "{\"model\":\"llama\",\"message\":{\"content\":\"par\"},\"done\":false}\n".to_string();
+        let hold = Duration::from_secs(10);
+
+        // Nothing after the first chunk for longer than timeout_secs
+        let url = crate::ai::test_http::serve_stalled("application/x-ndjson", head.clone(), hold);
+        configure(&db, "local", serde_json::json!({"base_url": url, "timeout_secs": 1, "retries": 0}));
+        let started = std::time::Instant::now();
+        let err = provider_from_config(&db, "local")
+            .unwrap()
+            .chat_stream(&ChatRequest::default(), &AtomicBool::new(false), &mut |_| true)
+            .unwrap_err();
+        assert!(err.starts_with("timeout: "), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Cancelled while waiting: returns what arrived without waiting for more
+        let url = crate::ai::test_http::serve_stalled("application/x-ndjson", head, hold);
+        configure(&db, "local", serde_json::json!({"base_url": url, "timeout_secs": 30}));
+        let cancel = AtomicBool::new(false);
+        let started = std::time::Instant::now();
+        let res = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(300));
+                cancel.store(true, Ordering::SeqCst);
+            });
+            provider_from_config(&db, "local")
+                .unwrap()
+                .chat_stream(&ChatRequest::default(), &cancel, &mut |_| true)
+                .unwrap()
+        });
+        assert_eq!(res.text, "par");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,94 @@
+//! Minimal one-shot HTTP mock server for provider/embedding tests.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver};
+
+/// A request captured by the mock server.
+pub struct Captured {
+    pub request_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// Serve `responses` (status, content-type, body) in order, one per connection.
+/// Returns the base URL (`http://127.0.0.1:port`) and a receiver of captured requests.
+pub fn serve(responses: Vec<(u16, &'static str, String)>) -> (String, Receiver<Captured>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock");
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        for (status, content_type, body) in responses {
+            let Ok((stream, _)) = listener.accept() else { return };
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            let _ = reader.read_line(&mut request_line);
+            let mut headers = Vec::new();
+            let mut content_length = 0usize;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((k, v)) = line.trim_end().split_once(':') {
+                    let (k, v) = (k.trim().to_ascii_lowercase(), v.trim().to_string());
+                    if k == "content-length" {
+                        content_length = v.parse().unwrap_or(0);
+                    }
+                    headers.push((k, v));
+                }
+            }
+            let mut req_body = vec![0u8; content_length];
+            let _ = reader.read_exact(&mut req_body);
+            let _ = tx.send(Captured {
+                request_line: request_line.trim_end().to_string(),
+                headers,
+                body: String::from_utf8_lossy(&req_body).to_string(),
+            });
+            let mut stream = reader.into_inner();
+            let resp = format!(
+                "HTTP/1.1 {} MOCK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                content_type,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(resp.as_bytes());
+        }
+    });
+    (format!("http://{}", addr), rx)
+}
+
+/// Serve one streamed reply that sends `head` and then stalls, holding the connection open
+/// for `hold`. Returns the base URL.
+pub fn serve_stalled(content_type: &'static str, head: String, hold: std::time::Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock");
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let Ok((stream, _)) = listener.accept() else { return };
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0usize;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                break;
+            }
+            if let Some((k, v)) = line.trim_end().split_once(':') {
+                if k.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = v.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let _ = reader.read_exact(&mut vec![0u8; content_length]);
+        let mut stream = reader.into_inner();
+        let chunk = format!("{:x}\r\n{}\r\n", head.len(), head);
+        let resp = format!(
+            "HTTP/1.1 200 MOCK\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+            content_type, chunk
+        );
+        let _ = stream.write_all(resp.as_bytes());
+        let _ = stream.flush();
+        std::thread::sleep(hold);
+    });
+    format!("http://{}", addr)
+}
@@ -0,0 +1,298 @@
+//! Tool use for `ai_run`.
+//!
+//! The model calls a tool by answering with a single JSON object (a code fence around it is
+//! fine): `{"tool": "<name>", "arguments": {...}}`. The result goes back as the next user
+//! message and the model is asked again, up to `max_steps` model calls; any other answer is
+//! the final one. Tools work on the run's repo only:
+//! - `search` `{ query, limit? }`: full-text hits
+//! - `docs_get` `{ doc }`: a doc with its body (id or slug)
+//! - `graph_backlinks` `{ doc }` / `graph_neighbors` `{ doc }`: linked docs
+//! - `suggest_edit` `{ doc, body | patch, message? }`: a pending suggestion (only with `allow_write`)
+//!
+//! Each model call is its own trace; a tool call is recorded on the trace of the answer that
+//! made it, as `response.tool_call: { tool, arguments, result | error }`.
+
+use super::provider::{ChatMessage, ChatRequest};
+use crate::db::Db;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+pub const READ_TOOLS: &[&str] = &["search", "docs_get", "graph_backlinks", "graph_neighbors"];
+pub const WRITE_TOOL: &str = "suggest_edit";
+const DEFAULT_MAX_STEPS: usize = 6;
+const MAX_STEPS: usize = 20;
+/// Doc bodies handed to the model are cut to this many characters
+const MAX_BODY_CHARS: usize = 16_000;
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+pub struct ToolOptions {
+    /// Tools offered; default all read-only ones
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+    /// Also offer `suggest_edit`
+    #[serde(default)]
+    pub allow_write: bool,
+    /// Model calls before giving up (default 6, at most 20)
+    #[serde(default)]
+    pub max_steps: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub tool: String,
+    pub arguments: serde_json::Value,
+}
+
+/// The tools of one run, bound to its repo.
+pub struct Toolbox {
+    repo_id: String,
+    tools: Vec<&'static str>,
+    pub max_steps: usize,
+}
+
+fn describe(tool: &str) -> &'static str {
+    match tool {
+        "search" => r#"search {"query": "<full-text query>", "limit": 10} - find docs"#,
+        "docs_get" => r#"docs_get {"doc": "<id or slug>"} - read a doc"#,
+        "graph_backlinks" => r#"graph_backlinks {"doc": "<id or slug>"} - docs linking to it"#,
+        "graph_neighbors" => r#"graph_neighbors {"doc": "<id or slug>"} - docs it links to or shares links with"#,
+        _ => r#"suggest_edit {"doc": "<id or slug>", "body": "<new full body>" or "patch": "<unified diff>", "message": "<why>"} - propose an edit for review"#,
+    }
+}
+
+impl Toolbox {
+    /// Errors `unknown_tool: <name>`, `write_not_allowed` (`suggest_edit` without `allow_write`).
+    pub fn new(repo_id: &str, opts: &ToolOptions) -> Result<Self, String> {
+        let mut tools: Vec<&'static str> = Vec::new();
+        match &opts.tools {
+            Some(names) => {
+                for name in names {
+                    let tool = READ_TOOLS
+                        .iter()
+                        .chain(std::iter::once(&WRITE_TOOL))
+                        .find(|t| **t == name.as_str())
+                        .ok_or_else(|| format!("unknown_tool: {}", name))?;
+                    if *tool == WRITE_TOOL && !opts.allow_write {
+                        return Err("write_not_allowed".into());
+                    }
+                    if !tools.contains(tool) {
+                        tools.push(tool);
+                    }
+                }
+            }
+            None => tools.extend(READ_TOOLS),
+        }
+        if opts.allow_write && !tools.contains(&WRITE_TOOL) {
+            tools.push(WRITE_TOOL);
+        }
+        Ok(Toolbox {
+            repo_id: repo_id.to_string(),
+            tools,
+            max_steps: opts.max_steps.unwrap_or(DEFAULT_MAX_STEPS).clamp(1, MAX_STEPS),
+        })
+    }
+
+    pub fn names(&self) -> &[&'static str] {
+        &self.tools
+    }
+
+    /// Added to the system prompt: the tools and how to call them.
+    pub fn instructions(&self) -> String {
+        let list: Vec<&str> = self.tools.iter().map(|t| describe(t)).collect();
+        format!(
+            "You can use these tools:\n- {}\nTo call one, reply with only a JSON object: {{\"tool\": \"<name>\", \"arguments\": {{...}}}}. \
+             The result comes back in the next message. Call one tool at a time; when you are done, reply with your answer as plain text.",
+            list.join("\n- ")
+        )
+    }
+
+    /// Id of a live doc of the run's repo.
+    fn doc_id(&self, db: &Db, args: &serde_json::Value) -> Result<String, String> {
+        let doc = args["doc"].as_str().or(args["doc_id"].as_str()).ok_or("missing_argument: doc")?;
+        db.0.lock()
+            .query_row(
+                "SELECT id FROM doc WHERE repo_id=?1 AND (id=?2 OR slug=?2) AND is_deleted=0 LIMIT 1",
+                params![self.repo_id, doc],
+                |r| r.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "not_found".to_string())
+    }
+
+    /// Run one call. `trace_id` is the trace of the answer that made it (suggestions point at it).
+    pub fn call(&self, db: &Db, call: &ToolCall, trace_id: &str) -> Result<serde_json::Value, String> {
+        if !self.tools.contains(&call.tool.as_str()) {
+            return Err(format!("unknown_tool: {}", call.tool));
+        }
+        let args = &call.arguments;
+        match call.tool.as_str() {
+            "search" => {
+                let query = args["query"].as_str().ok_or("missing_argument: query")?;
+                let hits = crate::commands::search_core(
+                    db,
+                    crate::commands::SearchRequest {
+                        repo_id: Some(self.repo_id.clone()),
+                        query: query.to_string(),
+                        limit: Some(args["limit"].as_i64().unwrap_or(10).clamp(1, 50)),
+                        offset: None,
+                        mode: None,
+                    },
+                )?;
+                let hits: Vec<serde_json::Value> = hits
+                    .into_iter()
+                    .map(|h| serde_json::json!({"id": h.id, "slug": h.slug, "title": h.title_snip, "snippet": crate::commands::redact(&h.body_snip)}))
+                    .collect();
+                Ok(serde_json::json!(hits))
+            }
+            "docs_get" => {
+                let mut doc = crate::commands::docs_get_core(db, &self.doc_id(db, args)?, true)?;
+                let body = crate::commands::redact(doc["body"].as_str().unwrap_or_default());
+                if body.chars().count() > MAX_BODY_CHARS {
+                    doc["body"] = serde_json::json!(body.chars().take(MAX_BODY_CHARS).collect::<String>());
+                    doc["truncated"] = serde_json::Value::Bool(true);
+                } else {
+                    doc["body"] = serde_json::json!(body);
+                }
+                Ok(doc)
+            }
+            "graph_backlinks" => {
+                let rows = crate::commands::graph_backlinks_core(db, &self.doc_id(db, args)?)?;
+                serde_json::to_value(rows).map_err(|e| e.to_string())
+            }
+            "graph_neighbors" => {
+                let rows = crate::commands::graph_neighbors_core(db, &self.doc_id(db, args)?)?;
+                serde_json::to_value(rows).map_err(|e| e.to_string())
+            }
+            _ => {
+                let doc_id = self.doc_id(db, args)?;
+                let conn = db.0.lock();
+                crate::commands::create_suggestion(
+                    &conn,
+                    crate::commands::SuggestionCreate {
+                        doc_id,
+                        base_version_id: None,
+                        body: args["body"].as_str().map(String::from),
+                        patch: args["patch"].as_str().map(String::from),
+                        message: args["message"].as_str().map(String::from),
+                        author: None,
+                        source: Some("ai".into()),
+                        trace_id: Some(trace_id.to_string()),
+                    },
+                )
+            }
+        }
+    }
+}
+
+/// The tool call an answer makes, if it is one.
+pub fn parse_call(text: &str) -> Option<ToolCall> {
+    let mut t = text.trim();
+    if t.starts_with("```") && t.ends_with("```") && t.len() > 6 {
+        let inner = &t[3..t.len() - 3];
+        t = inner[inner.find('\n')? + 1..].trim();
+    }
+    if !t.starts_with('{') {
+        return None;
+    }
+    let v: serde_json::Value = serde_json::from_str(t).ok()?;
+    let tool = v["tool"].as_str()?.to_string();
+    let arguments = match &v["arguments"] {
+        serde_json::Value::Null => serde_json::json!({}),
+        a => a.clone(),
+    };
+    Some(ToolCall { tool, arguments })
+}
+
+/// One model call of the loop: its trace and answer.
+pub struct Step {
+    pub trace_id: String,
+    pub text: String,
+    /// The command result for this call
+    pub out: serde_json::Value,
+}
+
+/// Ask, run the tool the answer calls, feed the result back, and repeat until an answer
+/// calls no tool. `ask` makes one model call (and records its trace). Returns the final step
+/// and the calls made, `{ step, trace_id, tool, arguments, result | error }` each. A tool
+/// error goes back to the model like a result. Errors `max_steps_exceeded: <n>` when the
+/// last allowed call still asked for a tool.
+pub fn run_loop(
+    db: &Db,
+    toolbox: &Toolbox,
+    chat: &mut ChatRequest,
+    mut ask: impl FnMut(&ChatRequest, usize) -> Result<Step, String>,
+) -> Result<(Step, Vec<serde_json::Value>), String> {
+    let mut calls = Vec::new();
+    for n in 1..=toolbox.max_steps {
+        let step = ask(chat, n)?;
+        let Some(call) = parse_call(&step.text) else {
+            return Ok((step, calls));
+        };
+        let mut record = serde_json::json!({"tool": call.tool, "arguments": call.arguments});
+        let feedback = match toolbox.call(db, &call, &step.trace_id) {
+            Ok(result) => {
+                let text = result.to_string();
+                record["result"] = result;
+                text
+            }
+            Err(e) => {
+                record["error"] = serde_json::json!(e);
+                serde_json::json!({"error": e}).to_string()
+            }
+        };
+        db.0.lock()
+            .execute(
+                "UPDATE ai_trace SET response=json_set(response,'$.tool_call',json(?2)) WHERE id=?1",
+                params![step.trace_id, record.to_string()],
+            )
+            .map_err(|e| e.to_string())?;
+        chat.messages.push(ChatMessage {
+            role: "assistant".into(),
+            content: step.text,
+        });
+        chat.messages.push(ChatMessage {
+            role: "user".into(),
+            content: format!("Result of {}:\n{}", call.tool, feedback),
+        });
+        record["step"] = serde_json::json!(n);
+        record["trace_id"] = serde_json::json!(step.trace_id);
+        calls.push(record);
+    }
+    Err(format!("max_steps_exceeded: {}", toolbox.max_steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_call_and_toolbox() {
+        let call = parse_call("```json\n{\"tool\": \"search\", \"arguments\": {\"query\": \"x\"}}\n```").unwrap();
+        assert_eq!((call.tool.as_str(), &call.arguments["query"]), ("search", &serde_json::json!("x")));
+        assert_eq!(parse_call("{\"tool\": \"docs_get\"}").unwrap().arguments, serde_json::json!({}));
+        assert_eq!(parse_call("The answer is {not json}"), None);
+        assert_eq!(parse_call("{\"answer\": 1}"), None);
+
+        let read = Toolbox::new("r", &ToolOptions::default()).unwrap();
+        assert_eq!((read.names(), read.max_steps), (READ_TOOLS, 6));
+        let write = ToolOptions {
+            tools: Some(vec!["search".into()]),
+            allow_write: true,
+            max_steps: Some(99),
+        };
+        let tb = Toolbox::new("r", &write).unwrap();
+        assert_eq!((tb.names(), tb.max_steps), (&["search", "suggest_edit"][..], 20));
+        assert!(tb.instructions().contains("suggest_edit {"));
+        let sneaky = ToolOptions {
+            tools: Some(vec!["suggest_edit".into()]),
+            ..Default::default()
+        };
+        assert_eq!(Toolbox::new("r", &sneaky).err().unwrap(), "write_not_allowed");
+        let unknown = ToolOptions {
+            tools: Some(vec!["shell".into()]),
+            ..Default::default()
+        };
+        assert_eq!(Toolbox::new("r", &unknown).err().unwrap(), "unknown_tool: shell");
+    }
+}
@@ -0,0 +1,128 @@
+//! Token accounting and cost for AI traces.
+//!
+//! Backends report usage when they can; otherwise tokens are estimated from the text
+//! (about four characters per token). Prices come from the provider's `config.prices`,
+//! in USD per million tokens: `{"<model>": {"input": 3.0, "output": 15.0}}`. The model the
+//! backend reports is looked up first, then the configured one (backends often answer with a
+//! dated variant of it), then `"*"`. Without a price the cost is left NULL.
+
+use super::provider::{ChatRequest, ChatResponse};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub input: f64,
+    #[serde(default)]
+    pub output: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// At least one side was estimated locally
+    pub estimated: bool,
+    pub cost_usd: Option<f64>,
+}
+
+pub fn estimate_tokens(text: &str) -> i64 {
+    text.chars().count().div_ceil(4) as i64
+}
+
+fn estimate_request(req: &ChatRequest) -> i64 {
+    let system = req.system.as_deref().map(estimate_tokens).unwrap_or(0);
+    // A few tokens of framing per message
+    system + req.messages.iter().map(|m| estimate_tokens(&m.content) + 4).sum::<i64>()
+}
+
+/// Price on `provider` of the first of `models` listed, then `"*"`.
+pub fn model_price(conn: &Connection, provider: &str, models: &[&str]) -> Option<ModelPrice> {
+    let raw: String = conn
+        .query_row(
+            "SELECT json_extract(config,'$.prices') FROM provider WHERE name=?1",
+            params![provider],
+            |r| r.get::<_, Option<String>>(0),
+        )
+        .optional()
+        .ok()???;
+    let prices: HashMap<String, ModelPrice> = serde_json::from_str(&raw).ok()?;
+    models
+        .iter()
+        .find_map(|m| prices.get(*m))
+        .or_else(|| prices.get("*"))
+        .copied()
+}
+
+/// Usage of one call: reported counts, estimates for what is missing, and the cost.
+/// `configured_model` is the model the request asked for.
+pub fn usage_for(
+    conn: &Connection,
+    provider: &str,
+    configured_model: &str,
+    req: &ChatRequest,
+    res: &ChatResponse,
+) -> Usage {
+    let input_tokens = res.input_tokens.unwrap_or_else(|| estimate_request(req));
+    let output_tokens = res.output_tokens.unwrap_or_else(|| estimate_tokens(&res.text));
+    let cost_usd = model_price(conn, provider, &[&res.model, configured_model]).map(|p| {
+        (input_tokens as f64 * p.input + output_tokens as f64 * p.output) / 1_000_000.0
+    });
+    Usage {
+        input_tokens,
+        output_tokens,
+        estimated: res.input_tokens.is_none() || res.output_tokens.is_none(),
+        cost_usd,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::provider::ChatMessage;
+    use crate::test_util::temp_db;
+
+    #[test]
+    fn test_usage_reported_estimated_and_priced() {
+        let db = temp_db("usage");
+        let conn = db.0.lock();
+        conn.execute(
+            "UPDATE provider SET config=json_set(config,'$.prices',json(?1)) WHERE name='openrouter'",
+            params![r#"{"m-1":{"input":3,"output":15},"*":{"input":1}}"#],
+        )
+        .unwrap();
+        let req = ChatRequest {
+            system: None,
+            messages: vec![ChatMessage {
+                role: "user".into(),
+                content: "12345678".into(),
+            }],
+        };
+        let mut res = ChatResponse {
+            text: "abcd".into(),
+            model: "m-1".into(),
+            input_tokens: Some(1000),
+            output_tokens: Some(2000),
+        };
+        let u = usage_for(&conn, "openrouter", "m-1", &req, &res);
+        assert_eq!((u.input_tokens, u.output_tokens, u.estimated), (1000, 2000, false));
+        assert!((u.cost_usd.unwrap() - 0.033).abs() < 1e-9);
+
+        // A dated variant of the configured model is priced as the configured one
+        res.model = "m-1-2026-01-01".into();
+        let u = usage_for(&conn, "openrouter", "m-1", &req, &res);
+        assert!((u.cost_usd.unwrap() - 0.033).abs() < 1e-9);
+
+        res.model = "other".into();
+        res.output_tokens = None;
+        let u = usage_for(&conn, "openrouter", "other", &req, &res);
+        assert_eq!((u.output_tokens, u.estimated), (1, true));
+        assert!((u.cost_usd.unwrap() - 0.001).abs() < 1e-9);
+
+        res.input_tokens = None;
+        let u = usage_for(&conn, "local", "other", &req, &res);
+        assert_eq!((u.input_tokens, u.cost_usd), (6, None));
+    }
+}
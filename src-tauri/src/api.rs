@@ -11,7 +11,15 @@
 
 use std::{net::SocketAddr, sync::Arc};
 
-use axum::{routing::post, Json, Router};
+use axum::{
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    routing::post,
+    Json, Router,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 use serde::{Deserialize, Serialize};
 
 use crate::db::Db;
@@ -56,10 +64,17 @@ pub async fn start_api(
 async fn handler(
     axum::extract::State(db): axum::extract::State<Arc<Db>>,
     Json(req): Json<RpcReq>,
-) -> Json<RpcRes> {
+) -> Response {
     let id = req.id.clone();
+    if req.method == "ai_run_stream" {
+        return ai_run_stream(req, db).into_response();
+    }
+    let stream = req.params.as_ref().and_then(|p| p.get("stream")).and_then(|s| s.as_bool());
+    if req.method == "search_grep" && stream == Some(true) {
+        return search_grep_stream(req, db).into_response();
+    }
     let result = route(req, db).await;
-    match result {
+    let res = match result {
         Ok(v) => Json(RpcRes {
             jsonrpc: "2.0".into(),
             id,
@@ -75,7 +90,98 @@ async fn handler(
                 message: e,
             }),
         }),
+    };
+    res.into_response()
+}
+
+/// `ai_run_stream` answers with server-sent events instead of a JSON-RPC envelope:
+/// `start` (`{trace_id}`), `delta` (`{text}`) per chunk, then `done` (the `ai_run` result)
+/// or `error` (`{error}`). A client that goes away cancels the run.
+fn ai_run_stream(
+    req: RpcReq,
+    db: Arc<Db>,
+) -> Sse<UnboundedReceiverStream<Result<Event, std::convert::Infallible>>> {
+    type Tx = tokio::sync::mpsc::UnboundedSender<Result<Event, std::convert::Infallible>>;
+    /// False once the client is gone
+    fn send(tx: &Tx, name: &str, data: serde_json::Value) -> bool {
+        tx.send(Ok(Event::default().event(name).data(data.to_string()))).is_ok()
+    }
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    match serde_json::from_value::<crate::commands::AiRunRequest>(req.params.unwrap_or_default()) {
+        Err(e) => {
+            send(&tx, "error", serde_json::json!({"error": e.to_string()}));
+        }
+        Ok(run) => {
+            let trace_id = Uuid::new_v4().to_string();
+            crate::commands::run_token(&trace_id);
+            send(&tx, "start", serde_json::json!({"trace_id": trace_id}));
+            // The client may drop the stream while no delta is going out (a stalled provider)
+            let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+            let (watch_tx, tid) = (tx.clone(), trace_id.clone());
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = watch_tx.closed() => {
+                        crate::commands::ai_run_cancel_core(&tid);
+                    }
+                    _ = done_rx => {}
+                }
+            });
+            std::thread::spawn(move || {
+                let delta_tx = tx.clone();
+                let res = crate::commands::ai_run_stream_core(&db, run, &trace_id, |text| {
+                    if !send(&delta_tx, "delta", serde_json::json!({"text": text})) {
+                        crate::commands::ai_run_cancel_core(&trace_id);
+                    }
+                });
+                match res {
+                    Ok(out) => send(&tx, "done", out),
+                    Err(e) => send(&tx, "error", serde_json::json!({"error": e})),
+                };
+                let _ = done_tx.send(());
+            });
+        }
     }
+    Sse::new(UnboundedReceiverStream::new(rx))
+}
+
+/// `search_grep` with `stream: true` answers with server-sent events: `matches`
+/// (`{search_id, matches}`) per batch, then `done` (the report without matches) or `error`
+/// (`{error}`). A client that goes away stops the scan.
+fn search_grep_stream(
+    req: RpcReq,
+    db: Arc<Db>,
+) -> Sse<UnboundedReceiverStream<Result<Event, std::convert::Infallible>>> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let send = move |name: &str, data: serde_json::Value| {
+        tx.send(Ok(Event::default().event(name).data(data.to_string()))).is_ok()
+    };
+    match serde_json::from_value::<crate::commands::GrepRequest>(req.params.unwrap_or_default()) {
+        Err(e) => {
+            send("error", serde_json::json!({"error": e.to_string()}));
+        }
+        Ok(p) => {
+            std::thread::spawn(move || {
+                let search_id = Uuid::new_v4().to_string();
+                let sid = search_id.clone();
+                let res = crate::commands::grep_core(&db, &p, search_id, |batch| {
+                    send("matches", serde_json::json!({"search_id": sid, "matches": batch}))
+                });
+                match res {
+                    Ok(report) => send(
+                        "done",
+                        serde_json::json!({
+                            "search_id": report.search_id,
+                            "docs_scanned": report.docs_scanned,
+                            "match_count": report.match_count,
+                            "truncated": report.truncated,
+                        }),
+                    ),
+                    Err(e) => send("error", serde_json::json!({"error": e})),
+                };
+            });
+        }
+    }
+    Sse::new(UnboundedReceiverStream::new(rx))
 }
 
 async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
@@ -162,6 +268,16 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
                 .map_err(|e| e.to_string())?;
             Ok(serde_json::json!({"removed": n>0}))
         }
+        "repos_set_fallback_providers" => {
+            #[derive(Deserialize)]
+            struct P {
+                id_or_name: String,
+                providers: Vec<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::repos_set_fallback_providers_core(&db, &p.id_or_name, &p.providers)
+        }
         "scan_repo" => {
             #[derive(Deserialize)]
             struct P {
@@ -225,117 +341,116 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
                 .map_err(|e| e.to_string())?;
             let conn = db.0.lock();
             conn.execute("UPDATE scan_job SET status='success', stats=?2, finished_at=datetime('now') WHERE id=?1", params![&job_id, serde_json::to_string(&serde_json::json!({"files_scanned": stats.files_scanned, "docs_added": stats.docs_added, "errors": stats.errors})).unwrap()]).map_err(|e| e.to_string())?;
+            drop(conn);
+            // No event channel over RPC: watched saved-search changes ride along in the response
+            let changes = crate::commands::saved_searches_check(&db)?;
             Ok(
-                serde_json::json!({"job_id": job_id, "files_scanned": stats.files_scanned, "docs_added": stats.docs_added, "errors": stats.errors}),
+                serde_json::json!({"job_id": job_id, "files_scanned": stats.files_scanned, "docs_added": stats.docs_added, "errors": stats.errors, "collisions": stats.collisions, "saved_search_changes": changes}),
             )
         }
         "docs_create" => {
+            let p: crate::commands::DocCreate = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::docs_create_core(&db, p)
+        }
+        "docs_update" => {
+            let p: crate::commands::DocUpdate = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::docs_update_core(&db, p)
+        }
+        "docs_get" => {
             #[derive(Deserialize)]
             struct P {
-                repo_id: String,
-                slug: String,
-                title: String,
-                body: String,
+                doc_id: String,
+                content: Option<bool>,
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let mut conn = db.0.lock();
-            let doc_id = Uuid::new_v4().to_string();
-            let blob_id = Uuid::new_v4().to_string();
-            let version_id = Uuid::new_v4().to_string();
-            let tx = conn.transaction().map_err(|e| e.to_string())?;
-            tx.execute("INSERT INTO doc(id,repo_id,folder_id,slug,title,size_bytes,line_count) VALUES(?,?,?,?,?,?,?)",
-                params![doc_id, p.repo_id, tx.last_insert_rowid(), p.slug, p.title, p.body.len() as i64, p.body.lines().count() as i64]).map_err(|e| e.to_string())?;
-            tx.execute(
-                "INSERT INTO doc_blob(id,content,size_bytes) VALUES(?,?,?)",
-                params![blob_id, p.body.as_bytes(), p.body.len() as i64],
-            )
-            .map_err(|e| e.to_string())?;
-            let body_hash = blake3::hash(p.body.as_bytes()).to_hex().to_string();
-            let version_hash = format!("{}:{}", doc_id, body_hash);
-            tx.execute(
-                "INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES(?,?,?,?)",
-                params![version_id, doc_id, blob_id, version_hash],
-            )
-            .map_err(|e| e.to_string())?;
-            tx.execute(
-                "UPDATE doc SET current_version_id=?1 WHERE id=?2",
-                params![version_id, doc_id],
-            )
-            .map_err(|e| e.to_string())?;
-            tx.execute("INSERT INTO doc_fts(rowid,title,body,slug,repo_id) SELECT d.rowid,d.title,?1,d.slug,d.repo_id FROM doc d WHERE d.id=?2", params![p.body, doc_id]).map_err(|e| e.to_string())?;
-            tx.commit().map_err(|e| e.to_string())?;
-            Ok(serde_json::json!({"doc_id": doc_id}))
+            crate::commands::docs_get_core(&db, &p.doc_id, p.content.unwrap_or(false))
         }
-        "docs_update" => {
+        "docs_versions_list" => {
             #[derive(Deserialize)]
             struct P {
                 doc_id: String,
-                body: String,
-                message: Option<String>,
+                limit: Option<i64>,
+                offset: Option<i64>,
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let mut conn = db.0.lock();
-            let version_id = Uuid::new_v4().to_string();
-            let blob_id = Uuid::new_v4().to_string();
-            let tx = conn.transaction().map_err(|e| e.to_string())?;
-            tx.execute(
-                "INSERT INTO doc_blob(id,content,size_bytes) VALUES(?,?,?)",
-                params![blob_id, p.body.as_bytes(), p.body.len() as i64],
-            )
-            .map_err(|e| e.to_string())?;
-            tx.execute(
-                "INSERT INTO doc_version(id,doc_id,blob_id,hash,message) VALUES(?,?,?,?,?)",
-                params![
-                    version_id,
-                    p.doc_id,
-                    blob_id,
-                    version_id,
-                    p.message.unwrap_or_default()
-                ],
-            )
-            .map_err(|e| e.to_string())?;
-            tx.execute("UPDATE doc SET current_version_id=?1, size_bytes=?2, line_count=?3, updated_at=datetime('now') WHERE id=?4", params![version_id, p.body.len() as i64, p.body.lines().count() as i64, p.doc_id]).map_err(|e| e.to_string())?;
-            tx.execute("INSERT INTO doc_fts(doc_fts,rowid) VALUES('delete',(SELECT rowid FROM doc WHERE id=?1))", params![p.doc_id]).ok();
-            tx.execute("INSERT INTO doc_fts(rowid,title,body,slug,repo_id) SELECT d.rowid,d.title,?1,d.slug,d.repo_id FROM doc d WHERE d.id=?2", params![p.body, p.doc_id]).map_err(|e| e.to_string())?;
-            tx.commit().map_err(|e| e.to_string())?;
-            Ok(serde_json::json!({"version_id": version_id}))
+            let list = crate::commands::docs_versions_list_core(&db, &p.doc_id, p.limit, p.offset)?;
+            serde_json::to_value(list).map_err(|e| e.to_string())
         }
-        "docs_get" => {
+        "docs_version_get" => {
+            #[derive(Deserialize)]
+            struct P {
+                version_id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::docs_version_get_core(&db, &p.version_id)
+        }
+        "docs_diff" => {
             #[derive(Deserialize)]
             struct P {
                 doc_id: String,
-                content: Option<bool>,
+                from: String,
+                to: Option<String>,
+                format: Option<String>,
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
+            let diff = crate::commands::docs_diff_core(&db, &p.doc_id, &p.from, p.to.as_deref(), p.format.as_deref())?;
+            serde_json::to_value(diff).map_err(|e| e.to_string())
+        }
+        "docs_restore" => {
+            #[derive(Deserialize)]
+            struct P {
+                doc_id: String,
+                version_id: String,
+                message: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::docs_restore_core(&db, &p.doc_id, &p.version_id, p.message)
+        }
+        "gc_blobs" => crate::commands::gc_blobs_core(&db),
+        "retention_policy_get" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
             let conn = db.0.lock();
-            let debug = std::env::var("AE_DEBUG_SQL")
-                .ok()
-                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
-                .unwrap_or(false);
-            let mut stmt = conn.prepare("SELECT id,repo_id,slug,title,current_version_id FROM doc WHERE id=?1 OR slug=?1 LIMIT 1").map_err(|e| e.to_string())?;
-            let mut rows = stmt.query(params![p.doc_id]).map_err(|e| e.to_string())?;
-            if let Some(r) = rows.next().map_err(|e| e.to_string())? {
-                let id: String = r.get(0).unwrap_or_default();
-                let mut out = serde_json::json!({
-                    "id": id,
-                    "repo_id": r.get::<_, String>(1).unwrap_or_default(),
-                    "slug": r.get::<_, String>(2).unwrap_or_default(),
-                    "title": r.get::<_, String>(3).unwrap_or_default(),
-                    "current_version_id": r.get::<_, String>(4).unwrap_or_default(),
-                });
-                if p.content.unwrap_or(false) {
-                    let body: Option<String> = conn.query_row("SELECT body FROM doc_fts WHERE rowid=(SELECT rowid FROM doc WHERE id=?1)", params![&id], |rr| rr.get(0)).ok();
-                    out["body"] = body
-                        .map(serde_json::Value::String)
-                        .unwrap_or(serde_json::Value::Null);
-                }
-                Ok(out)
-            } else {
-                Err("not_found".into())
+            let policy = crate::commands::retention_policy(&conn, p.repo_id.as_deref());
+            serde_json::to_value(policy).map_err(|e| e.to_string())
+        }
+        "retention_policy_set" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                policy: crate::commands::RetentionPolicy,
             }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::retention_policy_set_core(&db, p.repo_id.as_deref(), &p.policy)
+        }
+        "versions_compact" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                doc_id: Option<String>,
+                dry_run: Option<bool>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
+            let report = crate::commands::versions_compact_core(
+                &db,
+                p.repo_id.as_deref(),
+                p.doc_id.as_deref(),
+                p.dry_run.unwrap_or(false),
+            )?;
+            serde_json::to_value(report).map_err(|e| e.to_string())
         }
         "docs_delete" => {
             #[derive(Deserialize)]
@@ -344,14 +459,117 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let conn = db.0.lock();
-            let n = conn
-                .execute(
-                    "UPDATE doc SET is_deleted=1 WHERE id=?1 OR slug=?1",
-                    params![p.doc_id],
-                )
+            crate::commands::docs_delete_core(&db, &p.doc_id)
+        }
+        "docs_trash_list" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
+            let docs = crate::commands::docs_trash_list_core(&db, p.repo_id.as_deref())?;
+            serde_json::to_value(docs).map_err(|e| e.to_string())
+        }
+        "docs_restore_deleted" => {
+            #[derive(Deserialize)]
+            struct P {
+                doc_id: String,
+                slug: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::docs_restore_deleted_core(&db, &p.doc_id, p.slug)
+        }
+        "docs_purge" => {
+            #[derive(Deserialize)]
+            struct P {
+                doc_id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::docs_purge_core(&db, &p.doc_id)
+        }
+        "templates_list" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            let templates = crate::commands::templates_list_core(&db, &p.repo_id)?;
+            serde_json::to_value(templates).map_err(|e| e.to_string())
+        }
+        "docs_create_from_template" => {
+            let p: crate::commands::TemplateCreateRequest =
+                serde_json::from_value(req.params.unwrap_or_default())
+                    .map_err(|e| e.to_string())?;
+            crate::commands::docs_create_from_template_core(&db, p)
+        }
+        "docs_daily" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: String,
+                date: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::docs_daily_core(&db, &p.repo_id, p.date.as_deref())
+        }
+        "folders_children" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: String,
+                path: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            let children =
+                crate::commands::folders_children_core(&db, &p.repo_id, p.path.as_deref().unwrap_or(""))?;
+            serde_json::to_value(children).map_err(|e| e.to_string())
+        }
+        "folders_create" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: String,
+                path: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::folders_create_core(&db, &p.repo_id, &p.path)
+        }
+        "folders_rename" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: String,
+                path: String,
+                name: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::folders_rename_core(&db, &p.repo_id, &p.path, &p.name)
+        }
+        "folders_move" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: String,
+                path: String,
+                new_parent: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::folders_move_core(&db, &p.repo_id, &p.path, &p.new_parent)
+        }
+        "folders_delete" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: String,
+                path: String,
+                recursive: Option<bool>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            Ok(serde_json::json!({"deleted": n>0}))
+            crate::commands::folders_delete_core(&db, &p.repo_id, &p.path, p.recursive.unwrap_or(false))
         }
         "import_docs" => {
             let payload: crate::commands::ImportDocsPayload =
@@ -360,58 +578,99 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
             crate::commands::import_docs_exec(&db, payload)
         }
         "search" => {
+            let p: crate::commands::SearchRequest =
+                serde_json::from_value(req.params.unwrap_or_default())
+                    .map_err(|e| e.to_string())?;
+            let hits = crate::commands::search_core(&db, p)?;
+            serde_json::to_value(hits).map_err(|e| e.to_string())
+        }
+        "search_history" => {
+            let p: crate::commands::HistorySearchRequest =
+                serde_json::from_value(req.params.unwrap_or_default()).map_err(|e| e.to_string())?;
+            let hits = crate::commands::search_history_core(&db, p)?;
+            serde_json::to_value(hits).map_err(|e| e.to_string())
+        }
+        "search_grep" => {
+            // `stream: true` is answered by `search_grep_stream`
+            let p: crate::commands::GrepRequest =
+                serde_json::from_value(req.params.unwrap_or_default()).map_err(|e| e.to_string())?;
+            let report = crate::commands::grep_core(&db, &p, Uuid::new_v4().to_string(), |_| true)?;
+            serde_json::to_value(report).map_err(|e| e.to_string())
+        }
+        "search_faceted" => {
+            let p: crate::commands::FacetedSearchRequest =
+                serde_json::from_value(req.params.unwrap_or_default()).map_err(|e| e.to_string())?;
+            let res = crate::commands::search_faceted_core(&db, p)?;
+            serde_json::to_value(res).map_err(|e| e.to_string())
+        }
+        "saved_search_save" => {
             #[derive(Deserialize)]
             struct P {
-                repo_id: Option<String>,
-                query: String,
+                name: String,
+                params: serde_json::Value,
+                watch: Option<bool>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::saved_search_save_core(&db, &p.name, p.params, p.watch.unwrap_or(false))
+        }
+        "saved_search_list" => {
+            let list = crate::commands::saved_search_list_core(&db)?;
+            serde_json::to_value(list).map_err(|e| e.to_string())
+        }
+        "saved_search_delete" => {
+            #[derive(Deserialize)]
+            struct P {
+                name: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::saved_search_delete_core(&db, &p.name)
+        }
+        "saved_search_run" => {
+            #[derive(Deserialize)]
+            struct P {
+                name: String,
                 limit: Option<i64>,
                 offset: Option<i64>,
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let conn = db.0.lock();
-            let lim = p.limit.unwrap_or(50);
-            let off = p.offset.unwrap_or(0);
-            let primary = String::from("SELECT d.id, d.slug, bm25(doc_fts, 1.2, 0.75) as rank, snippet(doc_fts,1,'<b>','</b>','…',8) as title_snip, snippet(doc_fts,2,'<b>','</b>','…',8) as body_snip FROM doc_fts JOIN doc d ON d.rowid=doc_fts.rowid WHERE doc_fts MATCH ?1 AND (?2 IS NULL OR d.repo_id=?2) ORDER BY rank ASC, d.updated_at DESC LIMIT ?3 OFFSET ?4");
-            let mut out = Vec::new();
-            let mut tried_primary = false;
-            let mut primary_ok = false;
-            if let Ok(mut stmt) = conn.prepare(&primary) {
-                tried_primary = true;
-                let rows = stmt.query_map(params![p.query, p.repo_id, lim, off], |r| {
-                    Ok(serde_json::json!({
-                        "id": r.get::<_, String>(0)?,
-                        "slug": r.get::<_, String>(1)?,
-                        "rank": r.get::<_, f64>(2).unwrap_or(0.0),
-                        "title_snip": r.get::<_, String>(3).unwrap_or_default(),
-                        "body_snip": r.get::<_, String>(4).unwrap_or_default()
-                    }))
-                });
-                if let Ok(rs) = rows {
-                    let mut ok = true;
-                    for r in rs {
-                        if let Ok(v) = r {
-                            out.push(v)
-                        } else {
-                            ok = false;
-                            break;
-                        }
-                    }
-                    primary_ok = ok;
-                }
+            let res = crate::commands::saved_search_run_core(&db, &p.name, p.limit, p.offset)?;
+            serde_json::to_value(res).map_err(|e| e.to_string())
+        }
+        "history_index_set" => {
+            #[derive(Deserialize)]
+            struct P {
+                enabled: bool,
             }
-            if !primary_ok {
-                // Fallback without bm25/snippet to avoid env-specific FTS aux function issues
-                let simple = String::from("SELECT d.id, d.slug, 0.0 as rank, '' as title_snip, '' as body_snip FROM doc_fts JOIN doc d ON d.rowid=doc_fts.rowid WHERE doc_fts MATCH ?1 AND (?2 IS NULL OR d.repo_id=?2) ORDER BY d.updated_at DESC LIMIT ?3 OFFSET ?4");
-                if let Ok(mut stmt2) = conn.prepare(&simple) {
-                    if let Ok(rows2) = stmt2.query_map(params![p.query, p.repo_id, lim, off], |r| Ok(serde_json::json!({
-                        "id": r.get::<_, String>(0)?, "slug": r.get::<_, String>(1)?, "rank": r.get::<_, f64>(2).unwrap_or(0.0), "title_snip": r.get::<_, String>(3).unwrap_or_default(), "body_snip": r.get::<_, String>(4).unwrap_or_default()
-                    }))) {
-                        for r in rows2 { if let Ok(v) = r { out.push(v) } }
-                    }
-                }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::history_index_set_core(&db, p.enabled)
+        }
+        "search_semantic" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                query: String,
+                limit: Option<usize>,
             }
-            Ok(serde_json::json!(out))
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            let hits = crate::ai::embed::semantic_search(&db, p.repo_id.as_deref(), &p.query, p.limit.unwrap_or(20))?;
+            serde_json::to_value(hits).map_err(|e| e.to_string())
+        }
+        "embeddings_reindex" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::ai::embed::reindex_all(&db, p.repo_id.as_deref())
+        }
+        "embeddings_status" => {
+            serde_json::to_value(crate::ai::embed::refresh_status(&db)).map_err(|e| e.to_string())
         }
         "graph_backlinks" => {
             #[derive(Deserialize)]
@@ -420,14 +679,8 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let conn = db.0.lock();
-            let mut stmt = conn.prepare("SELECT d.id, d.slug, d.title FROM link l JOIN doc d ON d.id = l.from_doc_id WHERE l.to_doc_id = ?1 ORDER BY d.updated_at DESC").map_err(|e| e.to_string())?;
-            let rows = stmt.query_map(params![p.doc_id], |r| Ok(serde_json::json!({"id": r.get::<_, String>(0)?, "slug": r.get::<_, String>(1)?, "title": r.get::<_, String>(2)?}))).map_err(|e| e.to_string())?;
-            let mut out = Vec::new();
-            for r in rows {
-                out.push(r.map_err(|e| e.to_string())?)
-            }
-            Ok(serde_json::json!(out))
+            let rows = crate::commands::graph_backlinks_core(&db, &p.doc_id)?;
+            serde_json::to_value(rows).map_err(|e| e.to_string())
         }
         "graph_neighbors" => {
             #[derive(Deserialize)]
@@ -437,14 +690,8 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let conn = db.0.lock();
-            let mut stmt = conn.prepare("SELECT DISTINCT d.id, d.slug, d.title FROM (SELECT l2.from_doc_id AS neighbor_id FROM link l JOIN link l2 ON l.to_doc_id = l2.to_doc_id WHERE l.from_doc_id = ?1 AND l2.from_doc_id != ?1 UNION SELECT to_doc_id FROM link WHERE from_doc_id = ?1 AND to_doc_id IS NOT NULL) n JOIN doc d ON d.id = n.neighbor_id").map_err(|e| e.to_string())?;
-            let rows = stmt.query_map(params![p.doc_id], |r| Ok(serde_json::json!({"id": r.get::<_, String>(0)?, "slug": r.get::<_, String>(1)?, "title": r.get::<_, String>(2)?}))).map_err(|e| e.to_string())?;
-            let mut out = Vec::new();
-            for r in rows {
-                out.push(r.map_err(|e| e.to_string())?)
-            }
-            Ok(serde_json::json!(out))
+            let rows = crate::commands::graph_neighbors_core(&db, &p.doc_id)?;
+            serde_json::to_value(rows).map_err(|e| e.to_string())
         }
         "graph_related" => {
             #[derive(Deserialize)]
@@ -485,47 +732,213 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
             Ok(out)
         }
         "ai_run" => {
+            let run: crate::commands::AiRunRequest = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_run_core(&db, run)
+        }
+        "ai_run_cancel" => {
             #[derive(Deserialize)]
             struct P {
-                provider: String,
-                doc_id: String,
-                anchor_id: Option<String>,
-                prompt: String,
+                trace_id: String,
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let res = crate::commands::AiRunRequest {
-                provider: p.provider,
-                doc_id: p.doc_id,
-                anchor_id: p.anchor_id,
-                line: None,
-                prompt: p.prompt,
-            };
-            crate::commands::ai_run_core(&db, res)
+            Ok(crate::commands::ai_run_cancel_core(&p.trace_id))
         }
-        "anchors_list" => {
+        "ai_chat_send" => {
+            let chat: crate::commands::AiChatRequest = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_chat_send_core(&db, chat)
+        }
+        "ai_conversations_list" => {
             #[derive(Deserialize)]
             struct P {
-                doc_id: String,
+                doc_id: Option<String>,
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
+            let rows = crate::commands::ai_conversations_list_core(&db, p.doc_id.as_deref(), p.repo_id.as_deref())?;
+            serde_json::to_value(rows).map_err(|e| e.to_string())
+        }
+        "ai_conversations_get" => {
+            #[derive(Deserialize)]
+            struct P {
+                id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_conversations_get_core(&db, &p.id)
+        }
+        "ai_conversations_fork" => {
+            #[derive(Deserialize)]
+            struct P {
+                id: String,
+                upto_seq: Option<i64>,
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
+            crate::commands::ai_conversations_fork_core(&db, &p.id, p.upto_seq)
+        }
+        "ai_conversations_delete" => {
+            #[derive(Deserialize)]
+            struct P {
+                id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_conversations_delete_core(&db, &p.id)
+        }
+        "ai_context_get" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
             let conn = db.0.lock();
-            let mut stmt = conn.prepare("SELECT entity_id, COALESCE(json_extract(meta,'$.line'),0), created_at FROM provenance WHERE entity_type='anchor' AND json_extract(meta,'$.doc_id')=?1 ORDER BY created_at DESC").map_err(|e| e.to_string())?;
-            let rows = stmt
-                .query_map(params![p.doc_id], |r| {
-                    Ok(serde_json::json!({
-                        "id": r.get::<_, String>(0)?,
-                        "line": r.get::<_, i64>(1)?,
-                        "created_at": r.get::<_, String>(2)?,
-                    }))
-                })
+            let settings = crate::ai::context::settings(&conn, p.repo_id.as_deref());
+            serde_json::to_value(settings).map_err(|e| e.to_string())
+        }
+        "ai_context_set" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                settings: crate::ai::context::ContextSettings,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let mut out = Vec::new();
-            for r in rows {
-                out.push(r.map_err(|e| e.to_string())?)
+            crate::commands::ai_context_set_core(&db, p.repo_id.as_deref(), &p.settings)
+        }
+        "ai_templates_list" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
             }
-            Ok(serde_json::json!(out))
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
+            let templates = crate::commands::ai_templates_list_core(&db, p.repo_id.as_deref())?;
+            serde_json::to_value(templates).map_err(|e| e.to_string())
+        }
+        "ai_templates_upsert" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                template: crate::commands::PromptTemplate,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_templates_upsert_core(&db, p.repo_id.as_deref(), &p.template)
+        }
+        "ai_templates_delete" => {
+            #[derive(Deserialize)]
+            struct P {
+                name: String,
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_templates_delete_core(&db, &p.name, p.repo_id.as_deref())
+        }
+        "ai_templates_export" => {
+            #[derive(Deserialize)]
+            struct P {
+                dir: String,
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_templates_export_core(&db, std::path::Path::new(&p.dir), p.repo_id.as_deref())
+        }
+        "ai_templates_import" => {
+            #[derive(Deserialize)]
+            struct P {
+                path: String,
+                repo_id: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_templates_import_core(&db, std::path::Path::new(&p.path), p.repo_id.as_deref())
+        }
+        "ai_limits_get" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                provider: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
+            let limits = crate::commands::ai_limits_get_core(&db, p.repo_id.as_deref(), p.provider.as_deref())?;
+            serde_json::to_value(limits).map_err(|e| e.to_string())
+        }
+        "ai_limits_set" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                provider: Option<String>,
+                #[serde(default)]
+                limits: crate::ai::limits::AiLimits,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_limits_set_core(&db, p.repo_id.as_deref(), p.provider.as_deref(), &p.limits)
+        }
+        "ai_limits_status" => {
+            #[derive(Deserialize)]
+            struct P {
+                repo_id: Option<String>,
+                provider: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_limits_status_core(&db, p.repo_id.as_deref(), p.provider.as_deref())
+        }
+        "ai_usage_report" => {
+            let q: crate::commands::UsageQuery =
+                serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                    .map_err(|e| e.to_string())?;
+            crate::commands::ai_usage_report_core(&db, q)
+        }
+        "ai_provider_config_get" => {
+            #[derive(Deserialize)]
+            struct P {
+                name: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_provider_config_get_core(&db, &p.name)
+        }
+        "ai_provider_config_set" => {
+            #[derive(Deserialize)]
+            struct P {
+                name: String,
+                config: serde_json::Value,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::ai_provider_config_set_core(&db, &p.name, p.config)
+        }
+        "anchors_upsert" => {
+            #[derive(Deserialize)]
+            struct P {
+                doc_id: String,
+                anchor_id: String,
+                line: i64,
+                line_end: Option<i64>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::anchors_upsert_core(&db, &p.doc_id, &p.anchor_id, p.line, p.line_end)
+        }
+        "anchors_list" => {
+            #[derive(Deserialize)]
+            struct P {
+                doc_id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            let anchors = crate::commands::anchors_list_core(&db, &p.doc_id)?;
+            serde_json::to_value(anchors).map_err(|e| e.to_string())
         }
         "anchors_delete" => {
             #[derive(Deserialize)]
@@ -534,14 +947,114 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let conn = db.0.lock();
-            let n = conn
-                .execute(
-                    "DELETE FROM provenance WHERE entity_type='anchor' AND entity_id=?1",
-                    params![p.anchor_id],
-                )
+            crate::commands::anchors_delete_core(&db, &p.anchor_id)
+        }
+        "comments_threads_list" => {
+            #[derive(Deserialize)]
+            struct P {
+                doc_id: String,
+                include_resolved: Option<bool>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            Ok(serde_json::json!({"deleted": n>0}))
+            let threads = crate::commands::comments_threads_list_core(
+                &db,
+                &p.doc_id,
+                p.include_resolved.unwrap_or(true),
+            )?;
+            serde_json::to_value(threads).map_err(|e| e.to_string())
+        }
+        "comments_thread_create" => {
+            let p: crate::commands::ThreadCreate =
+                serde_json::from_value(req.params.unwrap_or_default())
+                    .map_err(|e| e.to_string())?;
+            crate::commands::comments_thread_create_core(&db, p)
+        }
+        "comments_reply" => {
+            #[derive(Deserialize)]
+            struct P {
+                thread_id: String,
+                body: String,
+                author: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::comments_reply_core(&db, &p.thread_id, &p.body, p.author)
+        }
+        "comments_resolve" => {
+            #[derive(Deserialize)]
+            struct P {
+                thread_id: String,
+                resolved: Option<bool>,
+                author: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::comments_resolve_core(
+                &db,
+                &p.thread_id,
+                p.resolved.unwrap_or(true),
+                p.author,
+            )
+        }
+        "comments_thread_delete" => {
+            #[derive(Deserialize)]
+            struct P {
+                thread_id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::comments_thread_delete_core(&db, &p.thread_id)
+        }
+        "suggestions_create" => {
+            let p: crate::commands::SuggestionCreate =
+                serde_json::from_value(req.params.unwrap_or_default())
+                    .map_err(|e| e.to_string())?;
+            crate::commands::suggestions_create_core(&db, p)
+        }
+        "suggestions_list" => {
+            #[derive(Deserialize)]
+            struct P {
+                doc_id: Option<String>,
+                status: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or(serde_json::json!({})))
+                .map_err(|e| e.to_string())?;
+            let list = crate::commands::suggestions_list_core(
+                &db,
+                p.doc_id.as_deref(),
+                p.status.as_deref(),
+            )?;
+            serde_json::to_value(list).map_err(|e| e.to_string())
+        }
+        "suggestions_preview" => {
+            #[derive(Deserialize)]
+            struct P {
+                suggestion_id: String,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::suggestions_preview_core(&db, &p.suggestion_id)
+        }
+        "suggestions_accept" => {
+            #[derive(Deserialize)]
+            struct P {
+                suggestion_id: String,
+                message: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::suggestions_accept_core(&db, &p.suggestion_id, p.message)
+        }
+        "suggestions_reject" => {
+            #[derive(Deserialize)]
+            struct P {
+                suggestion_id: String,
+                reason: Option<String>,
+            }
+            let p: P = serde_json::from_value(req.params.unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            crate::commands::suggestions_reject_core(&db, &p.suggestion_id, p.reason)
         }
         "fts_stats" => {
             let conn = db.0.lock();
@@ -575,8 +1088,13 @@ async fn route(req: RpcReq, db: Arc<Db>) -> Result<serde_json::Value, String> {
             }
             let p: P = serde_json::from_value(req.params.unwrap_or_default())
                 .map_err(|e| e.to_string())?;
-            let added = crate::scan::scan_one_file(&db, &p.repo_path, &p.file_path)?;
-            Ok(serde_json::json!({"changed": added}))
+            let (added, collision) = crate::scan::scan_one_file(&db, &p.repo_path, &p.file_path)?;
+            let changes = if added {
+                crate::commands::saved_searches_check(&db)?
+            } else {
+                vec![]
+            };
+            Ok(serde_json::json!({"changed": added, "collision": collision, "saved_search_changes": changes}))
         }
         m => Err(format!("unknown method: {}", m)),
     }
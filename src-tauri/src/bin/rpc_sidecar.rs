@@ -13,12 +13,21 @@ mod commands;
 mod scan;
 #[path = "../graph/mod.rs"]
 mod graph;
+#[path = "../blob/mod.rs"]
+mod blob;
+#[path = "../fts/mod.rs"]
+mod fts;
+#[path = "../merge/mod.rs"]
+mod merge;
 #[path = "../secrets.rs"]
 mod secrets;
 #[path = "../ai/mod.rs"]
 mod ai;
 #[path = "../plugins/mod.rs"]
 mod plugins;
+#[cfg(test)]
+#[path = "../test_util.rs"]
+mod test_util;
 
 use std::env;
 use std::path::PathBuf;
@@ -32,6 +41,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     });
     let port: u16 = env::var("AE_RPC_PORT").ok().and_then(|s| s.parse().ok()).unwrap_or(35678);
     let db = std::sync::Arc::new(db::open_db(&db_path).expect("open_db"));
+    ai::embed::start_refresh_worker(&db);
+    if let Err(e) = commands::purge_expired_trash(&db) {
+        eprintln!("[rpc_sidecar] trash purge failed: {}", e);
+    }
     eprintln!("[rpc_sidecar] DB: {}  Port: {}", db_path.display(), port);
     api::start_api(db, port).await.expect("start_api");
     Ok(())
@@ -0,0 +1,244 @@
+//! Content-addressed blob storage for doc versions and assets.
+//!
+//! `doc_blob.id` is the blake3 hex of the raw (uncompressed) bytes, so identical content is
+//! stored once. `encoding` describes the content (`utf8`, `binary`, ...) and carries a `+zstd`
+//! suffix when the stored bytes are compressed; readers go through `get`/`get_text`.
+//! Compression is opt-in via the `blob_compression` app setting (`"zstd"` or
+//! `{ "codec": "zstd", "level": 3 }`).
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+const ZSTD_SUFFIX: &str = "+zstd";
+/// Bodies smaller than this are stored as-is; compression rarely pays off below it.
+const MIN_COMPRESS_BYTES: usize = 256;
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+pub fn hash_id(content: &[u8]) -> String {
+    blake3::hash(content).to_hex().to_string()
+}
+
+/// zstd level from the `blob_compression` setting, or None when compression is off.
+fn compression_level(conn: &Connection) -> Option<i32> {
+    let v: serde_json::Value = conn
+        .query_row("SELECT value FROM app_setting WHERE key='blob_compression'", [], |r| {
+            r.get::<_, String>(0)
+        })
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())?;
+    match &v {
+        serde_json::Value::String(s) if s == "zstd" => Some(DEFAULT_ZSTD_LEVEL),
+        serde_json::Value::Object(o) if o.get("codec").and_then(|c| c.as_str()) == Some("zstd") => Some(
+            o.get("level")
+                .and_then(|l| l.as_i64())
+                .map(|l| l as i32)
+                .unwrap_or(DEFAULT_ZSTD_LEVEL),
+        ),
+        _ => None,
+    }
+}
+
+/// Store `content` (deduplicated by hash) and return its blob id.
+pub fn put(conn: &Connection, content: &[u8], encoding: &str, mime: &str) -> Result<String, String> {
+    let id = hash_id(content);
+    let exists: bool = conn
+        .query_row("SELECT EXISTS(SELECT 1 FROM doc_blob WHERE id=?1)", params![id], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    if exists {
+        return Ok(id);
+    }
+    let (stored, encoding) = encode(conn, content, encoding)?;
+    conn.execute(
+        "INSERT INTO doc_blob(id,content,encoding,mime,size_bytes) VALUES(?1,?2,?3,?4,?5)",
+        params![id, stored, encoding, mime, content.len() as i64],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+/// Store a markdown body.
+pub fn put_text(conn: &Connection, body: &str) -> Result<String, String> {
+    put(conn, body.as_bytes(), "utf8", "text/markdown")
+}
+
+fn encode(conn: &Connection, content: &[u8], encoding: &str) -> Result<(Vec<u8>, String), String> {
+    if let Some(level) = compression_level(conn) {
+        if content.len() >= MIN_COMPRESS_BYTES {
+            let packed = zstd::encode_all(content, level).map_err(|e| e.to_string())?;
+            if packed.len() < content.len() {
+                return Ok((packed, format!("{}{}", encoding, ZSTD_SUFFIX)));
+            }
+        }
+    }
+    Ok((content.to_vec(), encoding.to_string()))
+}
+
+/// Raw bytes from stored content and its `encoding` column.
+pub fn decode(stored: Vec<u8>, encoding: &str) -> Result<Vec<u8>, String> {
+    if encoding.ends_with(ZSTD_SUFFIX) {
+        zstd::decode_all(stored.as_slice()).map_err(|e| e.to_string())
+    } else {
+        Ok(stored)
+    }
+}
+
+/// Content encoding without the compression suffix (what exports report).
+pub fn content_encoding(encoding: &str) -> &str {
+    encoding.strip_suffix(ZSTD_SUFFIX).unwrap_or(encoding)
+}
+
+/// Decoded body text from stored content and encoding.
+pub fn decode_text(stored: Vec<u8>, encoding: &str) -> Result<String, String> {
+    String::from_utf8(decode(stored, encoding)?).map_err(|e| e.to_string())
+}
+
+pub fn get(conn: &Connection, id: &str) -> Result<Vec<u8>, String> {
+    let row: Option<(Vec<u8>, String)> = conn
+        .query_row(
+            "SELECT content, COALESCE(encoding,'utf8') FROM doc_blob WHERE id=?1",
+            params![id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let (stored, encoding) = row.ok_or("not_found")?;
+    decode(stored, &encoding)
+}
+
+pub fn get_text(conn: &Connection, id: &str) -> Result<String, String> {
+    String::from_utf8(get(conn, id)?).map_err(|e| e.to_string())
+}
+
+/// `(doc_id, body)` of the current versions of live (not trashed) docs, optionally for one repo.
+pub fn current_bodies(conn: &Connection, repo_id: Option<&str>) -> Result<Vec<(String, String)>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT d.id, b.content, COALESCE(b.encoding,'utf8') FROM doc d \
+             JOIN doc_version v ON v.id=d.current_version_id JOIN doc_blob b ON b.id=v.blob_id \
+             WHERE d.is_deleted=0 AND (?1 IS NULL OR d.repo_id=?1)",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![repo_id], |r| {
+            Ok((r.get::<_, String>(0)?, r.get::<_, Vec<u8>>(1)?, r.get::<_, String>(2)?))
+        })
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    for r in rows {
+        let (id, stored, encoding) = r.map_err(|e| e.to_string())?;
+        out.push((id, decode_text(stored, &encoding)?));
+    }
+    Ok(out)
+}
+
+/// Delete blobs no version or asset references. Returns `(deleted, bytes_freed)` where
+/// bytes are the stored (possibly compressed) sizes.
+pub fn gc(conn: &Connection) -> Result<(usize, i64), String> {
+    sweep(conn, None)
+}
+
+/// `gc` limited to `ids`, e.g. the blobs of versions just deleted.
+pub fn gc_ids(conn: &Connection, ids: &[String]) -> Result<(usize, i64), String> {
+    sweep(conn, Some(serde_json::json!(ids).to_string()))
+}
+
+fn sweep(conn: &Connection, ids: Option<String>) -> Result<(usize, i64), String> {
+    const UNREFERENCED: &str = "(?1 IS NULL OR id IN (SELECT value FROM json_each(?1))) \
+         AND id NOT IN (SELECT blob_id FROM doc_version) \
+         AND id NOT IN (SELECT blob_id FROM doc_asset)";
+    let bytes: i64 = conn
+        .query_row(
+            &format!("SELECT COALESCE(SUM(length(content)),0) FROM doc_blob WHERE {}", UNREFERENCED),
+            params![ids],
+            |r| r.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    let deleted = conn
+        .execute(&format!("DELETE FROM doc_blob WHERE {}", UNREFERENCED), params![ids])
+        .map_err(|e| e.to_string())?;
+    Ok((deleted, bytes))
+}
+
+/// Re-key blobs written before content addressing (random UUID ids) to their hash,
+/// merging duplicates. Returns the number of rows re-keyed.
+pub fn migrate_to_content_ids(conn: &Connection) -> Result<usize, String> {
+    let legacy: Vec<(String, Vec<u8>, String)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, CAST(content AS BLOB), COALESCE(encoding,'utf8') FROM doc_blob WHERE length(id) != 64",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    for (old_id, stored, encoding) in &legacy {
+        let new_id = hash_id(&decode(stored.clone(), encoding)?);
+        // Copy under the new id unless the same content already exists
+        conn.execute(
+            "INSERT OR IGNORE INTO doc_blob(id,content,encoding,mime,size_bytes) \
+             SELECT ?2,content,encoding,mime,size_bytes FROM doc_blob WHERE id=?1",
+            params![old_id, new_id],
+        )
+        .map_err(|e| e.to_string())?;
+        conn.execute("UPDATE doc_version SET blob_id=?2 WHERE blob_id=?1", params![old_id, new_id])
+            .map_err(|e| e.to_string())?;
+        conn.execute("UPDATE doc_asset SET blob_id=?2 WHERE blob_id=?1", params![old_id, new_id])
+            .map_err(|e| e.to_string())?;
+        conn.execute("DELETE FROM doc_blob WHERE id=?1", params![old_id])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(legacy.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{db_with_docs, temp_db};
+
+    #[test]
+    fn test_put_dedupes_and_compresses() {
+        let db = temp_db("blob-test");
+        let conn = db.0.lock();
+        let a = put_text(&conn, "same body").unwrap();
+        assert_eq!(a, put_text(&conn, "same body").unwrap());
+        assert_eq!(a, hash_id(b"same body"));
+        let n: i64 = conn.query_row("SELECT COUNT(*) FROM doc_blob", [], |r| r.get(0)).unwrap();
+        assert_eq!(n, 1);
+
+        conn.execute("INSERT INTO app_setting(key,value) VALUES('blob_compression','\"zstd\"')", []).unwrap();
+        let big = "lorem ipsum dolor sit amet\n".repeat(100);
+        let id = put_text(&conn, &big).unwrap();
+        let (encoding, stored): (String, i64) = conn
+            .query_row("SELECT encoding, length(content) FROM doc_blob WHERE id=?1", params![id], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(encoding, "utf8+zstd");
+        assert!((stored as usize) < big.len());
+        assert_eq!(get_text(&conn, &id).unwrap(), big);
+        assert_eq!(content_encoding(&encoding), "utf8");
+    }
+
+    #[test]
+    fn test_migrate_and_gc() {
+        let db = db_with_docs("blob-migrate", &[("d", "n")]);
+        let conn = db.0.lock();
+        for (blob, version) in [("u1", "v1"), ("u2", "v2")] {
+            conn.execute("INSERT INTO doc_blob(id,content,size_bytes) VALUES(?1,'dup',3)", params![blob]).unwrap();
+            conn.execute("INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES(?1,'d',?2,'d:h')", params![version, blob]).unwrap();
+        }
+        conn.execute("INSERT INTO doc_blob(id,content,size_bytes) VALUES('orphan','xyz',3)", []).unwrap();
+        assert_eq!(migrate_to_content_ids(&conn).unwrap(), 3);
+        let ids: Vec<String> = conn
+            .prepare("SELECT DISTINCT blob_id FROM doc_version")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(ids, vec![hash_id(b"dup")]);
+        assert_eq!(gc(&conn).unwrap(), (1, 3));
+        assert_eq!(gc(&conn).unwrap(), (0, 0));
+    }
+}
@@ -1,16 +1,284 @@
 //! Document anchor commands
+//!
+//! Anchors pin a line range of a doc. Besides the position they keep the anchored text, a few
+//! lines of context on either side and the version they were last placed against, so every
+//! write can re-place them (`reanchor_doc`): unchanged lines follow the diff from that version,
+//! otherwise the text is searched for exactly, then by its unchanged context and, when the body
+//! differs from that version, fuzzily near where it is expected. Anchors that cannot be placed
+//! keep their last position with `status = 'orphaned'` and are retried on later writes.
 
 use crate::db::Db;
-use rusqlite::params;
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::Serialize;
+use similar::{capture_diff_slices, Algorithm, DiffOp, TextDiff};
 use tauri::State;
-use uuid::Uuid;
+
+/// Lines of context stored before and after the anchored range.
+const CONTEXT_LINES: usize = 2;
+/// Minimum similarity for a fuzzy placement.
+const FUZZY_MIN_SCORE: f32 = 0.6;
+/// Most positions the fuzzy pass scores, nearest to the expected one first.
+const FUZZY_MAX_WINDOWS: usize = 200;
 
 #[derive(Serialize)]
 pub struct AnchorItem {
     pub id: String,
+    pub doc_id: String,
     pub line: i64,
+    pub line_end: i64,
+    pub text: String,
+    pub status: String,
+    pub version_id: Option<String>,
     pub created_at: String,
+    pub updated_at: String,
+}
+
+struct Stored {
+    id: String,
+    line_start: usize,
+    line_end: usize,
+    text: String,
+    prefix: String,
+    suffix: String,
+    version_id: Option<String>,
+}
+
+/// Body and version id of the current version of a live doc.
+pub(crate) fn current_body(conn: &Connection, doc_id: &str) -> Result<(String, Option<String>), String> {
+    let current: Option<String> = conn
+        .query_row(
+            "SELECT current_version_id FROM doc WHERE id=?1 AND is_deleted=0",
+            params![doc_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?;
+    let body = match current.as_deref() {
+        Some(v) => super::version_body(conn, v)?,
+        None => String::new(),
+    };
+    Ok((body, current))
+}
+
+/// Text of 1-based lines `start..=end` plus the context lines around them.
+fn capture(lines: &[&str], start: usize, end: usize) -> (String, String, String) {
+    let s = start.saturating_sub(1).min(lines.len());
+    let e = end.min(lines.len()).max(s);
+    let pre = s.saturating_sub(CONTEXT_LINES);
+    let post = (e + CONTEXT_LINES).min(lines.len());
+    (lines[s..e].join("\n"), lines[pre..s].join("\n"), lines[e..post].join("\n"))
+}
+
+fn similarity(a: &str, b: &str) -> f32 {
+    if a == b {
+        return 1.0;
+    }
+    TextDiff::from_chars(a, b).ratio()
+}
+
+/// Upper bound of `similarity(a, b)`, from the lengths alone.
+fn max_similarity(a: &str, b: &str) -> f32 {
+    let (la, lb) = (a.chars().count(), b.chars().count());
+    2.0 * la.min(lb) as f32 / (la + lb).max(1) as f32
+}
+
+/// Where old 1-based `line` ended up in `new`, following unchanged lines of the diff.
+fn map_line(old: &[&str], new: &[&str], line: usize) -> Option<usize> {
+    let idx = line.checked_sub(1)?;
+    capture_diff_slices(Algorithm::Myers, old, new)
+        .into_iter()
+        .find_map(|op| match op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } if (old_index..old_index + len).contains(&idx) => Some(new_index + (idx - old_index) + 1),
+            _ => None,
+        })
+}
+
+/// New `(line_start, line_end)` for an anchor in `new`, or None when it can't be found.
+/// `old` is the body of the version the anchor was placed against, when still available.
+fn place(anchor: &Stored, old: Option<&[&str]>, new: &[&str]) -> Option<(usize, usize)> {
+    let span = anchor.line_end.saturating_sub(anchor.line_start) + 1;
+    // Unchanged range: follow the diff
+    if let Some(old) = old {
+        if let (Some(s), Some(e)) = (
+            map_line(old, new, anchor.line_start),
+            map_line(old, new, anchor.line_end),
+        ) {
+            if e + 1 == s + span && new[s - 1..e].join("\n") == anchor.text {
+                return Some((s, e));
+            }
+        }
+    }
+    let expected = old
+        .and_then(|o| map_line(o, new, anchor.line_start))
+        .unwrap_or(anchor.line_start);
+    let windows = new.len().saturating_sub(span) + 1;
+    if new.len() < span || anchor.text.trim().is_empty() {
+        return None;
+    }
+    // Exact text, nearest to where it is expected
+    let exact = (1..=windows)
+        .filter(|&s| new[s - 1..s - 1 + span].join("\n") == anchor.text)
+        .min_by_key(|&s| s.abs_diff(expected));
+    if let Some(s) = exact {
+        return Some((s, s + span - 1));
+    }
+    // Edited in place: the same lines still surround it
+    if !anchor.prefix.is_empty() || !anchor.suffix.is_empty() {
+        let context = (1..=windows)
+            .filter(|&s| {
+                let (_, prefix, suffix) = capture(new, s, s + span - 1);
+                prefix == anchor.prefix && suffix == anchor.suffix
+            })
+            .min_by_key(|&s| s.abs_diff(expected));
+        if let Some(s) = context {
+            return Some((s, s + span - 1));
+        }
+    }
+    // Nothing changed since it was last placed (or given up on): fuzzy won't find it either
+    if old.is_some_and(|o| o == new) {
+        return None;
+    }
+    // Fuzzy: text similarity weighted with context, small penalty for distance
+    let mut starts: Vec<usize> = (1..=windows).collect();
+    starts.sort_by_key(|&s| s.abs_diff(expected));
+    starts.truncate(FUZZY_MAX_WINDOWS);
+    let mut best: Option<(f32, usize)> = None;
+    for s in starts {
+        let (text, prefix, suffix) = capture(new, s, s + span - 1);
+        if max_similarity(&anchor.text, &text) < FUZZY_MIN_SCORE {
+            continue;
+        }
+        let text_score = similarity(&anchor.text, &text);
+        if text_score < FUZZY_MIN_SCORE {
+            continue;
+        }
+        let context = (similarity(&anchor.prefix, &prefix) + similarity(&anchor.suffix, &suffix)) / 2.0;
+        let distance = s.abs_diff(expected) as f32 / new.len().max(1) as f32;
+        let score = text_score * 0.7 + context * 0.3 - distance * 0.05;
+        if best.is_none_or(|(b, _)| score > b) {
+            best = Some((score, s));
+        }
+    }
+    best.map(|(_, s)| (s, s + span - 1))
+}
+
+/// Re-place every anchor of a doc against its new body (written as `version_id`).
+pub(crate) fn reanchor_doc(conn: &Connection, doc_id: &str, version_id: &str, body: &str) -> Result<usize, String> {
+    let anchors: Vec<Stored> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, line_start, line_end, text, prefix, suffix, version_id FROM anchor \
+                 WHERE doc_id=?1 AND (version_id IS NULL OR version_id != ?2)",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![doc_id, version_id], |r| {
+                Ok(Stored {
+                    id: r.get(0)?,
+                    line_start: r.get::<_, i64>(1)?.max(1) as usize,
+                    line_end: r.get::<_, i64>(2)?.max(1) as usize,
+                    text: r.get(3)?,
+                    prefix: r.get(4)?,
+                    suffix: r.get(5)?,
+                    version_id: r.get(6)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    let new: Vec<&str> = body.lines().collect();
+    let mut old_bodies = std::collections::HashMap::new();
+    for a in &anchors {
+        let old = match &a.version_id {
+            Some(v) => old_bodies
+                .entry(v.clone())
+                .or_insert_with(|| super::version_body(conn, v).ok())
+                .as_deref(),
+            None => None,
+        };
+        let old_lines: Option<Vec<&str>> = old.map(|o| o.lines().collect());
+        match place(a, old_lines.as_deref(), &new) {
+            Some((s, e)) => {
+                let (text, prefix, suffix) = capture(&new, s, e);
+                conn.execute(
+                    "UPDATE anchor SET line_start=?2, line_end=?3, text=?4, prefix=?5, suffix=?6, version_id=?7, \
+                     status='ok', updated_at=datetime('now') WHERE id=?1",
+                    params![a.id, s as i64, e as i64, text, prefix, suffix, version_id],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            None => {
+                conn.execute(
+                    "UPDATE anchor SET status='orphaned', updated_at=datetime('now') WHERE id=?1",
+                    params![a.id],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    Ok(anchors.len())
+}
+
+/// Current start line of an anchor, unless it is orphaned.
+pub(crate) fn anchor_line(conn: &Connection, anchor_id: &str) -> Option<usize> {
+    conn.query_row(
+        "SELECT line_start FROM anchor WHERE id=?1 AND status != 'orphaned'",
+        params![anchor_id],
+        |r| r.get::<_, i64>(0),
+    )
+    .ok()
+    .map(|l| l.max(1) as usize)
+}
+
+/// Move anchors kept as `provenance` rows (line only) into the anchor table.
+pub(crate) fn migrate_provenance_anchors(conn: &Connection) -> Result<usize, String> {
+    let legacy: Vec<(String, String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT p.entity_id, json_extract(p.meta,'$.doc_id'), COALESCE(json_extract(p.meta,'$.line'),1) \
+                 FROM provenance p JOIN doc d ON d.id=json_extract(p.meta,'$.doc_id') WHERE p.entity_type='anchor'",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    for (id, doc_id, line) in &legacy {
+        let (body, version) = current_body(conn, doc_id).unwrap_or_default();
+        write_anchor(conn, id, doc_id, &body, version.as_deref(), *line as usize, *line as usize)?;
+    }
+    conn.execute("DELETE FROM provenance WHERE entity_type='anchor'", [])
+        .map_err(|e| e.to_string())?;
+    Ok(legacy.len())
+}
+
+pub(crate) fn write_anchor(
+    conn: &Connection,
+    anchor_id: &str,
+    doc_id: &str,
+    body: &str,
+    version_id: Option<&str>,
+    line_start: usize,
+    line_end: usize,
+) -> Result<(), String> {
+    let lines: Vec<&str> = body.lines().collect();
+    let (text, prefix, suffix) = capture(&lines, line_start, line_end);
+    conn.execute(
+        "INSERT INTO anchor(id,doc_id,version_id,line_start,line_end,text,prefix,suffix,status) \
+         VALUES(?1,?2,?3,?4,?5,?6,?7,?8,'ok') \
+         ON CONFLICT(id) DO UPDATE SET doc_id=excluded.doc_id, version_id=excluded.version_id, \
+         line_start=excluded.line_start, line_end=excluded.line_end, text=excluded.text, \
+         prefix=excluded.prefix, suffix=excluded.suffix, status='ok', updated_at=datetime('now')",
+        params![anchor_id, doc_id, version_id, line_start as i64, line_end as i64, text, prefix, suffix],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
 }
 
 #[tauri::command]
@@ -18,17 +286,44 @@ pub async fn anchors_upsert(
     doc_id: String,
     anchor_id: String,
     line: i64,
+    line_end: Option<i64>,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<serde_json::Value, String> {
+    anchors_upsert_core(&db, &doc_id, &anchor_id, line, line_end)
+}
+
+/// Create or re-place an anchor on lines `line..=line_end` of the current version. A
+/// `line_end` past the end is clamped to the last line; `line` past it is `invalid_range`.
+pub fn anchors_upsert_core(
+    db: &Db,
+    doc_id: &str,
+    anchor_id: &str,
+    line: i64,
+    line_end: Option<i64>,
+) -> Result<serde_json::Value, String> {
+    let line_end = line_end.unwrap_or(line);
+    if line < 1 || line_end < line {
+        return Err("invalid_range".into());
+    }
     let conn = db.0.lock();
-    let id = Uuid::new_v4().to_string();
-    let meta = serde_json::json!({"doc_id": doc_id, "line": line});
-    conn.execute(
-        "INSERT INTO provenance(id,entity_type,entity_id,source,meta) VALUES(?, 'anchor', ?, 'ui', ?)",
-        params![id, anchor_id, meta.to_string()],
-    )
-    .map_err(|e| e.to_string())?;
-    Ok(serde_json::json!({"ok": true}))
+    let doc_id: String = conn
+        .query_row(
+            "SELECT id FROM doc WHERE (id=?1 OR slug=?1) AND is_deleted=0 LIMIT 1",
+            params![doc_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?;
+    let (body, version) = current_body(&conn, &doc_id)?;
+    // An empty doc still has its first line
+    let last = body.lines().count().max(1);
+    if line as usize > last {
+        return Err("invalid_range".into());
+    }
+    let line_end = (line_end as usize).min(last);
+    write_anchor(&conn, anchor_id, &doc_id, &body, version.as_deref(), line as usize, line_end)?;
+    Ok(serde_json::json!({"ok": true, "line_end": line_end}))
 }
 
 #[tauri::command]
@@ -36,20 +331,30 @@ pub async fn anchors_list(
     doc_id: String,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<Vec<AnchorItem>, String> {
+    anchors_list_core(&db, &doc_id)
+}
+
+pub fn anchors_list_core(db: &Db, doc_id: &str) -> Result<Vec<AnchorItem>, String> {
     let conn = db.0.lock();
     let mut stmt = conn
         .prepare(
-            "SELECT entity_id, COALESCE(json_extract(meta,'$.line'), 0), created_at \
-         FROM provenance WHERE entity_type='anchor' AND json_extract(meta,'$.doc_id')=?1 \
-         ORDER BY created_at DESC",
+            "SELECT a.id, a.doc_id, a.line_start, a.line_end, a.text, a.status, a.version_id, a.created_at, a.updated_at \
+             FROM anchor a JOIN doc d ON d.id=a.doc_id WHERE (d.id=?1 OR d.slug=?1) AND d.is_deleted=0 \
+             ORDER BY a.line_start, a.created_at",
         )
         .map_err(|e| e.to_string())?;
     let rows = stmt
         .query_map(params![doc_id], |r| {
             Ok(AnchorItem {
                 id: r.get(0)?,
-                line: r.get(1)?,
-                created_at: r.get(2)?,
+                doc_id: r.get(1)?,
+                line: r.get(2)?,
+                line_end: r.get(3)?,
+                text: r.get(4)?,
+                status: r.get(5)?,
+                version_id: r.get(6)?,
+                created_at: r.get(7)?,
+                updated_at: r.get(8)?,
             })
         })
         .map_err(|e| e.to_string())?;
@@ -65,12 +370,83 @@ pub async fn anchors_delete(
     anchor_id: String,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<serde_json::Value, String> {
+    anchors_delete_core(&db, &anchor_id)
+}
+
+pub fn anchors_delete_core(db: &Db, anchor_id: &str) -> Result<serde_json::Value, String> {
     let conn = db.0.lock();
     let n = conn
-        .execute(
-            "DELETE FROM provenance WHERE entity_type='anchor' AND entity_id=?1",
-            params![anchor_id],
-        )
+        .execute("DELETE FROM anchor WHERE id=?1", params![anchor_id])
         .map_err(|e| e.to_string())?;
     Ok(serde_json::json!({"deleted": n>0}))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    fn update(db: &Db, body: &str) {
+        super::super::docs_update_core(
+            db,
+            super::super::DocUpdate {
+                doc_id: "d".into(),
+                body: body.into(),
+                message: None,
+                base_version_id: None,
+            },
+        )
+        .unwrap();
+    }
+
+    fn anchor(db: &Db, id: &str) -> AnchorItem {
+        anchors_list_core(db, "d").unwrap().into_iter().find(|a| a.id == id).unwrap()
+    }
+
+    #[test]
+    fn test_anchors_follow_edits() {
+        let db = db_with_docs("anchor", &[("d", "n")]);
+        update(&db, "# Title\nintro\nThe quick brown fox jumps\nmiddle\nclosing line\n");
+        anchors_upsert_core(&db, "d", "fox", 3, None).unwrap();
+        anchors_upsert_core(&db, "d", "end", 5, None).unwrap();
+        anchors_upsert_core(&db, "d", "mid", 4, None).unwrap();
+        assert_eq!(anchor(&db, "fox").text, "The quick brown fox jumps");
+        assert_eq!(anchors_upsert_core(&db, "d", "eof", 6, None).unwrap_err(), "invalid_range");
+        assert_eq!(anchors_upsert_core(&db, "d", "tail", 4, Some(9)).unwrap()["line_end"], 5);
+        assert_eq!(anchor(&db, "tail").line_end, 5);
+
+        // Lines inserted above: anchors shift with the diff
+        update(&db, "# Title\nnew one\nnew two\nintro\nThe quick brown fox jumps\nmiddle\nclosing line\n");
+        assert_eq!((anchor(&db, "fox").line, anchor(&db, "end").line), (5, 7));
+
+        // Anchored line edited slightly and moved: fuzzy match; removed line: orphaned
+        update(&db, "# Title\nThe quick brown fox jumped\nnew one\nnew two\nintro\nclosing line\n");
+        let fox = anchor(&db, "fox");
+        assert_eq!((fox.line, fox.status.as_str()), (2, "ok"));
+        assert_eq!(fox.text, "The quick brown fox jumped");
+        let mid = anchor(&db, "mid");
+        assert_eq!((mid.line, mid.status.as_str()), (6, "orphaned"));
+        assert_eq!(anchor(&db, "end").line, 6);
+
+        // The text coming back re-attaches the orphan
+        update(&db, "# Title\nThe quick brown fox jumped\nnew one\nnew two\nintro\nmiddle\nclosing line\n");
+        let mid = anchor(&db, "mid");
+        assert_eq!((mid.line, mid.status.as_str()), (6, "ok"));
+
+        // Rewritten beyond a fuzzy match but between the same lines: follows its context
+        update(&db, "# Title\nThe quick brown fox jumped\nnew one\nnew two\nintro\nsomething else entirely\nclosing line\n");
+        let mid = anchor(&db, "mid");
+        assert_eq!((mid.line, mid.status.as_str(), mid.text.as_str()), (6, "ok", "something else entirely"));
+    }
+
+    #[test]
+    fn test_anchors_hidden_for_trashed_doc() {
+        let db = db_with_docs("anchor-trash", &[("d", "n")]);
+        update(&db, "one\ntwo\n");
+        anchors_upsert_core(&db, "d", "a1", 2, None).unwrap();
+        assert_eq!(anchors_list_core(&db, "d").unwrap().len(), 1);
+        super::super::docs_delete_core(&db, "d").unwrap();
+        assert!(anchors_list_core(&db, "d").unwrap().is_empty());
+        assert_eq!(super::super::comments_threads_list_core(&db, "d", true).err().as_deref(), Some("not_found"));
+    }
+}
@@ -0,0 +1,406 @@
+//! Comment thread commands
+//!
+//! Threads hang off a doc and are normally pinned to an anchor, so they follow the text across
+//! edits. AI runs made against the same anchor (`ai_trace.anchor_id`) are merged into the
+//! thread as `kind: "ai"` entries, next to the human comments.
+
+use crate::db::Db;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::State;
+use uuid::Uuid;
+
+#[derive(Clone, Serialize)]
+pub struct CommentEntry {
+    pub id: String,
+    /// `comment` or `ai` (an ai_trace on the thread's anchor)
+    pub kind: String,
+    pub author: String,
+    pub body: String,
+    pub created_at: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct CommentThread {
+    pub id: String,
+    pub doc_id: String,
+    pub anchor_id: Option<String>,
+    pub line: Option<i64>,
+    pub line_end: Option<i64>,
+    pub anchor_status: Option<String>,
+    /// Anchored text at the last placement
+    pub quote: Option<String>,
+    pub created_by: String,
+    pub resolved: bool,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub entries: Vec<CommentEntry>,
+}
+
+#[derive(Deserialize)]
+pub struct ThreadCreate {
+    pub doc_id: String,
+    /// Existing anchor to attach to; otherwise `line..=line_end` gets a new anchor
+    #[serde(default)]
+    pub anchor_id: Option<String>,
+    #[serde(default)]
+    pub line: Option<i64>,
+    #[serde(default)]
+    pub line_end: Option<i64>,
+    #[serde(default)]
+    pub author: Option<String>,
+    pub body: String,
+}
+
+fn author_or_default(conn: &Connection, author: Option<String>) -> String {
+    author
+        .filter(|a| !a.trim().is_empty())
+        .unwrap_or_else(|| super::current_user(conn))
+}
+
+/// Threads of the given docs keyed by doc id, oldest first, each with its merged entries.
+pub(crate) fn threads_for_docs(
+    conn: &Connection,
+    doc_ids: &[String],
+    include_resolved: bool,
+) -> Result<HashMap<String, Vec<CommentThread>>, String> {
+    let mut map: HashMap<String, Vec<CommentThread>> = HashMap::new();
+    if doc_ids.is_empty() {
+        return Ok(map);
+    }
+    let ids_json = serde_json::to_string(doc_ids).map_err(|e| e.to_string())?;
+    let mut threads: Vec<CommentThread> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT t.id, t.doc_id, t.anchor_id, a.line_start, a.line_end, a.status, a.text, t.created_by, \
+                 t.resolved, t.resolved_by, t.resolved_at, t.created_at, t.updated_at \
+                 FROM comment_thread t LEFT JOIN anchor a ON a.id=t.anchor_id \
+                 WHERE t.doc_id IN (SELECT value FROM json_each(?1)) AND (?2 OR t.resolved=0) \
+                 ORDER BY t.created_at, t.rowid",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![ids_json, include_resolved], |r| {
+                Ok(CommentThread {
+                    id: r.get(0)?,
+                    doc_id: r.get(1)?,
+                    anchor_id: r.get(2)?,
+                    line: r.get(3)?,
+                    line_end: r.get(4)?,
+                    anchor_status: r.get(5)?,
+                    quote: r.get(6)?,
+                    created_by: r.get(7)?,
+                    resolved: r.get::<_, i64>(8)? != 0,
+                    resolved_by: r.get(9)?,
+                    resolved_at: r.get(10)?,
+                    created_at: r.get(11)?,
+                    updated_at: r.get(12)?,
+                    entries: Vec::new(),
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    for thread in threads.iter_mut() {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, 'comment', author, body, created_at, 0 AS k FROM comment WHERE thread_id=?1 \
+                 UNION ALL \
+                 SELECT id, 'ai', provider, COALESCE(json_extract(response,'$.text'),''), created_at, 1 \
+                 FROM ai_trace WHERE ?2 IS NOT NULL AND anchor_id=?2 AND doc_id IN (?3, (SELECT slug FROM doc WHERE id=?3)) \
+                 ORDER BY 5, 6",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![thread.id, thread.anchor_id, thread.doc_id], |r| {
+                Ok(CommentEntry {
+                    id: r.get(0)?,
+                    kind: r.get(1)?,
+                    author: r.get(2)?,
+                    body: r.get(3)?,
+                    created_at: r.get(4)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        thread.entries = rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?;
+    }
+    for thread in threads {
+        map.entry(thread.doc_id.clone()).or_default().push(thread);
+    }
+    Ok(map)
+}
+
+fn thread_exists(conn: &Connection, thread_id: &str) -> Result<(), String> {
+    conn.query_row("SELECT 1 FROM comment_thread WHERE id=?1", params![thread_id], |_| Ok(()))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "not_found".to_string())
+}
+
+#[tauri::command]
+pub async fn comments_threads_list(
+    doc_id: String,
+    include_resolved: Option<bool>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<CommentThread>, String> {
+    comments_threads_list_core(&db, &doc_id, include_resolved.unwrap_or(true))
+}
+
+pub fn comments_threads_list_core(
+    db: &Db,
+    doc_id: &str,
+    include_resolved: bool,
+) -> Result<Vec<CommentThread>, String> {
+    let conn = db.0.lock();
+    let doc_id: String = conn
+        .query_row(
+            "SELECT id FROM doc WHERE (id=?1 OR slug=?1) AND is_deleted=0 LIMIT 1",
+            params![doc_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?;
+    let mut map = threads_for_docs(&conn, std::slice::from_ref(&doc_id), include_resolved)?;
+    Ok(map.remove(&doc_id).unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn comments_thread_create(
+    payload: ThreadCreate,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    comments_thread_create_core(&db, payload)
+}
+
+/// Start a thread with its first comment, anchoring `line..=line_end` when no anchor is given.
+pub fn comments_thread_create_core(db: &Db, payload: ThreadCreate) -> Result<serde_json::Value, String> {
+    if payload.body.trim().is_empty() {
+        return Err("empty_body".into());
+    }
+    let conn = db.0.lock();
+    let doc_id: String = conn
+        .query_row(
+            "SELECT id FROM doc WHERE (id=?1 OR slug=?1) AND is_deleted=0 LIMIT 1",
+            params![payload.doc_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let anchor_id = match (payload.anchor_id, payload.line) {
+        (Some(anchor_id), _) => {
+            let anchor_doc: String = tx
+                .query_row("SELECT doc_id FROM anchor WHERE id=?1", params![anchor_id], |r| r.get(0))
+                .optional()
+                .map_err(|e| e.to_string())?
+                .ok_or("anchor_not_found")?;
+            if anchor_doc != doc_id {
+                return Err("anchor_not_found".into());
+            }
+            Some(anchor_id)
+        }
+        (None, Some(line)) => {
+            let line_end = payload.line_end.unwrap_or(line);
+            if line < 1 || line_end < line {
+                return Err("invalid_range".into());
+            }
+            let anchor_id = format!("thr_{}", Uuid::new_v4());
+            let (body, version) = super::current_body(&tx, &doc_id)?;
+            super::write_anchor(&tx, &anchor_id, &doc_id, &body, version.as_deref(), line as usize, line_end as usize)?;
+            Some(anchor_id)
+        }
+        (None, None) => None,
+    };
+    let author = author_or_default(&tx, payload.author);
+    let thread_id = Uuid::new_v4().to_string();
+    let comment_id = Uuid::new_v4().to_string();
+    tx.execute(
+        "INSERT INTO comment_thread(id,doc_id,anchor_id,created_by) VALUES(?1,?2,?3,?4)",
+        params![thread_id, doc_id, anchor_id, author],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT INTO comment(id,thread_id,author,body) VALUES(?1,?2,?3,?4)",
+        params![comment_id, thread_id, author, payload.body],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"thread_id": thread_id, "comment_id": comment_id, "anchor_id": anchor_id}))
+}
+
+#[tauri::command]
+pub async fn comments_reply(
+    thread_id: String,
+    body: String,
+    author: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    comments_reply_core(&db, &thread_id, &body, author)
+}
+
+pub fn comments_reply_core(
+    db: &Db,
+    thread_id: &str,
+    body: &str,
+    author: Option<String>,
+) -> Result<serde_json::Value, String> {
+    if body.trim().is_empty() {
+        return Err("empty_body".into());
+    }
+    let conn = db.0.lock();
+    thread_exists(&conn, thread_id)?;
+    let author = author_or_default(&conn, author);
+    let comment_id = Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO comment(id,thread_id,author,body) VALUES(?1,?2,?3,?4)",
+        params![comment_id, thread_id, author, body],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE comment_thread SET updated_at=datetime('now') WHERE id=?1",
+        params![thread_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"comment_id": comment_id}))
+}
+
+#[tauri::command]
+pub async fn comments_resolve(
+    thread_id: String,
+    resolved: Option<bool>,
+    author: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    comments_resolve_core(&db, &thread_id, resolved.unwrap_or(true), author)
+}
+
+/// Resolve (or with `resolved = false` reopen) a thread.
+pub fn comments_resolve_core(
+    db: &Db,
+    thread_id: &str,
+    resolved: bool,
+    author: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    thread_exists(&conn, thread_id)?;
+    if resolved {
+        let author = author_or_default(&conn, author);
+        conn.execute(
+            "UPDATE comment_thread SET resolved=1, resolved_by=?2, resolved_at=datetime('now'), updated_at=datetime('now') WHERE id=?1",
+            params![thread_id, author],
+        )
+    } else {
+        conn.execute(
+            "UPDATE comment_thread SET resolved=0, resolved_by=NULL, resolved_at=NULL, updated_at=datetime('now') WHERE id=?1",
+            params![thread_id],
+        )
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"thread_id": thread_id, "resolved": resolved}))
+}
+
+#[tauri::command]
+pub async fn comments_thread_delete(
+    thread_id: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    comments_thread_delete_core(&db, &thread_id)
+}
+
+/// Delete a thread and its comments. An anchor created for the thread is removed with it.
+pub fn comments_thread_delete_core(db: &Db, thread_id: &str) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let anchor_id: Option<Option<String>> = conn
+        .query_row("SELECT anchor_id FROM comment_thread WHERE id=?1", params![thread_id], |r| r.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let Some(anchor_id) = anchor_id else {
+        return Ok(serde_json::json!({"deleted": false}));
+    };
+    conn.execute("DELETE FROM comment_thread WHERE id=?1", params![thread_id])
+        .map_err(|e| e.to_string())?;
+    if let Some(a) = anchor_id.filter(|a| a.starts_with("thr_")) {
+        conn.execute(
+            "DELETE FROM anchor WHERE id=?1 AND NOT EXISTS (SELECT 1 FROM comment_thread WHERE anchor_id=?1)",
+            params![a],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(serde_json::json!({"deleted": true}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    #[test]
+    fn test_threads_with_replies_and_ai_entries() {
+        let db = db_with_docs("comment", &[("d", "n")]);
+        super::super::docs_update_core(
+            &db,
+            super::super::DocUpdate {
+                doc_id: "d".into(),
+                body: "# Title\nfirst claim\nsecond claim\n".into(),
+                message: None,
+                base_version_id: None,
+            },
+        )
+        .unwrap();
+
+        let created = comments_thread_create_core(
+            &db,
+            ThreadCreate {
+                doc_id: "n".into(),
+                anchor_id: None,
+                line: Some(3),
+                line_end: None,
+                author: Some("ann".into()),
+                body: "Source?".into(),
+            },
+        )
+        .unwrap();
+        let thread_id = created["thread_id"].as_str().unwrap().to_string();
+        let anchor_id = created["anchor_id"].as_str().unwrap().to_string();
+        comments_reply_core(&db, &thread_id, "Added one", Some("bob".into())).unwrap();
+        {
+            let conn = db.0.lock();
+            conn.execute(
+                "INSERT INTO ai_trace(id,repo_id,doc_id,anchor_id,provider,request,response,created_at) \
+                 VALUES('t1','r','d',?1,'local','{}','{\"text\":\"Consider citing\"}',datetime('now','+1 minute'))",
+                params![anchor_id],
+            )
+            .unwrap();
+        }
+
+        let threads = comments_threads_list_core(&db, "d", true).unwrap();
+        assert_eq!(threads.len(), 1);
+        let t = &threads[0];
+        assert_eq!((t.line, t.quote.as_deref()), (Some(3), Some("second claim")));
+        let entries: Vec<(&str, &str, &str)> = t
+            .entries
+            .iter()
+            .map(|e| (e.kind.as_str(), e.author.as_str(), e.body.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![("comment", "ann", "Source?"), ("comment", "bob", "Added one"), ("ai", "local", "Consider citing")]
+        );
+
+        comments_resolve_core(&db, &thread_id, true, Some("ann".into())).unwrap();
+        assert!(comments_threads_list_core(&db, "d", false).unwrap().is_empty());
+        let t = comments_threads_list_core(&db, "d", true).unwrap().remove(0);
+        assert_eq!((t.resolved, t.resolved_by.as_deref()), (true, Some("ann")));
+        comments_resolve_core(&db, &thread_id, false, None).unwrap();
+        assert_eq!(comments_threads_list_core(&db, "d", false).unwrap().len(), 1);
+
+        assert_eq!(comments_reply_core(&db, "nope", "x", None).unwrap_err(), "not_found");
+        comments_thread_delete_core(&db, &thread_id).unwrap();
+        assert!(super::super::anchors_list_core(&db, "d").unwrap().is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 //! Document CRUD commands
 
 use crate::db::Db;
-use rusqlite::params;
+use rusqlite::{params, OptionalExtension};
 use serde::Deserialize;
 use tauri::State;
 use uuid::Uuid;
@@ -12,6 +12,9 @@ pub struct DocCreate {
     pub slug: String,
     pub title: String,
     pub body: String,
+    /// Repo-relative folder path (created with its ancestors if missing); repo root by default
+    #[serde(default)]
+    pub folder: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -19,6 +22,9 @@ pub struct DocUpdate {
     pub doc_id: String,
     pub body: String,
     pub message: Option<String>,
+    /// Version the edit started from; when it is no longer current the body is three-way merged
+    #[serde(default)]
+    pub base_version_id: Option<String>,
 }
 
 /// Helper function to compute document version hash
@@ -27,27 +33,31 @@ fn doc_version_hash(doc_id: &str, body: &str) -> String {
     format!("{doc_id}:{body_hash}")
 }
 
+/// Queue the embedding refresh after a write; failures never fail the save (see `embeddings_status`).
+pub(crate) fn refresh_embeddings(db: &Db, doc_id: &str, body: &str) {
+    crate::ai::embed::queue_refresh(db, doc_id, body);
+}
+
 #[tauri::command]
 pub async fn docs_create(
     payload: DocCreate,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<serde_json::Value, String> {
+    docs_create_core(&db, payload)
+}
+
+pub fn docs_create_core(db: &Db, payload: DocCreate) -> Result<serde_json::Value, String> {
     let mut conn = db.0.lock();
     let doc_id = Uuid::new_v4().to_string();
-    let blob_id = Uuid::new_v4().to_string();
     let version_id = Uuid::new_v4().to_string();
     let tx = conn.transaction().map_err(|e| e.to_string())?;
-    tx.execute(
-        "INSERT INTO folder(id,repo_id,path,slug) VALUES(?,?,?,?) ON CONFLICT(repo_id,path) DO NOTHING",
-        params![Uuid::new_v4().to_string(), payload.repo_id, "", ""],
-    )
-    .ok();
+    let folder_id = super::ensure_folder(&tx, &payload.repo_id, payload.folder.as_deref().unwrap_or(""))?;
     tx.execute(
         "INSERT INTO doc(id,repo_id,folder_id,slug,title,size_bytes,line_count) VALUES(?,?,?,?,?,?,?)",
         params![
             doc_id,
             payload.repo_id,
-            tx.last_insert_rowid(),
+            folder_id,
             payload.slug,
             payload.title,
             payload.body.len() as i64,
@@ -55,11 +65,7 @@ pub async fn docs_create(
         ],
     )
     .map_err(|e| e.to_string())?;
-    tx.execute(
-        "INSERT INTO doc_blob(id,content,size_bytes) VALUES(?,?,?)",
-        params![blob_id, payload.body.as_bytes(), payload.body.len() as i64],
-    )
-    .map_err(|e| e.to_string())?;
+    let blob_id = crate::blob::put_text(&tx, &payload.body)?;
     let version_hash = doc_version_hash(&doc_id, &payload.body);
     tx.execute(
         "INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES(?,?,?,?)",
@@ -72,15 +78,15 @@ pub async fn docs_create(
     )
     .map_err(|e| e.to_string())?;
     // FTS update
-    tx.execute(
-        "INSERT INTO doc_fts(rowid,title,body,slug,repo_id) SELECT d.rowid,d.title,?1,d.slug,d.repo_id FROM doc d WHERE d.id=?2",
-        params![payload.body, doc_id],
-    )
-    .map_err(|e| e.to_string())?;
+    crate::fts::insert_doc(&tx, &doc_id, &payload.body)?;
+    crate::fts::index_version(&tx, &version_id, &payload.body)?;
     tx.commit().map_err(|e| e.to_string())?;
+    // release connection lock before link update to avoid deadlock
+    drop(conn);
     // update links
     crate::graph::update_links_for_doc(&db.0.lock(), &doc_id, &payload.body)?;
-    Ok(serde_json::json!({"doc_id": doc_id}))
+    refresh_embeddings(db, &doc_id, &payload.body);
+    Ok(serde_json::json!({"doc_id": doc_id, "folder_id": folder_id}))
 }
 
 #[tauri::command]
@@ -88,10 +94,80 @@ pub async fn docs_update(
     payload: DocUpdate,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<serde_json::Value, String> {
+    docs_update_core(&db, payload)
+}
+
+/// Append a version with a new body (skipped when identical to the current one), then
+/// refresh FTS, links and embeddings. Shared by IPC, RPC and restore.
+///
+/// With `base_version_id` set and stale, the body is merged against the current version using
+/// the base as common ancestor; if that conflicts nothing is written and a `conflict` object
+/// is returned instead of a `version_id`. A trashed doc is `not_found`.
+pub fn docs_update_core(db: &Db, payload: DocUpdate) -> Result<serde_json::Value, String> {
+    let doc_id = payload.doc_id.clone();
     let mut conn = db.0.lock();
-    let version_hash = doc_version_hash(&payload.doc_id, &payload.body);
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let (out, written) = write_update(&tx, payload)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    // release connection lock before link update to avoid deadlock
+    drop(conn);
+    if let Some(body) = written {
+        after_update(db, &doc_id, &body)?;
+    }
+    Ok(out)
+}
+
+/// The database half of `docs_update_core`, inside the caller's transaction. Returns the
+/// result and, when a version was appended, the body to pass to [`after_update`] once the
+/// transaction is committed.
+pub(crate) fn write_update(
+    tx: &rusqlite::Transaction,
+    payload: DocUpdate,
+) -> Result<(serde_json::Value, Option<String>), String> {
+    let current: Option<String> = tx
+        .query_row(
+            "SELECT current_version_id FROM doc WHERE id=?1 AND is_deleted=0",
+            params![&payload.doc_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?;
+    let mut body = payload.body;
+    let mut merged_with: Option<String> = None;
+    if let (Some(base), Some(cur)) = (payload.base_version_id.as_deref(), current.as_deref()) {
+        if base != cur {
+            let base_owner: Option<String> = tx
+                .query_row("SELECT doc_id FROM doc_version WHERE id=?1", params![base], |r| r.get(0))
+                .optional()
+                .map_err(|e| e.to_string())?;
+            if base_owner.as_deref() != Some(payload.doc_id.as_str()) {
+                return Err("invalid_base_version".into());
+            }
+            let base_body = super::version_body(tx, base)?;
+            let current_body = super::version_body(tx, cur)?;
+            match crate::merge::merge3(&base_body, &body, &current_body) {
+                crate::merge::MergeOutcome::Clean(merged) => {
+                    body = merged;
+                    merged_with = Some(cur.to_string());
+                }
+                crate::merge::MergeOutcome::Conflicted { conflicts, marked } => {
+                    let out = serde_json::json!({
+                        "conflict": {
+                            "base_version_id": base,
+                            "current_version_id": cur,
+                            "conflicts": conflicts,
+                            "marked": marked,
+                        }
+                    });
+                    return Ok((out, None));
+                }
+            }
+        }
+    }
+    let version_hash = doc_version_hash(&payload.doc_id, &body);
     // Check if same as current
-    let unchanged: bool = conn
+    let unchanged: bool = tx
         .query_row(
             "SELECT v.hash FROM doc d JOIN doc_version v ON v.id=d.current_version_id WHERE d.id=?1",
             params![&payload.doc_id],
@@ -100,24 +176,11 @@ pub async fn docs_update(
         .map(|h| h == version_hash)
         .unwrap_or(false);
     if unchanged {
-        let cur: String = conn
-            .query_row(
-                "SELECT current_version_id FROM doc WHERE id=?1",
-                params![&payload.doc_id],
-                |r| r.get(0),
-            )
-            .unwrap_or_default();
-        drop(conn);
-        return Ok(serde_json::json!({"version_id": cur, "skipped": true}));
+        let out = serde_json::json!({"version_id": current.unwrap_or_default(), "skipped": true});
+        return Ok((out, None));
     }
     let version_id = Uuid::new_v4().to_string();
-    let blob_id = Uuid::new_v4().to_string();
-    let tx = conn.transaction().map_err(|e| e.to_string())?;
-    tx.execute(
-        "INSERT INTO doc_blob(id,content,size_bytes) VALUES(?,?,?)",
-        params![blob_id, payload.body.as_bytes(), payload.body.len() as i64],
-    )
-    .map_err(|e| e.to_string())?;
+    let blob_id = crate::blob::put_text(tx, &body)?;
     tx.execute(
         "INSERT INTO doc_version(id,doc_id,blob_id,hash,message) VALUES(?,?,?,?,?)",
         params![
@@ -133,29 +196,30 @@ pub async fn docs_update(
         "UPDATE doc SET current_version_id=?1, size_bytes=?2, line_count=?3, updated_at=datetime('now') WHERE id=?4",
         params![
             version_id,
-            payload.body.len() as i64,
-            payload.body.lines().count() as i64,
+            body.len() as i64,
+            body.lines().count() as i64,
             payload.doc_id
         ],
     )
     .map_err(|e| e.to_string())?;
     // FTS update: delete+insert
-    tx.execute(
-        "INSERT INTO doc_fts(doc_fts,rowid) VALUES('delete',(SELECT rowid FROM doc WHERE id=?1))",
-        params![payload.doc_id],
-    )
-    .ok();
-    tx.execute(
-        "INSERT INTO doc_fts(rowid,title,body,slug,repo_id) SELECT d.rowid,d.title,?1,d.slug,d.repo_id FROM doc d WHERE d.id=?2",
-        params![payload.body, payload.doc_id],
-    )
-    .map_err(|e| e.to_string())?;
-    tx.commit().map_err(|e| e.to_string())?;
-    // release connection lock before link update to avoid deadlock
-    drop(conn);
-    // update links
-    crate::graph::update_links_for_doc(&db.0.lock(), &payload.doc_id, &payload.body)?;
-    Ok(serde_json::json!({"version_id": version_id}))
+    crate::fts::reindex_doc(tx, &payload.doc_id, &body)?;
+    crate::fts::index_version(tx, &version_id, &body)?;
+    super::reanchor_doc(tx, &payload.doc_id, &version_id, &body)?;
+    let mut out = serde_json::json!({"version_id": version_id});
+    if let Some(cur) = merged_with {
+        // The caller's editor needs the merged text, not what it sent
+        out["merged_with"] = serde_json::Value::String(cur);
+        out["body"] = serde_json::Value::String(body.clone());
+    }
+    Ok((out, Some(body)))
+}
+
+/// Links and embeddings for a body written by [`write_update`]; takes the lock itself.
+pub(crate) fn after_update(db: &Db, doc_id: &str, body: &str) -> Result<(), String> {
+    crate::graph::update_links_for_doc(&db.0.lock(), doc_id, body)?;
+    refresh_embeddings(db, doc_id, body);
+    Ok(())
 }
 
 #[tauri::command]
@@ -164,29 +228,27 @@ pub async fn docs_get(
     content: Option<bool>,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<serde_json::Value, String> {
+    docs_get_core(&db, &doc_id, content.unwrap_or(false))
+}
+
+pub fn docs_get_core(db: &Db, doc_id: &str, include_body: bool) -> Result<serde_json::Value, String> {
     let conn = db.0.lock();
     let mut stmt = conn
-        .prepare("SELECT id,repo_id,slug,title,current_version_id FROM doc WHERE id=?1 OR slug=?1 LIMIT 1")
+        .prepare("SELECT id,repo_id,slug,title,current_version_id FROM doc WHERE (id=?1 OR slug=?1) AND is_deleted=0 LIMIT 1")
         .map_err(|e| e.to_string())?;
     let mut rows = stmt.query(params![doc_id]).map_err(|e| e.to_string())?;
     if let Some(r) = rows.next().map_err(|e| e.to_string())? {
         let id: String = r.get(0).unwrap_or_default();
-        let include_body = content.unwrap_or(false);
+        let current: Option<String> = r.get(4).unwrap_or_default();
         let mut out = serde_json::json!({
             "id": id,
             "repo_id": r.get::<_, String>(1).unwrap_or_default(),
             "slug": r.get::<_, String>(2).unwrap_or_default(),
             "title": r.get::<_, String>(3).unwrap_or_default(),
-            "current_version_id": r.get::<_, String>(4).unwrap_or_default(),
+            "current_version_id": current.clone().unwrap_or_default(),
         });
         if include_body {
-            let body: Option<String> = conn
-                .query_row(
-                    "SELECT body FROM doc_fts WHERE rowid=(SELECT rowid FROM doc WHERE id=?1)",
-                    params![&id],
-                    |rr| rr.get(0),
-                )
-                .ok();
+            let body = current.and_then(|v| super::version_body(&conn, &v).ok());
             out["body"] = body
                 .map(serde_json::Value::String)
                 .unwrap_or(serde_json::Value::Null);
@@ -195,18 +257,3 @@ pub async fn docs_get(
     }
     Err("not_found".into())
 }
-
-#[tauri::command]
-pub async fn docs_delete(
-    doc_id: String,
-    db: State<'_, std::sync::Arc<Db>>,
-) -> Result<serde_json::Value, String> {
-    let conn = db.0.lock();
-    let n = conn
-        .execute(
-            "UPDATE doc SET is_deleted=1 WHERE id=?1 OR slug=?1",
-            params![doc_id],
-        )
-        .map_err(|e| e.to_string())?;
-    Ok(serde_json::json!({"deleted": n>0}))
-}
@@ -37,6 +37,8 @@ pub struct DocExportRow {
     pub versions: Option<Vec<DocVersionExport>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub attachments: Option<Vec<DocAttachmentExport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<Vec<super::CommentThread>>,
 }
 
 #[derive(Clone, Serialize)]
@@ -134,8 +136,8 @@ struct PendingAttachment {
 
 fn export_docs_sql(include_deleted: bool, with_repo: bool) -> String {
     let mut sql = String::from(
-        "SELECT d.id, d.repo_id, d.slug, d.title, COALESCE(fts.body,'') as body, d.updated_at, d.is_deleted \
-         FROM doc d JOIN doc_fts fts ON fts.rowid = d.rowid WHERE 1=1",
+        "SELECT d.id, d.repo_id, COALESCE(d.trashed_slug, d.slug), d.title, b.content, COALESCE(b.encoding,'utf8'), d.updated_at, d.is_deleted \
+         FROM doc d LEFT JOIN doc_version v ON v.id = d.current_version_id LEFT JOIN doc_blob b ON b.id = v.blob_id WHERE 1=1",
     );
     if !include_deleted {
         sql.push_str(" AND d.is_deleted=0");
@@ -157,16 +159,25 @@ fn fetch_doc_exports(
     let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
 
     let mapper = |r: &rusqlite::Row| -> rusqlite::Result<DocExportRow> {
+        let stored: Option<Vec<u8>> = r.get(4)?;
+        let encoding: String = r.get(5)?;
+        let body = match stored {
+            Some(s) => crate::blob::decode_text(s, &encoding).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Blob, e.into())
+            })?,
+            None => String::new(),
+        };
         Ok(DocExportRow {
             id: r.get(0)?,
             repo_id: r.get(1)?,
             slug: r.get(2)?,
             title: r.get(3)?,
-            body: r.get(4)?,
-            updated_at: r.get(5)?,
-            is_deleted: r.get::<_, i64>(6)? != 0,
+            body,
+            updated_at: r.get(6)?,
+            is_deleted: r.get::<_, i64>(7)? != 0,
             versions: None,
             attachments: None,
+            threads: None,
         })
     };
 
@@ -243,6 +254,8 @@ fn fetch_doc_attachments(
         .map_err(|e| e.to_string())?;
     for row in rows {
         let (doc_id, filename, mime, encoding, content) = row.map_err(|e| e.to_string())?;
+        let content = crate::blob::decode(content, &encoding)?;
+        let encoding = crate::blob::content_encoding(&encoding).to_string();
         map.entry(doc_id.clone()).or_default().push(DocAttachmentExport {
             doc_id,
             filename,
@@ -260,11 +273,13 @@ pub async fn export_docs(
     include_deleted: Option<bool>,
     include_versions: Option<bool>,
     include_attachments: Option<bool>,
+    include_comments: Option<bool>,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<Vec<DocExportRow>, String> {
     let include_deleted = include_deleted.unwrap_or(false);
     let include_versions = include_versions.unwrap_or(false);
     let include_attachments = include_attachments.unwrap_or(false);
+    let include_comments = include_comments.unwrap_or(false);
     let conn = db.0.lock();
     let mut docs = fetch_doc_exports(&conn, repo_id.as_deref(), include_deleted)?;
     if include_versions && !docs.is_empty() {
@@ -285,6 +300,13 @@ pub async fn export_docs(
             }
         }
     }
+    if include_comments && !docs.is_empty() {
+        let ids: Vec<String> = docs.iter().map(|d| d.id.clone()).collect();
+        let mut thread_map = super::threads_for_docs(&conn, &ids, true)?;
+        for doc in docs.iter_mut() {
+            doc.threads = thread_map.remove(&doc.id);
+        }
+    }
     Ok(docs)
 }
 
@@ -562,19 +584,12 @@ fn insert_doc_blob(
     encoding: Option<&str>,
     mime: Option<&str>,
 ) -> Result<String, String> {
-    let blob_id = Uuid::new_v4().to_string();
-    conn.execute(
-        "INSERT INTO doc_blob(id,content,size_bytes,encoding,mime) VALUES(?,?,?,?,?)",
-        params![
-            blob_id,
-            content,
-            content.len() as i64,
-            encoding.unwrap_or("utf8"),
-            mime.unwrap_or("text/markdown")
-        ],
+    crate::blob::put(
+        conn,
+        content,
+        encoding.unwrap_or("utf8"),
+        mime.unwrap_or("text/markdown"),
     )
-    .map_err(|e| e.to_string())?;
-    Ok(blob_id)
 }
 
 fn write_doc_version(
@@ -596,27 +611,8 @@ fn write_doc_version(
         params![version_id, doc_id],
     )
     .map_err(|e| e.to_string())?;
-    Ok(())
-}
-
-fn refresh_doc_fts(
-    conn: &Connection,
-    doc_id: &str,
-    title: &str,
-    body: &str,
-    slug: &str,
-    repo_id: &str,
-) -> Result<(), String> {
-    conn.execute(
-        "INSERT INTO doc_fts(doc_fts,rowid) VALUES('delete',(SELECT rowid FROM doc WHERE id=?1))",
-        params![doc_id],
-    )
-    .ok();
-    conn.execute(
-        "INSERT INTO doc_fts(rowid,title,body,slug,repo_id) SELECT d.rowid,?2,?3,?4,?5 FROM doc d WHERE d.id=?1",
-        params![doc_id, title, body, slug, repo_id],
-    )
-    .map_err(|e| e.to_string())?;
+    crate::fts::index_version(conn, &version_id, body)?;
+    super::reanchor_doc(conn, doc_id, &version_id, body)?;
     Ok(())
 }
 
@@ -821,31 +817,23 @@ fn insert_doc_record(
     let size_bytes = body.len() as i64;
     let line_count = body.lines().count() as i64;
     conn.execute(
-        "INSERT INTO doc(id,repo_id,folder_id,slug,title,is_deleted,size_bytes,line_count,created_at,updated_at) VALUES(?,?,?,?,?,?,?, ?,datetime('now'),datetime('now'))",
-        params![
-            doc_id,
-            repo_id,
-            folder_id,
-            slug,
-            title,
-            if is_deleted { 1 } else { 0 },
-            size_bytes,
-            line_count
-        ],
+        "INSERT INTO doc(id,repo_id,folder_id,slug,title,size_bytes,line_count,created_at,updated_at) VALUES(?,?,?,?,?,?,?,datetime('now'),datetime('now'))",
+        params![doc_id, repo_id, folder_id, slug, title, size_bytes, line_count],
     )
     .map_err(|e| e.to_string())?;
     write_doc_version(conn, doc_id, body, message)?;
-    refresh_doc_fts(conn, doc_id, title, body, slug, repo_id)?;
+    crate::fts::reindex_doc(conn, doc_id, body)?;
     crate::graph::update_links_for_doc(conn, doc_id, body)?;
     record_import_provenance(conn, doc_id, import_path)?;
+    if is_deleted {
+        super::trash_doc(conn, doc_id)?;
+    }
     Ok(())
 }
 
 fn update_doc_record(
     conn: &Connection,
     doc_id: &str,
-    repo_id: &str,
-    slug: &str,
     title: &str,
     body: &str,
     is_deleted: bool,
@@ -855,20 +843,17 @@ fn update_doc_record(
     let size_bytes = body.len() as i64;
     let line_count = body.lines().count() as i64;
     conn.execute(
-        "UPDATE doc SET title=?1, is_deleted=?2, size_bytes=?3, line_count=?4, updated_at=datetime('now') WHERE id=?5",
-        params![
-            title,
-            if is_deleted { 1 } else { 0 },
-            size_bytes,
-            line_count,
-            doc_id
-        ],
+        "UPDATE doc SET title=?1, size_bytes=?2, line_count=?3, updated_at=datetime('now') WHERE id=?4",
+        params![title, size_bytes, line_count, doc_id],
     )
     .map_err(|e| e.to_string())?;
     write_doc_version(conn, doc_id, body, message)?;
-    refresh_doc_fts(conn, doc_id, title, body, slug, repo_id)?;
+    crate::fts::reindex_doc(conn, doc_id, body)?;
     crate::graph::update_links_for_doc(conn, doc_id, body)?;
     record_import_provenance(conn, doc_id, import_path)?;
+    if is_deleted {
+        super::trash_doc(conn, doc_id)?;
+    }
     Ok(())
 }
 
@@ -921,8 +906,6 @@ fn import_doc_record(
             update_doc_record(
                 conn,
                 &doc_id,
-                repo_id,
-                &slug,
                 &title,
                 &body,
                 is_deleted,
@@ -0,0 +1,390 @@
+//! Folder tree commands
+//!
+//! Folders are identified by their repo-relative path (`/`-separated, `""` is the repo root)
+//! and linked through `parent_id`. Folder operations only reshape the tree: doc slugs are
+//! stable identifiers (wikilinks point at them) and are left untouched by rename/move, and no
+//! files are moved. A doc scanned from a file goes back under its file's directory on the
+//! next scan.
+
+use crate::db::Db;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use tauri::State;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct FolderInfo {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub path: String,
+    pub slug: String,
+}
+
+#[derive(Serialize)]
+pub struct FolderDoc {
+    pub id: String,
+    pub slug: String,
+    pub title: String,
+}
+
+#[derive(Serialize)]
+pub struct FolderChildren {
+    pub folder: FolderInfo,
+    pub folders: Vec<FolderInfo>,
+    pub docs: Vec<FolderDoc>,
+}
+
+/// Canonical folder path: `/`-separated, no leading/trailing or empty segments.
+pub(crate) fn normalize_folder_path(path: &str) -> Result<String, String> {
+    let parts: Vec<&str> = path
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+    if parts.contains(&"..") {
+        return Err("invalid_path".into());
+    }
+    Ok(parts.join("/"))
+}
+
+pub(crate) fn folder_slug(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).replace(' ', "-")
+}
+
+fn parent_path(path: &str) -> Option<&str> {
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.rsplit_once('/').map(|(p, _)| p).unwrap_or(""))
+    }
+}
+
+fn folder_id(conn: &Connection, repo_id: &str, path: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT id FROM folder WHERE repo_id=?1 AND path=?2",
+        params![repo_id, path],
+        |r| r.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// Id of the folder at `path`, creating it and any missing ancestors (root included).
+pub(crate) fn ensure_folder(conn: &Connection, repo_id: &str, path: &str) -> Result<String, String> {
+    let path = normalize_folder_path(path)?;
+    if let Some(id) = folder_id(conn, repo_id, &path)? {
+        return Ok(id);
+    }
+    let parent_id = match parent_path(&path) {
+        Some(parent) => Some(ensure_folder(conn, repo_id, parent)?),
+        None => None,
+    };
+    let id = Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO folder(id,repo_id,parent_id,path,slug) VALUES(?1,?2,?3,?4,?5)",
+        params![id, repo_id, parent_id, path, folder_slug(&path)],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+/// Link folders created before the tree existed to their parents, and re-home docs whose
+/// `folder_id` points nowhere (older `docs_create` stored a rowid) under their repo root.
+/// Returns folders updated.
+pub(crate) fn repair_folder_tree(conn: &Connection) -> Result<usize, String> {
+    let orphans: Vec<(String, String, String)> = {
+        let mut stmt = conn
+            .prepare("SELECT id, repo_id, path FROM folder WHERE parent_id IS NULL AND path != '' ORDER BY length(path)")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    for (id, repo_id, path) in &orphans {
+        let parent = parent_path(path).unwrap_or("");
+        let parent_id = ensure_folder(conn, repo_id, parent)?;
+        conn.execute("UPDATE folder SET parent_id=?2 WHERE id=?1", params![id, parent_id])
+            .map_err(|e| e.to_string())?;
+    }
+    let dangling: Vec<String> = {
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT repo_id FROM doc WHERE folder_id NOT IN (SELECT id FROM folder)")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map([], |r| r.get(0)).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    for repo_id in &dangling {
+        let root = ensure_folder(conn, repo_id, "")?;
+        conn.execute(
+            "UPDATE doc SET folder_id=?2 WHERE repo_id=?1 AND folder_id NOT IN (SELECT id FROM folder)",
+            params![repo_id, root],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(orphans.len())
+}
+
+fn folder_info(conn: &Connection, repo_id: &str, path: &str) -> Result<FolderInfo, String> {
+    conn.query_row(
+        "SELECT id, parent_id, path, slug FROM folder WHERE repo_id=?1 AND path=?2",
+        params![repo_id, path],
+        |r| {
+            Ok(FolderInfo {
+                id: r.get(0)?,
+                parent_id: r.get(1)?,
+                path: r.get(2)?,
+                slug: r.get(3)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "not_found".to_string())
+}
+
+#[tauri::command]
+pub async fn folders_children(
+    repo_id: String,
+    path: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<FolderChildren, String> {
+    folders_children_core(&db, &repo_id, path.as_deref().unwrap_or(""))
+}
+
+/// Direct subfolders and live docs of a folder (the repo root by default).
+pub fn folders_children_core(db: &Db, repo_id: &str, path: &str) -> Result<FolderChildren, String> {
+    let conn = db.0.lock();
+    let path = normalize_folder_path(path)?;
+    if path.is_empty() {
+        // Repos scanned before the tree existed may lack a root row
+        ensure_folder(&conn, repo_id, "")?;
+    }
+    let folder = folder_info(&conn, repo_id, &path)?;
+    let mut stmt = conn
+        .prepare("SELECT id, parent_id, path, slug FROM folder WHERE parent_id=?1 ORDER BY path")
+        .map_err(|e| e.to_string())?;
+    let folders = stmt
+        .query_map(params![folder.id], |r| {
+            Ok(FolderInfo {
+                id: r.get(0)?,
+                parent_id: r.get(1)?,
+                path: r.get(2)?,
+                slug: r.get(3)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT id, slug, title FROM doc WHERE folder_id=?1 AND is_deleted=0 ORDER BY title")
+        .map_err(|e| e.to_string())?;
+    let docs = stmt
+        .query_map(params![folder.id], |r| {
+            Ok(FolderDoc {
+                id: r.get(0)?,
+                slug: r.get(1)?,
+                title: r.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(FolderChildren { folder, folders, docs })
+}
+
+#[tauri::command]
+pub async fn folders_create(
+    repo_id: String,
+    path: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    folders_create_core(&db, &repo_id, &path)
+}
+
+/// Create a folder and any missing ancestors; `created` is false when it already existed.
+pub fn folders_create_core(db: &Db, repo_id: &str, path: &str) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let path = normalize_folder_path(path)?;
+    let existed = folder_id(&conn, repo_id, &path)?.is_some();
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let id = ensure_folder(&tx, repo_id, &path)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"folder_id": id, "path": path, "created": !existed}))
+}
+
+#[tauri::command]
+pub async fn folders_rename(
+    repo_id: String,
+    path: String,
+    name: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    folders_rename_core(&db, &repo_id, &path, &name)
+}
+
+/// Rename the last segment of a folder path, keeping it under the same parent.
+pub fn folders_rename_core(db: &Db, repo_id: &str, path: &str, name: &str) -> Result<serde_json::Value, String> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err("invalid_name".into());
+    }
+    let path = normalize_folder_path(path)?;
+    let parent = parent_path(&path).ok_or("invalid_path")?;
+    let to = if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    };
+    move_folder(db, repo_id, &path, &to)
+}
+
+#[tauri::command]
+pub async fn folders_move(
+    repo_id: String,
+    path: String,
+    new_parent: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    folders_move_core(&db, &repo_id, &path, &new_parent)
+}
+
+/// Move a folder (with its subtree) under `new_parent` (`""` for the repo root).
+pub fn folders_move_core(db: &Db, repo_id: &str, path: &str, new_parent: &str) -> Result<serde_json::Value, String> {
+    let path = normalize_folder_path(path)?;
+    let new_parent = normalize_folder_path(new_parent)?;
+    let name = path.rsplit('/').next().unwrap_or("");
+    let to = if new_parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", new_parent, name)
+    };
+    move_folder(db, repo_id, &path, &to)
+}
+
+/// Re-path a folder and its descendants from `from` to `to`.
+fn move_folder(db: &Db, repo_id: &str, from: &str, to: &str) -> Result<serde_json::Value, String> {
+    if from.is_empty() {
+        return Err("invalid_path".into());
+    }
+    if to == from {
+        return Ok(serde_json::json!({"path": to, "moved": 0}));
+    }
+    if to.starts_with(&format!("{}/", from)) {
+        return Err("invalid_move".into());
+    }
+    let conn = db.0.lock();
+    let id = folder_id(&conn, repo_id, from)?.ok_or("not_found")?;
+    if folder_id(&conn, repo_id, to)?.is_some() {
+        return Err("exists".into());
+    }
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let parent_id = ensure_folder(&tx, repo_id, parent_path(to).unwrap_or(""))?;
+    let moved = tx
+        .execute(
+            "UPDATE folder SET path=?3 || substr(path, length(?2) + 1), updated_at=datetime('now') \
+             WHERE repo_id=?1 AND (path=?2 OR substr(path, 1, length(?2) + 1) = ?2 || '/')",
+            params![repo_id, from, to],
+        )
+        .map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE folder SET parent_id=?2, slug=?3 WHERE id=?1",
+        params![id, parent_id, folder_slug(to)],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"path": to, "moved": moved}))
+}
+
+#[tauri::command]
+pub async fn folders_delete(
+    repo_id: String,
+    path: String,
+    recursive: Option<bool>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    folders_delete_core(&db, &repo_id, &path, recursive.unwrap_or(false))
+}
+
+/// Delete a folder. Non-empty folders need `recursive`, which moves the subtree's docs to the
+/// trash (re-homed under the repo root so they survive the folder rows) before deleting.
+pub fn folders_delete_core(db: &Db, repo_id: &str, path: &str, recursive: bool) -> Result<serde_json::Value, String> {
+    let path = normalize_folder_path(path)?;
+    if path.is_empty() {
+        return Err("invalid_path".into());
+    }
+    let conn = db.0.lock();
+    folder_id(&conn, repo_id, &path)?.ok_or("not_found")?;
+    const SUBTREE: &str = "SELECT id FROM folder WHERE repo_id=?1 AND (path=?2 OR substr(path, 1, length(?2) + 1) = ?2 || '/')";
+    let docs: Vec<String> = {
+        let mut stmt = conn
+            .prepare(&format!("SELECT id FROM doc WHERE folder_id IN ({})", SUBTREE))
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![repo_id, path], |r| r.get(0))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    let folders: i64 = conn
+        .query_row(&format!("SELECT COUNT(*) FROM ({})", SUBTREE), params![repo_id, path], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    if !recursive && (folders > 1 || !docs.is_empty()) {
+        return Err("not_empty".into());
+    }
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let root = ensure_folder(&tx, repo_id, "")?;
+    let mut trashed = 0;
+    for id in &docs {
+        if super::trash_doc(&tx, id)? {
+            trashed += 1;
+        }
+        tx.execute("UPDATE doc SET folder_id=?2 WHERE id=?1", params![id, root])
+            .map_err(|e| e.to_string())?;
+    }
+    // Descendants go with it through the parent_id cascade
+    tx.execute(&format!("DELETE FROM folder WHERE id IN ({})", SUBTREE), params![repo_id, path])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"deleted": folders, "docs_trashed": trashed}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    #[test]
+    fn test_folder_tree_ops() {
+        let db = db_with_docs("folder", &[]);
+        assert_eq!(folders_create_core(&db, "r", "/notes//deep/").unwrap()["created"], true);
+        assert_eq!(folders_create_core(&db, "r", "notes\\deep").unwrap()["created"], false);
+        assert_eq!(folders_create_core(&db, "r", "../x").unwrap_err(), "invalid_path");
+        let root = folders_children_core(&db, "r", "").unwrap();
+        assert_eq!(root.folder.parent_id, None);
+        assert_eq!(root.folders.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["notes"]);
+
+        folders_create_core(&db, "r", "archive").unwrap();
+        assert_eq!(folders_move_core(&db, "r", "notes", "notes/deep").unwrap_err(), "invalid_move");
+        assert_eq!(folders_move_core(&db, "r", "notes", "archive").unwrap()["moved"], 2);
+        let deep = folders_children_core(&db, "r", "archive/notes/deep").unwrap();
+        let notes = folders_children_core(&db, "r", "archive/notes").unwrap();
+        assert_eq!(deep.folder.parent_id.as_deref(), Some(notes.folder.id.as_str()));
+        assert_eq!(folders_rename_core(&db, "r", "archive/notes", "old").unwrap()["path"], "archive/old");
+        assert_eq!(folders_children_core(&db, "r", "archive/old/deep").unwrap().folder.id, deep.folder.id);
+
+        {
+            let conn = db.0.lock();
+            conn.execute(
+                "INSERT INTO doc(id,repo_id,folder_id,slug,title) VALUES('d','r',?1,'n','N')",
+                params![deep.folder.id],
+            )
+            .unwrap();
+        }
+        assert_eq!(folders_children_core(&db, "r", "archive/old/deep").unwrap().docs.len(), 1);
+        assert_eq!(folders_delete_core(&db, "r", "archive", false).unwrap_err(), "not_empty");
+        let res = folders_delete_core(&db, "r", "archive", true).unwrap();
+        assert_eq!((res["deleted"].as_i64(), res["docs_trashed"].as_i64()), (Some(3), Some(1)));
+        let trashed: bool = db.0.lock().query_row("SELECT is_deleted FROM doc WHERE id='d'", [], |r| r.get(0)).unwrap();
+        assert!(trashed);
+    }
+}
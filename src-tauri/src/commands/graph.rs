@@ -18,6 +18,11 @@ pub async fn graph_backlinks(
     doc_id: String,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<Vec<GraphDoc>, String> {
+    graph_backlinks_core(&db, &doc_id)
+}
+
+/// Docs linking to `doc_id`, most recently updated first.
+pub fn graph_backlinks_core(db: &Db, doc_id: &str) -> Result<Vec<GraphDoc>, String> {
     let conn = db.0.lock();
     let mut stmt = conn
         .prepare(
@@ -46,9 +51,14 @@ pub async fn graph_neighbors(
     depth: Option<u8>,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<Vec<GraphDoc>, String> {
+    let _ = depth; // reserved for future use
+    graph_neighbors_core(&db, &doc_id)
+}
+
+/// Docs `doc_id` links to, and docs linking to the same targets.
+pub fn graph_neighbors_core(db: &Db, doc_id: &str) -> Result<Vec<GraphDoc>, String> {
     // 1-hop for now per plan; can expand depth later
     let conn = db.0.lock();
-    let _ = depth; // reserved for future use
     let mut stmt = conn
         .prepare(
             "SELECT DISTINCT d.id, d.slug, d.title FROM (
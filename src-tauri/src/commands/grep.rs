@@ -0,0 +1,260 @@
+//! Regex search over current doc bodies
+
+use crate::db::Db;
+use globset::{Glob, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tauri::{Emitter, State};
+use uuid::Uuid;
+
+/// Docs read per lock acquisition; also the streaming batch granularity.
+const GREP_BATCH_DOCS: usize = 50;
+
+#[derive(Deserialize)]
+pub struct GrepRequest {
+    pub pattern: String,
+    pub repo_id: Option<String>,
+    /// Folder path relative to the repo root; includes subfolders
+    pub folder: Option<String>,
+    /// Glob over the doc path (`slug` with `__` as `/`, plus `.md`), e.g. `notes/**/*.md`
+    pub glob: Option<String>,
+    pub case_insensitive: Option<bool>,
+    /// Lines of context before and after each match (default 2)
+    pub context: Option<usize>,
+    /// Stop after this many matches (default 1000)
+    pub limit: Option<usize>,
+    /// Emit `search.grep` events (IPC) or server-sent events (sidecar) per batch instead of
+    /// returning matches
+    pub stream: Option<bool>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct GrepMatch {
+    pub doc_id: String,
+    pub slug: String,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column in chars
+    pub column: usize,
+    pub text: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct GrepReport {
+    pub search_id: String,
+    pub docs_scanned: usize,
+    pub match_count: usize,
+    pub truncated: bool,
+    pub matches: Vec<GrepMatch>,
+}
+
+#[tauri::command]
+pub async fn search_grep(
+    payload: GrepRequest,
+    db: State<'_, std::sync::Arc<Db>>,
+    app: tauri::AppHandle,
+) -> Result<GrepReport, String> {
+    let search_id = Uuid::new_v4().to_string();
+    if !payload.stream.unwrap_or(false) {
+        return grep_core(&db, &payload, search_id, |_| true);
+    }
+    let sid = search_id.clone();
+    let report = grep_core(&db, &payload, search_id, |batch| {
+        let _ = app.emit(
+            "search.grep",
+            serde_json::json!({"search_id": sid, "event": "matches", "matches": batch}),
+        );
+        true
+    })?;
+    let _ = app.emit(
+        "search.grep",
+        serde_json::json!({"search_id": report.search_id, "event": "done", "match_count": report.match_count, "truncated": report.truncated}),
+    );
+    Ok(report)
+}
+
+/// Run a grep. Each non-empty batch is passed to `on_batch`, which returns false to stop
+/// the scan; matches are only collected into the report when `stream` is off.
+pub fn grep_core(
+    db: &Db,
+    req: &GrepRequest,
+    search_id: String,
+    mut on_batch: impl FnMut(&[GrepMatch]) -> bool,
+) -> Result<GrepReport, String> {
+    let re = RegexBuilder::new(&req.pattern)
+        .case_insensitive(req.case_insensitive.unwrap_or(false))
+        .build()
+        .map_err(|e| format!("invalid_regex: {}", e))?;
+    let glob: Option<GlobMatcher> = match req.glob.as_deref() {
+        Some(g) if !g.is_empty() => Some(
+            Glob::new(g)
+                .map_err(|e| format!("invalid_glob: {}", e))?
+                .compile_matcher(),
+        ),
+        _ => None,
+    };
+    let folder = req
+        .folder
+        .as_deref()
+        .map(|f| f.trim_matches('/').to_string())
+        .filter(|f| !f.is_empty());
+    let context = req.context.unwrap_or(2);
+    let limit = req.limit.unwrap_or(1000);
+    let collect = !req.stream.unwrap_or(false);
+
+    // Candidate docs first, so bodies can be read in short lock windows
+    let docs: Vec<(String, String)> = {
+        let conn = db.0.lock();
+        let mut stmt = conn
+            .prepare(
+                "SELECT d.id, d.slug FROM doc d JOIN folder f ON f.id=d.folder_id \
+                 WHERE d.is_deleted=0 AND (?1 IS NULL OR d.repo_id=?1) \
+                 AND (?2 IS NULL OR f.path=?2 OR f.path LIKE ?2 || '/%') \
+                 ORDER BY d.slug",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![req.repo_id, folder], |r| Ok((r.get(0)?, r.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.filter_map(|r| r.ok())
+            .filter(|(_, slug): &(String, String)| glob.as_ref().is_none_or(|g| g.is_match(doc_path(slug))))
+            .collect()
+    };
+
+    let mut report = GrepReport {
+        search_id,
+        docs_scanned: 0,
+        match_count: 0,
+        truncated: false,
+        matches: vec![],
+    };
+    for chunk in docs.chunks(GREP_BATCH_DOCS) {
+        let bodies: Vec<(String, String, String)> = {
+            let conn = db.0.lock();
+            chunk
+                .iter()
+                .filter_map(|(id, slug)| {
+                    conn.query_row(
+                        "SELECT v.blob_id FROM doc d JOIN doc_version v ON v.id=d.current_version_id WHERE d.id=?1",
+                        params![id],
+                        |r| r.get::<_, String>(0),
+                    )
+                    .ok()
+                    .and_then(|blob_id| crate::blob::get_text(&conn, &blob_id).ok())
+                    .map(|body| (id.clone(), slug.clone(), body))
+                })
+                .collect()
+        };
+        let mut batch = Vec::new();
+        for (id, slug, body) in bodies {
+            report.docs_scanned += 1;
+            // One past what is left, to tell whether the limit cut anything off
+            for m in grep_body(&re, &body, context, (limit - report.match_count).saturating_add(1)) {
+                if report.match_count >= limit {
+                    report.truncated = true;
+                    break;
+                }
+                report.match_count += 1;
+                batch.push(GrepMatch {
+                    doc_id: id.clone(),
+                    slug: slug.clone(),
+                    line: m.line,
+                    column: m.column,
+                    text: m.text,
+                    before: m.before,
+                    after: m.after,
+                });
+            }
+            if report.truncated {
+                break;
+            }
+        }
+        if !batch.is_empty() {
+            let go_on = on_batch(&batch);
+            if collect {
+                report.matches.extend(batch);
+            }
+            if !go_on {
+                break;
+            }
+        }
+        if report.truncated {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+/// Repo-relative path a doc glob is matched against.
+fn doc_path(slug: &str) -> String {
+    format!("{}.md", slug.replace("__", "/"))
+}
+
+struct LineMatch {
+    line: usize,
+    column: usize,
+    text: String,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+/// The first `max` matches in `body` with their 1-based line/column and surrounding lines.
+fn grep_body(re: &Regex, body: &str, context: usize, max: usize) -> Vec<LineMatch> {
+    let lines: Vec<&str> = body.lines().collect();
+    let mut out = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        for m in re.find_iter(line) {
+            // Skip zero-width hits inside text (e.g. `a*`); still report blank lines for `^$`
+            if m.as_str().is_empty() && !line.is_empty() {
+                continue;
+            }
+            if out.len() == max {
+                return out;
+            }
+            out.push(LineMatch {
+                line: i + 1,
+                column: line[..m.start()].chars().count() + 1,
+                text: line.to_string(),
+                before: lines[i.saturating_sub(context)..i].iter().map(|s| s.to_string()).collect(),
+                after: lines[(i + 1).min(lines.len())..(i + 1 + context).min(lines.len())]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+            });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grep_body_positions_and_context() {
+        let re = Regex::new(r"to\w+").unwrap();
+        let body = "# Title\nfirst line\nsay tokio and tower\nlast";
+        let hits = grep_body(&re, body, 1, usize::MAX);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(grep_body(&re, body, 1, 1).len(), 1);
+        assert_eq!((hits[0].line, hits[0].column), (3, 5));
+        assert_eq!(hits[1].column, 15);
+        assert_eq!(hits[0].before, vec!["first line"]);
+        assert_eq!(hits[0].after, vec!["last"]);
+        // Columns count chars, not bytes
+        let hits = grep_body(&re, "é tokio", 0, usize::MAX);
+        assert_eq!(hits[0].column, 3);
+        assert!(hits[0].before.is_empty());
+    }
+
+    #[test]
+    fn test_doc_path_for_glob() {
+        assert_eq!(doc_path("notes__daily__2024-01-01"), "notes/daily/2024-01-01.md");
+        let g = Glob::new("notes/**/*.md").unwrap().compile_matcher();
+        assert!(g.is_match(doc_path("notes__daily__x")));
+        assert!(!g.is_match(doc_path("other")));
+    }
+}
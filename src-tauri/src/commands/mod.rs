@@ -3,23 +3,43 @@
 // Module declarations - these are kept private since we re-export their contents
 mod ai;
 mod anchor;
+mod comment;
 mod doc;
 mod export;
+mod folder;
 mod graph;
+mod grep;
 mod plugin;
+mod prompt;
 mod repo;
+mod saved_search;
 mod scan;
 mod search;
 mod settings;
+mod storage;
+mod suggestion;
+mod template;
+mod trash;
+mod version;
 
 // Re-export all items from each module (including Tauri-generated __cmd__ items)
 pub use ai::*;
 pub use anchor::*;
+pub use comment::*;
 pub use doc::*;
 pub use export::*;
+pub use folder::*;
 pub use graph::*;
+pub use grep::*;
 pub use plugin::*;
+pub use prompt::*;
 pub use repo::*;
+pub use saved_search::*;
 pub use scan::*;
 pub use search::*;
 pub use settings::*;
+pub use storage::*;
+pub use suggestion::*;
+pub use template::*;
+pub use trash::*;
+pub use version::*;
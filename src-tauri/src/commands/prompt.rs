@@ -0,0 +1,367 @@
+//! Prompt templates for `ai_run`
+//!
+//! A template has a `system` and a `user` part and is stored app-wide or for one repo; a
+//! repo's template overrides the app-wide one of the same name. Both parts may use
+//! `{{prompt}}`, `{{selection}}`, `{{doc.title}}`, `{{doc.slug}}`, `{{doc.id}}`,
+//! `{{doc.body}}`, `{{section}}`, `{{backlinks}}`, `{{outbound}}`, `{{context}}` (the
+//! assembled context, see `ai::context`) and `{{date}}`; other names come from the caller's
+//! `vars`. Files for import/export are JSON: `{ name, description?, system?, user, context? }`.
+
+use crate::db::Db;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tauri::State;
+use uuid::Uuid;
+
+/// Variables filled in by `ai_run` itself.
+pub const BUILTIN_VARS: &[&str] = &[
+    "prompt", "selection", "doc.title", "doc.slug", "doc.id", "doc.body", "section", "backlinks", "outbound", "context",
+    "date",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PromptTemplate {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub user: String,
+    /// Context strategies for runs using this template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct PromptTemplateInfo {
+    #[serde(flatten)]
+    pub template: PromptTemplate,
+    /// The repo it belongs to; None for app-wide templates
+    pub repo_id: Option<String>,
+    /// Placeholder names used, in order of first use
+    pub vars: Vec<String>,
+    /// The ones `ai_run` callers fill through `vars`
+    pub inputs: Vec<String>,
+    pub updated_at: String,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Placeholder names used by a template, in order of first use.
+pub fn template_vars(t: &PromptTemplate) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for text in [t.system.as_deref().unwrap_or_default(), t.user.as_str()] {
+        for (_, _, name, _) in super::placeholders(text) {
+            if !out.contains(&name) {
+                out.push(name);
+            }
+        }
+    }
+    out
+}
+
+/// Canonical repo id for an id or name; `Ok(None)` for app-wide.
+fn resolve_repo(conn: &Connection, repo_id: Option<&str>) -> Result<Option<String>, String> {
+    match repo_id {
+        None => Ok(None),
+        Some(r) => conn
+            .query_row("SELECT id FROM repo WHERE id=?1 OR name=?1", params![r], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "not_found".to_string())
+            .map(Some),
+    }
+}
+
+fn info_row(r: &rusqlite::Row) -> rusqlite::Result<PromptTemplateInfo> {
+    let context: Option<String> = r.get(4)?;
+    let template = PromptTemplate {
+        name: r.get(0)?,
+        description: r.get(1)?,
+        system: r.get(2)?,
+        user: r.get(3)?,
+        context: context.and_then(|c| serde_json::from_str(&c).ok()),
+    };
+    let vars = template_vars(&template);
+    Ok(PromptTemplateInfo {
+        inputs: vars.iter().filter(|v| !BUILTIN_VARS.contains(&v.as_str())).cloned().collect(),
+        vars,
+        template,
+        repo_id: r.get(5)?,
+        updated_at: r.get(6)?,
+    })
+}
+
+const TEMPLATE_COLUMNS: &str = "name, description, system, user, context, repo_id, updated_at";
+
+/// Templates in effect for a repo (its own, then app-wide ones it doesn't override), by name.
+/// Without a repo, the app-wide ones.
+fn effective_templates(conn: &Connection, repo_id: Option<&str>) -> Result<Vec<PromptTemplateInfo>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM prompt_template t WHERE repo_id IS ?1 \
+             OR (repo_id IS NULL AND NOT EXISTS (SELECT 1 FROM prompt_template o WHERE o.repo_id IS ?1 AND o.name=t.name)) \
+             ORDER BY name",
+            TEMPLATE_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![repo_id], info_row).map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+/// The template a run in `repo_id` gets for `name`.
+pub(crate) fn find_prompt_template(
+    conn: &Connection,
+    repo_id: &str,
+    name: &str,
+) -> Result<Option<PromptTemplate>, String> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM prompt_template WHERE name=?2 AND (repo_id=?1 OR repo_id IS NULL) ORDER BY repo_id IS NULL LIMIT 1",
+            TEMPLATE_COLUMNS
+        ),
+        params![repo_id, name],
+        info_row,
+    )
+    .optional()
+    .map(|t| t.map(|i| i.template))
+    .map_err(|e| e.to_string())
+}
+
+/// Fill placeholders from `vars`. Errors `missing_template_vars: <names>` for names without a value.
+pub(crate) fn render_prompt(text: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut missing: Vec<String> = Vec::new();
+    let mut last = 0;
+    for (start, end, name, _) in super::placeholders(text) {
+        out.push_str(&text[last..start]);
+        last = end;
+        match vars.get(&name) {
+            Some(v) => out.push_str(v),
+            None if !missing.contains(&name) => missing.push(name),
+            None => {}
+        }
+    }
+    out.push_str(&text[last..]);
+    if missing.is_empty() {
+        Ok(out)
+    } else {
+        Err(format!("missing_template_vars: {}", missing.join(", ")))
+    }
+}
+
+/// `- Title (slug)` per doc linking to `doc_id` (`inbound`) or linked from it, by title.
+pub(crate) fn linked_doc_list(conn: &Connection, doc_id: &str, inbound: bool) -> Result<String, String> {
+    let sql = if inbound {
+        "SELECT d.title, d.slug FROM link l JOIN doc d ON d.id=l.from_doc_id WHERE l.to_doc_id=?1 AND d.is_deleted=0 GROUP BY d.id ORDER BY d.title"
+    } else {
+        "SELECT d.title, d.slug FROM link l JOIN doc d ON d.id=l.to_doc_id WHERE l.from_doc_id=?1 AND d.is_deleted=0 GROUP BY d.id ORDER BY d.title"
+    };
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![doc_id], |r| Ok(format!("- {} ({})", r.get::<_, String>(0)?, r.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?;
+    Ok(rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?.join("\n"))
+}
+
+fn upsert(conn: &Connection, repo_id: Option<&str>, t: &PromptTemplate) -> Result<(), String> {
+    if !valid_name(&t.name) {
+        return Err("invalid_name".into());
+    }
+    if let Some(strategies) = &t.context {
+        crate::ai::context::validate_strategies(strategies)?;
+    }
+    let context = t.context.as_ref().map(|c| serde_json::json!(c).to_string());
+    let n = conn
+        .execute(
+            "UPDATE prompt_template SET description=?3, system=?4, user=?5, context=?6, updated_at=datetime('now') \
+             WHERE repo_id IS ?1 AND name=?2",
+            params![repo_id, t.name, t.description, t.system, t.user, context],
+        )
+        .map_err(|e| e.to_string())?;
+    if n == 0 {
+        conn.execute(
+            "INSERT INTO prompt_template(id,repo_id,name,description,system,user,context) VALUES(?,?,?,?,?,?,?)",
+            params![Uuid::new_v4().to_string(), repo_id, t.name, t.description, t.system, t.user, context],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn ai_templates_list(
+    repo_id: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<PromptTemplateInfo>, String> {
+    ai_templates_list_core(&db, repo_id.as_deref())
+}
+
+pub fn ai_templates_list_core(db: &Db, repo_id: Option<&str>) -> Result<Vec<PromptTemplateInfo>, String> {
+    let conn = db.0.lock();
+    let repo = resolve_repo(&conn, repo_id)?;
+    effective_templates(&conn, repo.as_deref())
+}
+
+#[tauri::command]
+pub async fn ai_templates_upsert(
+    repo_id: Option<String>,
+    template: PromptTemplate,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    ai_templates_upsert_core(&db, repo_id.as_deref(), &template)
+}
+
+/// Create or replace a template, for a repo or app-wide.
+pub fn ai_templates_upsert_core(db: &Db, repo_id: Option<&str>, template: &PromptTemplate) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let repo = resolve_repo(&conn, repo_id)?;
+    upsert(&conn, repo.as_deref(), template)?;
+    Ok(serde_json::json!({"name": template.name, "repo_id": repo}))
+}
+
+#[tauri::command]
+pub async fn ai_templates_delete(
+    name: String,
+    repo_id: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    ai_templates_delete_core(&db, &name, repo_id.as_deref())
+}
+
+/// Delete a template from its scope only; deleting a repo override brings back the app-wide one.
+pub fn ai_templates_delete_core(db: &Db, name: &str, repo_id: Option<&str>) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let repo = resolve_repo(&conn, repo_id)?;
+    let n = conn
+        .execute("DELETE FROM prompt_template WHERE repo_id IS ?1 AND name=?2", params![repo, name])
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"deleted": n > 0}))
+}
+
+#[tauri::command]
+pub async fn ai_templates_export(
+    dir: String,
+    repo_id: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    ai_templates_export_core(&db, Path::new(&dir), repo_id.as_deref())
+}
+
+/// Write the templates in effect (for the repo, else app-wide) as `<dir>/<name>.json`.
+pub fn ai_templates_export_core(db: &Db, dir: &Path, repo_id: Option<&str>) -> Result<serde_json::Value, String> {
+    let templates = ai_templates_list_core(db, repo_id)?;
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let mut written = Vec::new();
+    for info in templates {
+        let path = dir.join(format!("{}.json", info.template.name));
+        let text = serde_json::to_string_pretty(&info.template).map_err(|e| e.to_string())?;
+        std::fs::write(&path, text + "\n").map_err(|e| e.to_string())?;
+        written.push(path.to_string_lossy().to_string());
+    }
+    Ok(serde_json::json!({"written": written}))
+}
+
+#[tauri::command]
+pub async fn ai_templates_import(
+    path: String,
+    repo_id: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    ai_templates_import_core(&db, Path::new(&path), repo_id.as_deref())
+}
+
+/// Import one template file, or every `*.json` file of a directory, into a repo or app-wide.
+/// Existing templates of the same name are replaced; files that fail to parse are reported
+/// in `errors` and skipped.
+pub fn ai_templates_import_core(db: &Db, path: &Path, repo_id: Option<&str>) -> Result<serde_json::Value, String> {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+            .map_err(|e| e.to_string())?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|x| x == "json"))
+            .collect();
+        files.sort();
+        files
+    } else if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        return Err("not_found".into());
+    };
+    let conn = db.0.lock();
+    let repo = resolve_repo(&conn, repo_id)?;
+    let mut imported = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        let parsed = std::fs::read_to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str::<PromptTemplate>(&text).map_err(|e| e.to_string()))
+            .and_then(|t| upsert(&conn, repo.as_deref(), &t).map(|_| t.name));
+        match parsed {
+            Ok(name) => imported.push(name),
+            Err(e) => errors.push(serde_json::json!({"path": file.to_string_lossy(), "error": e})),
+        }
+    }
+    Ok(serde_json::json!({"imported": imported, "errors": errors}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_db;
+
+    #[test]
+    fn test_templates_override_render_and_files() {
+        let db = temp_db("prompt");
+        db.0.lock().execute("INSERT INTO repo(id,name,path) VALUES('r','notes','/r')", []).unwrap();
+        let t = |user: &str| PromptTemplate {
+            name: "summarize".into(),
+            description: None,
+            system: Some("You summarize {{doc.title}}.".into()),
+            user: user.into(),
+            context: Some(vec!["section".into()]),
+        };
+        ai_templates_upsert_core(&db, None, &t("Summarize:\n{{selection}}")).unwrap();
+        ai_templates_upsert_core(&db, Some("notes"), &t("Briefly:\n{{selection}} {{tone}}")).unwrap();
+        ai_templates_upsert_core(&db, None, &t("Summarize again:\n{{selection}}")).unwrap();
+
+        let app = ai_templates_list_core(&db, None).unwrap();
+        assert_eq!(app.len(), 1);
+        assert_eq!(app[0].template.user, "Summarize again:\n{{selection}}");
+        let repo = ai_templates_list_core(&db, Some("r")).unwrap();
+        assert_eq!((repo.len(), repo[0].repo_id.as_deref()), (1, Some("r")));
+        assert_eq!(repo[0].vars, vec!["doc.title", "selection", "tone"]);
+        assert_eq!(repo[0].inputs, vec!["tone"]);
+
+        let conn = db.0.lock();
+        let found = find_prompt_template(&conn, "r", "summarize").unwrap().unwrap();
+        let mut vars = HashMap::new();
+        vars.insert("selection".to_string(), "text".to_string());
+        assert_eq!(render_prompt(&found.user, &vars).unwrap_err(), "missing_template_vars: tone");
+        vars.insert("tone".to_string(), "(dry)".to_string());
+        assert_eq!(render_prompt(&found.user, &vars).unwrap(), "Briefly:\ntext (dry)");
+        drop(conn);
+
+        let dir = std::env::temp_dir().join(format!("ae-prompts-{}", Uuid::new_v4()));
+        let out = ai_templates_export_core(&db, &dir, Some("r")).unwrap();
+        assert_eq!(out["written"].as_array().unwrap().len(), 1);
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        ai_templates_delete_core(&db, "summarize", Some("r")).unwrap();
+        assert_eq!(ai_templates_list_core(&db, Some("r")).unwrap()[0].repo_id, None);
+        let res = ai_templates_import_core(&db, &dir, Some("r")).unwrap();
+        assert_eq!(res["imported"], serde_json::json!(["summarize"]));
+        assert_eq!(res["errors"].as_array().unwrap().len(), 1);
+        let repo = ai_templates_list_core(&db, Some("r")).unwrap();
+        assert_eq!(repo[0].template, t("Briefly:\n{{selection}} {{tone}}"));
+
+        let mut bad = t("x");
+        bad.name = "../x".into();
+        assert_eq!(ai_templates_upsert_core(&db, None, &bad).unwrap_err(), "invalid_name");
+    }
+}
@@ -111,3 +111,38 @@ pub async fn repos_set_default_provider(
         .map_err(|e| e.to_string())?;
     Ok(serde_json::json!({"updated": n>0}))
 }
+
+#[tauri::command]
+pub async fn repos_set_fallback_providers(
+    id_or_name: String,
+    providers: Vec<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    repos_set_fallback_providers_core(&db, &id_or_name, &providers)
+}
+
+/// Providers `ai_run` tries, in order, when the resolved one fails; an empty list removes them.
+pub fn repos_set_fallback_providers_core(
+    db: &Db,
+    id_or_name: &str,
+    providers: &[String],
+) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    for p in providers {
+        let known: bool = conn
+            .query_row("SELECT 1 FROM provider WHERE name=?1", params![p], |_| Ok(()))
+            .is_ok();
+        if !known {
+            return Err(format!("provider_not_found: {}", p));
+        }
+    }
+    let value = serde_json::to_string(providers).map_err(|e| e.to_string())?;
+    let n = conn
+        .execute(
+            "UPDATE repo SET settings=CASE WHEN ?2='[]' THEN json_remove(COALESCE(settings,json('{}')),'$.fallback_providers') \
+             ELSE json_set(COALESCE(settings,json('{}')),'$.fallback_providers',json(?2)) END, updated_at=datetime('now') WHERE id=?1 OR name=?1",
+            params![id_or_name, value],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"updated": n>0}))
+}
@@ -0,0 +1,413 @@
+//! Faceted search and saved searches
+
+use super::search::{search_core, SearchHit, SearchRequest};
+use crate::db::Db;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tauri::State;
+use uuid::Uuid;
+
+/// Upper bound on matches considered for totals, facets and watch diffs; past it results
+/// are flagged `truncated`.
+const FACET_WINDOW: i64 = 10_000;
+
+#[derive(Deserialize)]
+pub struct FacetedSearchRequest {
+    #[serde(flatten)]
+    pub search: SearchRequest,
+    /// Folder path relative to the repo root; includes subfolders
+    pub folder: Option<String>,
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FacetCount {
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub count: i64,
+}
+
+#[derive(Serialize)]
+pub struct Facets {
+    pub repo: Vec<FacetCount>,
+    pub folder: Vec<FacetCount>,
+    pub tag: Vec<FacetCount>,
+}
+
+#[derive(Serialize)]
+pub struct FacetedResult {
+    pub total: usize,
+    /// More than `FACET_WINDOW` matches: `total` and the facet counts cover only the first ones
+    pub truncated: bool,
+    pub hits: Vec<SearchHit>,
+    pub facets: Facets,
+}
+
+#[derive(Serialize)]
+pub struct SavedSearch {
+    pub id: String,
+    pub name: String,
+    pub params: serde_json::Value,
+    pub watch: bool,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SavedSearchChange {
+    pub name: String,
+    pub total: usize,
+    pub truncated: bool,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[tauri::command]
+pub async fn search_faceted(
+    payload: FacetedSearchRequest,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<FacetedResult, String> {
+    search_faceted_core(&db, payload)
+}
+
+/// Run a search over the full match set (up to `FACET_WINDOW`), apply folder/tag filters,
+/// count facets, then page the hits with the request's limit/offset.
+pub fn search_faceted_core(db: &Db, req: FacetedSearchRequest) -> Result<FacetedResult, String> {
+    let lim = req.search.limit.unwrap_or(50).max(0) as usize;
+    let off = req.search.offset.unwrap_or(0).max(0) as usize;
+    // One past the window tells whether it cut the match set short
+    let mut all = search_core(
+        db,
+        SearchRequest {
+            limit: Some(FACET_WINDOW + 1),
+            offset: Some(0),
+            ..req.search
+        },
+    )?;
+    let truncated = all.len() > FACET_WINDOW as usize;
+    all.truncate(FACET_WINDOW as usize);
+    let conn = db.0.lock();
+    let ids = ids_json(all.iter().map(|h| h.id.as_str()));
+    let folder = req
+        .folder
+        .as_deref()
+        .map(|f| f.trim_matches('/').to_string())
+        .filter(|f| !f.is_empty());
+    let hits: Vec<SearchHit> = if folder.is_some() || req.tag.is_some() {
+        let keep: HashSet<String> = query_strings(
+            &conn,
+            "SELECT d.id FROM doc d JOIN folder f ON f.id=d.folder_id \
+             WHERE d.id IN (SELECT value FROM json_each(?1)) \
+             AND (?2 IS NULL OR f.path=?2 OR f.path LIKE ?2 || '/%') \
+             AND (?3 IS NULL OR EXISTS(SELECT 1 FROM doc_tag t WHERE t.doc_id=d.id AND t.tag=lower(?3)))",
+            params![ids, folder, req.tag],
+        )?
+        .into_iter()
+        .collect();
+        all.into_iter().filter(|h| keep.contains(&h.id)).collect()
+    } else {
+        all
+    };
+    let ids = ids_json(hits.iter().map(|h| h.id.as_str()));
+    let facets = Facets {
+        repo: facet_counts(
+            &conn,
+            "SELECT d.repo_id, r.name, COUNT(*) FROM doc d JOIN repo r ON r.id=d.repo_id \
+             WHERE d.id IN (SELECT value FROM json_each(?1)) GROUP BY d.repo_id ORDER BY 3 DESC, 2",
+            &ids,
+        )?,
+        folder: facet_counts(
+            &conn,
+            "SELECT f.path, NULL, COUNT(*) FROM doc d JOIN folder f ON f.id=d.folder_id \
+             WHERE d.id IN (SELECT value FROM json_each(?1)) GROUP BY f.path ORDER BY 3 DESC, 1",
+            &ids,
+        )?,
+        tag: facet_counts(
+            &conn,
+            "SELECT tag, NULL, COUNT(*) FROM doc_tag \
+             WHERE doc_id IN (SELECT value FROM json_each(?1)) GROUP BY tag ORDER BY 3 DESC, 1",
+            &ids,
+        )?,
+    };
+    Ok(FacetedResult {
+        total: hits.len(),
+        truncated,
+        hits: hits.into_iter().skip(off).take(lim).collect(),
+        facets,
+    })
+}
+
+#[tauri::command]
+pub async fn saved_search_save(
+    name: String,
+    params: serde_json::Value,
+    watch: Option<bool>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    saved_search_save_core(&db, &name, params, watch.unwrap_or(false))
+}
+
+/// Create or replace a saved search. Watched searches snapshot their current result set.
+pub fn saved_search_save_core(
+    db: &Db,
+    name: &str,
+    params: serde_json::Value,
+    watch: bool,
+) -> Result<serde_json::Value, String> {
+    if name.trim().is_empty() {
+        return Err("invalid_name".into());
+    }
+    // Validate by running it; also gives the watch baseline
+    let ids = if watch {
+        Some(result_ids(db, &params)?.0)
+    } else {
+        parse_request(&params)?;
+        None
+    };
+    let conn = db.0.lock();
+    let id = Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO saved_search(id,name,params,watch,last_ids) VALUES(?1,?2,?3,?4,?5) \
+         ON CONFLICT(name) DO UPDATE SET params=excluded.params, watch=excluded.watch, last_ids=excluded.last_ids, updated_at=datetime('now')",
+        params![
+            id,
+            name,
+            params.to_string(),
+            watch as i64,
+            ids.map(|v| serde_json::json!(v).to_string())
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    let id: String = conn
+        .query_row("SELECT id FROM saved_search WHERE name=?1", params![name], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"id": id, "name": name, "watch": watch}))
+}
+
+#[tauri::command]
+pub async fn saved_search_list(db: State<'_, std::sync::Arc<Db>>) -> Result<Vec<SavedSearch>, String> {
+    saved_search_list_core(&db)
+}
+
+pub fn saved_search_list_core(db: &Db) -> Result<Vec<SavedSearch>, String> {
+    let conn = db.0.lock();
+    let mut stmt = conn
+        .prepare("SELECT id,name,params,watch,created_at,updated_at FROM saved_search ORDER BY name")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |r| {
+            Ok(SavedSearch {
+                id: r.get(0)?,
+                name: r.get(1)?,
+                params: serde_json::from_str(&r.get::<_, String>(2)?).unwrap_or_default(),
+                watch: r.get::<_, i64>(3)? != 0,
+                created_at: r.get(4)?,
+                updated_at: r.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    for r in rows {
+        out.push(r.map_err(|e| e.to_string())?)
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub async fn saved_search_delete(
+    name: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    saved_search_delete_core(&db, &name)
+}
+
+pub fn saved_search_delete_core(db: &Db, name: &str) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let n = conn
+        .execute("DELETE FROM saved_search WHERE name=?1", params![name])
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"deleted": n}))
+}
+
+#[tauri::command]
+pub async fn saved_search_run(
+    name: String,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<FacetedResult, String> {
+    saved_search_run_core(&db, &name, limit, offset)
+}
+
+pub fn saved_search_run_core(
+    db: &Db,
+    name: &str,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<FacetedResult, String> {
+    let params = saved_params(db, name)?.ok_or("not_found")?;
+    let mut req = parse_request(&params)?;
+    if limit.is_some() {
+        req.search.limit = limit;
+    }
+    if offset.is_some() {
+        req.search.offset = offset;
+    }
+    search_faceted_core(db, req)
+}
+
+/// Re-run watched saved searches and record those whose result set changed.
+/// Called after scans; callers decide how to notify (Tauri event or RPC response).
+pub fn saved_searches_check(db: &Db) -> Result<Vec<SavedSearchChange>, String> {
+    let watched: Vec<(String, String, Option<String>)> = {
+        let conn = db.0.lock();
+        let mut stmt = conn
+            .prepare("SELECT name, params, last_ids FROM saved_search WHERE watch=1 ORDER BY name")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .map_err(|e| e.to_string())?;
+        rows.filter_map(|r| r.ok()).collect()
+    };
+    let mut changes = Vec::new();
+    for (name, params, last) in watched {
+        let params: serde_json::Value = serde_json::from_str(&params).unwrap_or_default();
+        let Ok((ids, truncated)) = result_ids(db, &params) else { continue };
+        let last: Vec<String> = last
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        let added: Vec<String> = ids.iter().filter(|id| !last.contains(id)).cloned().collect();
+        let removed: Vec<String> = last.iter().filter(|id| !ids.contains(id)).cloned().collect();
+        if added.is_empty() && removed.is_empty() {
+            continue;
+        }
+        db.0.lock()
+            .execute(
+                "UPDATE saved_search SET last_ids=?2, updated_at=datetime('now') WHERE name=?1",
+                params![name, serde_json::json!(ids).to_string()],
+            )
+            .map_err(|e| e.to_string())?;
+        changes.push(SavedSearchChange {
+            name,
+            total: ids.len(),
+            truncated,
+            added,
+            removed,
+        });
+    }
+    Ok(changes)
+}
+
+fn parse_request(params: &serde_json::Value) -> Result<FacetedSearchRequest, String> {
+    serde_json::from_value(params.clone()).map_err(|e| format!("invalid_params: {}", e))
+}
+
+fn saved_params(db: &Db, name: &str) -> Result<Option<serde_json::Value>, String> {
+    let conn = db.0.lock();
+    let params: Option<String> = conn
+        .query_row("SELECT params FROM saved_search WHERE name=?1", params![name], |r| r.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    Ok(params.and_then(|p| serde_json::from_str(&p).ok()))
+}
+
+/// Sorted doc ids of the full (unpaged) result set, and whether it was truncated.
+fn result_ids(db: &Db, params: &serde_json::Value) -> Result<(Vec<String>, bool), String> {
+    let mut req = parse_request(params)?;
+    req.search.limit = Some(FACET_WINDOW);
+    req.search.offset = Some(0);
+    let res = search_faceted_core(db, req)?;
+    let mut ids: Vec<String> = res.hits.into_iter().map(|h| h.id).collect();
+    ids.sort();
+    Ok((ids, res.truncated))
+}
+
+fn ids_json<'a>(ids: impl Iterator<Item = &'a str>) -> String {
+    serde_json::json!(ids.collect::<Vec<_>>()).to_string()
+}
+
+fn query_strings(
+    conn: &Connection,
+    sql: &str,
+    args: impl rusqlite::Params,
+) -> Result<Vec<String>, String> {
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(args, |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+fn facet_counts(conn: &Connection, sql: &str, ids: &str) -> Result<Vec<FacetCount>, String> {
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![ids], |r| {
+            Ok(FacetCount {
+                value: r.get(0)?,
+                label: r.get(1)?,
+                count: r.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    for r in rows {
+        out.push(r.map_err(|e| e.to_string())?)
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_db;
+
+    fn add_doc(db: &Db, id: &str, folder: &str, body: &str) {
+        let conn = db.0.lock();
+        conn.execute(
+            "INSERT INTO doc(id,repo_id,folder_id,slug,title) VALUES(?1,'r',?2,?1,?1)",
+            params![id, folder],
+        )
+        .unwrap();
+        crate::fts::insert_doc(&conn, id, body).unwrap();
+    }
+
+    fn request(query: &str, tag: Option<&str>) -> serde_json::Value {
+        serde_json::json!({"query": query, "tag": tag, "limit": 1})
+    }
+
+    #[test]
+    fn test_facets_and_watched_changes() {
+        let db = temp_db("facet-test");
+        {
+            let conn = db.0.lock();
+            conn.execute("INSERT INTO repo(id,name,path) VALUES('r','notes','/r')", []).unwrap();
+            conn.execute("INSERT INTO folder(id,repo_id,path,slug) VALUES('f1','r','a','a')", []).unwrap();
+            conn.execute("INSERT INTO folder(id,repo_id,path,slug) VALUES('f2','r','b','b')", []).unwrap();
+        }
+        add_doc(&db, "d1", "f1", "rust tips #lang");
+        add_doc(&db, "d2", "f2", "rust and go #lang #go");
+
+        let res = search_faceted_core(&db, parse_request(&request("rust", None)).unwrap()).unwrap();
+        assert_eq!(res.total, 2);
+        assert!(!res.truncated);
+        assert_eq!(res.hits.len(), 1);
+        assert_eq!(res.facets.repo[0].label.as_deref(), Some("notes"));
+        assert_eq!(res.facets.folder.len(), 2);
+        assert_eq!((res.facets.tag[0].value.as_str(), res.facets.tag[0].count), ("lang", 2));
+
+        let res = search_faceted_core(&db, parse_request(&request("rust", Some("Go"))).unwrap()).unwrap();
+        assert_eq!(res.total, 1);
+        assert_eq!(res.hits[0].id, "d2");
+
+        saved_search_save_core(&db, "rusty", request("rust", None), true).unwrap();
+        assert!(saved_searches_check(&db).unwrap().is_empty());
+        add_doc(&db, "d3", "f1", "more rust");
+        let changes = saved_searches_check(&db).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].total, changes[0].added.clone()), (3, vec!["d3".to_string()]));
+        assert!(saved_searches_check(&db).unwrap().is_empty());
+        assert_eq!(saved_search_run_core(&db, "rusty", Some(10), None).unwrap().hits.len(), 3);
+    }
+}
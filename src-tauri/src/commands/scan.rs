@@ -18,6 +18,8 @@ pub struct ScanJobReport {
     pub files_scanned: i64,
     pub docs_added: i64,
     pub errors: i64,
+    /// Files re-created at a trashed doc's path with a new body
+    pub collisions: Vec<scan::ScanCollision>,
 }
 
 #[tauri::command]
@@ -80,6 +82,8 @@ pub async fn scan_repo(
             ],
         )
         .map_err(|e| e.to_string())?;
+    drop(conn2);
+    notify_saved_searches(&db, &app);
     if watch.unwrap_or(false) {
         let _ = app.emit(
             "progress.scan",
@@ -99,5 +103,18 @@ pub async fn scan_repo(
         files_scanned: stats.files_scanned,
         docs_added: stats.docs_added,
         errors: stats.errors,
+        collisions: stats.collisions,
     })
 }
+
+/// Emit `search.saved.changed` for each watched saved search whose results moved.
+pub(crate) fn notify_saved_searches(db: &Db, app: &tauri::AppHandle) {
+    match super::saved_searches_check(db) {
+        Ok(changes) => {
+            for change in changes {
+                let _ = app.emit("search.saved.changed", change);
+            }
+        }
+        Err(e) => eprintln!("[search] saved search check failed: {}", e),
+    }
+}
@@ -1,11 +1,12 @@
 //! Full-text search commands
 
-use crate::db::Db;
-use rusqlite::params;
-use serde::Serialize;
+use crate::{ai::embed, db::Db, fts};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tauri::State;
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct SearchHit {
     pub id: String,
     pub slug: String,
@@ -14,29 +15,360 @@ pub struct SearchHit {
     pub rank: f64,
 }
 
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    pub repo_id: Option<String>,
+    pub query: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// `fts` (default, whole tokens via bm25), `substring`/`fuzzy` (trigram index) or `hybrid` (embeddings), merged with bm25
+    pub mode: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum SearchMode {
+    Fts,
+    Substring,
+    Fuzzy,
+    Hybrid,
+}
+
+impl SearchMode {
+    fn parse(mode: Option<&str>) -> Result<Self, String> {
+        match mode.unwrap_or("fts") {
+            "" | "fts" => Ok(SearchMode::Fts),
+            "substring" => Ok(SearchMode::Substring),
+            "fuzzy" => Ok(SearchMode::Fuzzy),
+            "hybrid" => Ok(SearchMode::Hybrid),
+            _ => Err("invalid_mode".into()),
+        }
+    }
+}
+
 #[tauri::command]
 pub async fn search(
     repo_id: Option<String>,
     query: String,
     limit: Option<i64>,
     offset: Option<i64>,
+    mode: Option<String>,
     db: State<'_, std::sync::Arc<Db>>,
 ) -> Result<Vec<SearchHit>, String> {
+    search_core(
+        &db,
+        SearchRequest {
+            repo_id,
+            query,
+            limit,
+            offset,
+            mode,
+        },
+    )
+}
+
+pub fn search_core(db: &Db, req: SearchRequest) -> Result<Vec<SearchHit>, String> {
+    let mode = SearchMode::parse(req.mode.as_deref())?;
+    let lim = req.limit.unwrap_or(50);
+    let off = req.offset.unwrap_or(0);
+    // Merged modes: take a window from each source, fuse by rank, then page
+    let window = (lim + off).max(1);
+    // Embed the query before taking the DB lock (the embedder may call out to a backend)
+    let semantic = if mode == SearchMode::Hybrid {
+        embed::semantic_search(db, req.repo_id.as_deref(), &req.query, window as usize)?
+    } else {
+        vec![]
+    };
+    let conn = db.0.lock();
+    if mode == SearchMode::Fts {
+        return bm25_hits(&conn, &req.query, req.repo_id.as_deref(), lim, off);
+    }
+    let primary = bm25_hits(&conn, &req.query, req.repo_id.as_deref(), window, 0).unwrap_or_default();
+    let secondary = match mode {
+        SearchMode::Substring => substring_hits(&conn, &req.query, req.repo_id.as_deref(), window)?,
+        SearchMode::Fuzzy => fuzzy_hits(&conn, &req.query, req.repo_id.as_deref(), window)?,
+        _ => semantic
+            .into_iter()
+            .map(|h| SearchHit {
+                id: h.id,
+                slug: h.slug,
+                title_snip: h.title,
+                body_snip: h.preview,
+                rank: -(h.score as f64),
+            })
+            .collect(),
+    };
+    let ids = [
+        primary.iter().map(|h| h.id.clone()).collect::<Vec<_>>(),
+        secondary.iter().map(|h| h.id.clone()).collect::<Vec<_>>(),
+    ];
+    let mut by_id: HashMap<String, SearchHit> =
+        secondary.into_iter().map(|h| (h.id.clone(), h)).collect();
+    for hit in primary {
+        // bm25 snippets win when present; trigram snippets fill the gaps
+        let has_snip = !hit.title_snip.is_empty() || !hit.body_snip.is_empty();
+        if has_snip || !by_id.contains_key(&hit.id) {
+            by_id.insert(hit.id.clone(), hit);
+        }
+    }
+    let mut out = Vec::new();
+    for (id, score) in fts::rrf_merge(&ids)
+        .into_iter()
+        .skip(off.max(0) as usize)
+        .take(lim.max(0) as usize)
+    {
+        if let Some(mut hit) = by_id.remove(&id) {
+            hit.rank = -score;
+            out.push(hit);
+        }
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub async fn search_semantic(
+    repo_id: Option<String>,
+    query: String,
+    limit: Option<usize>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<embed::SemanticHit>, String> {
+    embed::semantic_search(&db, repo_id.as_deref(), &query, limit.unwrap_or(20))
+}
+
+#[tauri::command]
+pub async fn embeddings_reindex(
+    repo_id: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    embed::reindex_all(&db, repo_id.as_deref())
+}
+
+#[tauri::command]
+pub async fn embeddings_status(db: State<'_, std::sync::Arc<Db>>) -> Result<embed::RefreshStatus, String> {
+    Ok(embed::refresh_status(&db))
+}
+
+#[derive(Serialize)]
+pub struct HistoryHit {
+    pub doc_id: String,
+    pub slug: String,
+    pub title: String,
+    pub version_id: String,
+    pub created_at: String,
+    pub message: Option<String>,
+    /// True when the match is in the doc's current version
+    pub is_current: bool,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+#[derive(Deserialize)]
+pub struct HistorySearchRequest {
+    pub query: String,
+    pub repo_id: Option<String>,
+    /// Doc id or slug
+    pub doc: Option<String>,
+    /// Inclusive bounds, `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[tauri::command]
+pub async fn search_history(
+    payload: HistorySearchRequest,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<HistoryHit>, String> {
+    search_history_core(&db, payload)
+}
+
+/// Matching versions, newest first. Errors with `history_index_disabled` until opted in.
+pub fn search_history_core(db: &Db, req: HistorySearchRequest) -> Result<Vec<HistoryHit>, String> {
     let conn = db.0.lock();
-    let lim = limit.unwrap_or(50);
-    let off = offset.unwrap_or(0);
-    let sql = String::from(
-        "SELECT d.id, d.slug, bm25(doc_fts, 1.2, 0.75) as rank, \
+    if !fts::history_enabled(&conn) {
+        return Err("history_index_disabled".into());
+    }
+    // A bare date as upper bound covers the whole day
+    let to = req.to.map(|t| if t.len() == 10 { format!("{} 23:59:59", t) } else { t });
+    let sql = "SELECT d.id, d.slug, d.title, v.id, v.created_at, v.message, v.id = d.current_version_id, \
+         snippet(doc_version_fts,0,'<b>','</b>','…',8), bm25(doc_version_fts) as rank \
+         FROM doc_version_fts JOIN doc_version v ON v.rowid=doc_version_fts.rowid JOIN doc d ON d.id=v.doc_id \
+         WHERE doc_version_fts MATCH ?1 AND d.is_deleted=0 AND (?2 IS NULL OR d.repo_id = ?2) \
+         AND (?3 IS NULL OR d.id = ?3 OR d.slug = ?3) \
+         AND (?4 IS NULL OR v.created_at >= datetime(?4)) AND (?5 IS NULL OR v.created_at <= datetime(?5)) \
+         ORDER BY v.created_at DESC, rank ASC LIMIT ?6 OFFSET ?7";
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(
+            params![
+                req.query,
+                req.repo_id,
+                req.doc,
+                req.from,
+                to,
+                req.limit.unwrap_or(50),
+                req.offset.unwrap_or(0)
+            ],
+            |r| {
+                Ok(HistoryHit {
+                    doc_id: r.get(0)?,
+                    slug: r.get(1)?,
+                    title: r.get(2)?,
+                    version_id: r.get(3)?,
+                    created_at: r.get(4)?,
+                    message: r.get(5)?,
+                    is_current: r.get(6)?,
+                    snippet: r.get::<_, String>(7).unwrap_or_default(),
+                    rank: r.get::<_, f64>(8).unwrap_or(0.0),
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    for r in rows {
+        out.push(r.map_err(|e| e.to_string())?)
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub async fn history_index_set(
+    enabled: bool,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    history_index_set_core(&db, enabled)
+}
+
+/// Toggle the history index; enabling backfills existing versions, disabling drops the index rows.
+pub fn history_index_set_core(db: &Db, enabled: bool) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    conn.execute(
+        "INSERT INTO app_setting(key,value) VALUES('history_index',?1) ON CONFLICT(key) DO UPDATE SET value=excluded.value, updated_at=datetime('now')",
+        params![serde_json::Value::Bool(enabled).to_string()],
+    )
+    .map_err(|e| e.to_string())?;
+    let indexed = if enabled {
+        fts::backfill_history(&conn)?
+    } else {
+        fts::clear_history(&conn)?;
+        0
+    };
+    Ok(serde_json::json!({"enabled": enabled, "indexed": indexed}))
+}
+
+fn bm25_hits(
+    conn: &Connection,
+    query: &str,
+    repo_id: Option<&str>,
+    lim: i64,
+    off: i64,
+) -> Result<Vec<SearchHit>, String> {
+    let primary = "SELECT d.id, d.slug, bm25(doc_fts, 1.2, 0.75) as rank, \
          snippet(doc_fts,1,'<b>','</b>','…',8) as title_snip, \
          snippet(doc_fts,2,'<b>','</b>','…',8) as body_snip \
          FROM doc_fts JOIN doc d ON d.rowid=doc_fts.rowid \
-         WHERE doc_fts MATCH ?1 AND (?2 IS NULL OR d.repo_id = ?2) \
-         ORDER BY rank ASC, d.updated_at DESC LIMIT ?3 OFFSET ?4",
-    );
-    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
-    let mut out = Vec::new();
+         WHERE doc_fts MATCH ?1 AND d.is_deleted=0 AND (?2 IS NULL OR d.repo_id = ?2) \
+         ORDER BY rank ASC, d.updated_at DESC LIMIT ?3 OFFSET ?4";
+    if let Ok(hits) = collect_hits(conn, primary, params![query, repo_id, lim, off]) {
+        return Ok(hits);
+    }
+    // Fallback without bm25/snippet to avoid env-specific FTS aux function issues
+    let simple = "SELECT d.id, d.slug, 0.0 as rank, '' as title_snip, '' as body_snip \
+         FROM doc_fts JOIN doc d ON d.rowid=doc_fts.rowid \
+         WHERE doc_fts MATCH ?1 AND d.is_deleted=0 AND (?2 IS NULL OR d.repo_id = ?2) \
+         ORDER BY d.updated_at DESC LIMIT ?3 OFFSET ?4";
+    collect_hits(conn, simple, params![query, repo_id, lim, off])
+}
+
+fn substring_hits(
+    conn: &Connection,
+    query: &str,
+    repo_id: Option<&str>,
+    lim: i64,
+) -> Result<Vec<SearchHit>, String> {
+    // Terms under 3 chars have no trigram: they filter with LIKE instead
+    let short = serde_json::json!(fts::short_term_patterns(query)).to_string();
+    match fts::substring_match_expr(query) {
+        Some(expr) => collect_hits(conn, SUBSTRING_SQL, params![expr, repo_id, lim, short]),
+        None if short != "[]" => collect_hits(conn, SHORT_TERMS_SQL, params![repo_id, lim, short]),
+        None => Ok(vec![]),
+    }
+}
+
+fn fuzzy_hits(
+    conn: &Connection,
+    query: &str,
+    repo_id: Option<&str>,
+    lim: i64,
+) -> Result<Vec<SearchHit>, String> {
+    let Some(expr) = fts::fuzzy_match_expr(query) else {
+        return Ok(vec![]);
+    };
+    let terms = fts::query_terms(query);
+    // Over-fetch candidates sharing any trigram, then keep those within edit-distance tolerance
+    let candidates = collect_hits(conn, TRIGRAM_SQL, params![expr, repo_id, (lim * 4).max(100)])?;
+    let ids = serde_json::json!(candidates.iter().map(|h| h.id.as_str()).collect::<Vec<_>>()).to_string();
+    let texts: HashMap<String, String> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT d.id, t.title || ' ' || t.slug || ' ' || t.body FROM doc_fts_tri t JOIN doc d ON d.rowid=t.rowid \
+                 WHERE d.id IN (SELECT value FROM json_each(?1))",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![ids], |r| Ok((r.get(0)?, r.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    let mut scored = Vec::new();
+    for hit in candidates {
+        let text = texts.get(&hit.id).map(String::as_str).unwrap_or_default();
+        let score = fts::fuzzy_score(&terms, text);
+        if score >= fts::FUZZY_MIN_SIMILARITY {
+            scored.push((score, hit));
+        }
+    }
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(scored
+        .into_iter()
+        .take(lim.max(0) as usize)
+        .map(|(_, h)| h)
+        .collect())
+}
+
+const TRIGRAM_SQL: &str = "SELECT d.id, d.slug, bm25(doc_fts_tri) as rank, \
+     snippet(doc_fts_tri,0,'<b>','</b>','…',8) as title_snip, \
+     snippet(doc_fts_tri,1,'<b>','</b>','…',8) as body_snip \
+     FROM doc_fts_tri JOIN doc d ON d.rowid=doc_fts_tri.rowid \
+     WHERE doc_fts_tri MATCH ?1 AND d.is_deleted=0 AND (?2 IS NULL OR d.repo_id = ?2) \
+     ORDER BY rank ASC, d.updated_at DESC LIMIT ?3";
+
+/// `TRIGRAM_SQL` that also requires every LIKE pattern of the JSON array `?4`.
+const SUBSTRING_SQL: &str = "SELECT d.id, d.slug, bm25(doc_fts_tri) as rank, \
+     snippet(doc_fts_tri,0,'<b>','</b>','…',8) as title_snip, \
+     snippet(doc_fts_tri,1,'<b>','</b>','…',8) as body_snip \
+     FROM doc_fts_tri JOIN doc d ON d.rowid=doc_fts_tri.rowid \
+     WHERE doc_fts_tri MATCH ?1 AND d.is_deleted=0 AND (?2 IS NULL OR d.repo_id = ?2) \
+     AND NOT EXISTS(SELECT 1 FROM json_each(?4) p WHERE NOT (doc_fts_tri.title LIKE p.value ESCAPE '\\' \
+     OR doc_fts_tri.body LIKE p.value ESCAPE '\\' OR doc_fts_tri.slug LIKE p.value ESCAPE '\\')) \
+     ORDER BY rank ASC, d.updated_at DESC LIMIT ?3";
+
+/// Only short terms: a scan with every LIKE pattern of `?3`, unranked and without snippets.
+const SHORT_TERMS_SQL: &str = "SELECT d.id, d.slug, 0.0 as rank, doc_fts_tri.title as title_snip, '' as body_snip \
+     FROM doc_fts_tri JOIN doc d ON d.rowid=doc_fts_tri.rowid \
+     WHERE d.is_deleted=0 AND (?1 IS NULL OR d.repo_id = ?1) \
+     AND NOT EXISTS(SELECT 1 FROM json_each(?3) p WHERE NOT (doc_fts_tri.title LIKE p.value ESCAPE '\\' \
+     OR doc_fts_tri.body LIKE p.value ESCAPE '\\' OR doc_fts_tri.slug LIKE p.value ESCAPE '\\')) \
+     ORDER BY d.updated_at DESC LIMIT ?2";
+
+fn collect_hits(
+    conn: &Connection,
+    sql: &str,
+    args: impl rusqlite::Params,
+) -> Result<Vec<SearchHit>, String> {
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
     let rows = stmt
-        .query_map(params![query, repo_id, lim, off], |r| {
+        .query_map(args, |r| {
             Ok(SearchHit {
                 id: r.get(0)?,
                 slug: r.get(1)?,
@@ -46,8 +378,45 @@ pub async fn search(
             })
         })
         .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
     for r in rows {
         out.push(r.map_err(|e| e.to_string())?)
     }
     Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    fn substring(db: &Db, query: &str) -> Vec<String> {
+        let conn = db.0.lock();
+        let mut ids: Vec<String> = substring_hits(&conn, query, None, 10).unwrap().into_iter().map(|h| h.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_substring_short_terms_and_fuzzy() {
+        let db = db_with_docs("search", &[("a", "a"), ("b", "b"), ("c", "c")]);
+        {
+            let conn = db.0.lock();
+            fts::reindex_doc(&conn, "a", "kubernetes on k8s nodes").unwrap();
+            fts::reindex_doc(&conn, "b", "kubernetes in 50% of io paths").unwrap();
+            fts::reindex_doc(&conn, "c", "bread baking").unwrap();
+        }
+        // Short terms narrow the trigram match instead of being dropped
+        assert_eq!(substring(&db, "kubern"), vec!["a", "b"]);
+        assert_eq!(substring(&db, "kubern io"), vec!["b"]);
+        // Only short terms: still filtered, `%` literally
+        assert_eq!(substring(&db, "k8"), vec!["a"]);
+        assert_eq!(substring(&db, "0%"), vec!["b"]);
+        assert!(substring(&db, "zq").is_empty());
+
+        let conn = db.0.lock();
+        let hits: Vec<String> = fuzzy_hits(&conn, "kuberntes", None, 10).unwrap().into_iter().map(|h| h.id).collect();
+        assert_eq!(hits.len(), 2);
+        assert!(!hits.contains(&"c".to_string()));
+    }
+}
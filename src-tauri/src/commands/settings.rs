@@ -1,7 +1,7 @@
 //! Application settings commands
 
 use crate::db::Db;
-use rusqlite::params;
+use rusqlite::{params, Connection};
 use tauri::State;
 
 #[tauri::command]
@@ -39,3 +39,15 @@ pub async fn app_settings_set(
         .map_err(|e| e.to_string())?;
     Ok(serde_json::json!({"updated": n > 0}))
 }
+
+/// Name recorded as the author of templates and comments: the `user_name` app setting, else $USER.
+pub(crate) fn current_user(conn: &Connection) -> String {
+    conn.query_row("SELECT value FROM app_setting WHERE key='user_name'", [], |r| {
+        r.get::<_, String>(0)
+    })
+    .ok()
+    .and_then(|s| serde_json::from_str::<String>(&s).ok())
+    .or_else(|| std::env::var("USER").ok())
+    .or_else(|| std::env::var("USERNAME").ok())
+    .unwrap_or_default()
+}
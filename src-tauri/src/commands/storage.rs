@@ -0,0 +1,377 @@
+//! Storage maintenance commands: blob GC, version retention and compaction
+
+use crate::db::Db;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+/// How much version history to keep. Versions younger than `keep_all_hours` are all kept;
+/// up to `hourly_days` old the newest per hour is kept; older than that the newest per day.
+/// The current version, versions with a message and bases of suggestions are never dropped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    #[serde(default = "default_keep_all_hours")]
+    pub keep_all_hours: i64,
+    #[serde(default = "default_hourly_days")]
+    pub hourly_days: i64,
+}
+
+fn default_keep_all_hours() -> i64 {
+    24
+}
+
+fn default_hourly_days() -> i64 {
+    30
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_all_hours: default_keep_all_hours(),
+            hourly_days: default_hourly_days(),
+        }
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct CompactReport {
+    pub docs: usize,
+    pub versions_deleted: usize,
+    pub blobs_deleted: usize,
+    pub bytes_freed: i64,
+    pub dry_run: bool,
+}
+
+#[tauri::command]
+pub async fn gc_blobs(db: State<'_, std::sync::Arc<Db>>) -> Result<serde_json::Value, String> {
+    gc_blobs_core(&db)
+}
+
+/// Delete blobs that no version or asset references.
+pub fn gc_blobs_core(db: &Db) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let (deleted, bytes_freed) = crate::blob::gc(&conn)?;
+    Ok(serde_json::json!({"deleted": deleted, "bytes_freed": bytes_freed}))
+}
+
+/// Effective policy for a repo: `repo.settings.version_retention`, then the
+/// `version_retention` app setting, then the default.
+pub(crate) fn retention_policy(conn: &Connection, repo_id: Option<&str>) -> RetentionPolicy {
+    let repo: Option<String> = repo_id.and_then(|r| {
+        conn.query_row(
+            "SELECT json_extract(settings,'$.version_retention') FROM repo WHERE id=?1 OR name=?1",
+            params![r],
+            |row| row.get(0),
+        )
+        .ok()
+        .flatten()
+    });
+    repo.or_else(|| {
+        conn.query_row("SELECT value FROM app_setting WHERE key='version_retention'", [], |r| r.get(0))
+            .ok()
+    })
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+pub async fn retention_policy_get(
+    repo_id: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<RetentionPolicy, String> {
+    let conn = db.0.lock();
+    Ok(retention_policy(&conn, repo_id.as_deref()))
+}
+
+#[tauri::command]
+pub async fn retention_policy_set(
+    repo_id: Option<String>,
+    policy: RetentionPolicy,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    retention_policy_set_core(&db, repo_id.as_deref(), &policy)
+}
+
+/// Store the policy on a repo, or app-wide when `repo_id` is None.
+pub fn retention_policy_set_core(
+    db: &Db,
+    repo_id: Option<&str>,
+    policy: &RetentionPolicy,
+) -> Result<serde_json::Value, String> {
+    if policy.keep_all_hours < 0 || policy.hourly_days < 0 {
+        return Err("invalid_policy".into());
+    }
+    let value = serde_json::to_string(policy).map_err(|e| e.to_string())?;
+    let conn = db.0.lock();
+    let n = match repo_id {
+        Some(r) => conn.execute(
+            "UPDATE repo SET settings=json_set(COALESCE(settings,json('{}')),'$.version_retention',json(?2)), updated_at=datetime('now') WHERE id=?1 OR name=?1",
+            params![r, value],
+        ),
+        None => conn.execute(
+            "INSERT INTO app_setting(key,value) VALUES('version_retention',?1) ON CONFLICT(key) DO UPDATE SET value=excluded.value, updated_at=datetime('now')",
+            params![value],
+        ),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"updated": n > 0}))
+}
+
+/// Ids of a doc's versions the policy drops, as of `now` (`YYYY-MM-DD HH:MM:SS`).
+fn versions_to_drop(
+    conn: &Connection,
+    doc_id: &str,
+    policy: &RetentionPolicy,
+    now: &str,
+) -> Result<Vec<String>, String> {
+    // Newest first, so the first version seen in a bucket is the one kept
+    let mut stmt = conn
+        .prepare(
+            "SELECT v.id, v.id = d.current_version_id OR COALESCE(v.message,'') != '' \
+                    OR EXISTS (SELECT 1 FROM suggestion s WHERE s.base_version_id=v.id), \
+                    (julianday(?2) - julianday(v.created_at)) * 24.0, \
+                    strftime('%Y-%m-%d %H', v.created_at), date(v.created_at) \
+             FROM doc_version v JOIN doc d ON d.id=v.doc_id WHERE v.doc_id=?1 \
+             ORDER BY v.created_at DESC, v.rowid DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![doc_id, now], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, bool>(1)?,
+                r.get::<_, f64>(2)?,
+                r.get::<_, String>(3)?,
+                r.get::<_, String>(4)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+    let mut seen = std::collections::HashSet::new();
+    let mut drop = Vec::new();
+    for r in rows {
+        let (id, pinned, age_hours, hour, day) = r.map_err(|e| e.to_string())?;
+        let bucket = if age_hours < policy.keep_all_hours as f64 {
+            None
+        } else if age_hours < (policy.hourly_days * 24) as f64 {
+            Some(hour)
+        } else {
+            Some(day)
+        };
+        let first_in_bucket = match bucket {
+            Some(b) => seen.insert(b),
+            None => true,
+        };
+        if !pinned && !first_in_bucket {
+            drop.push(id);
+        }
+    }
+    Ok(drop)
+}
+
+/// Apply each doc's repo policy, then GC the dropped versions' blobs no one else uses.
+/// Anchors and accepted suggestions pointing at a dropped version lose that pointer.
+pub(crate) fn compact_versions(
+    conn: &Connection,
+    repo_id: Option<&str>,
+    doc_id: Option<&str>,
+    now: &str,
+    dry_run: bool,
+) -> Result<CompactReport, String> {
+    let docs: Vec<(String, String)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT d.id, d.repo_id FROM doc d JOIN repo r ON r.id=d.repo_id \
+                 WHERE (?1 IS NULL OR r.id=?1 OR r.name=?1) AND (?2 IS NULL OR d.id=?2 OR d.slug=?2)",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![repo_id, doc_id], |r| Ok((r.get(0)?, r.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    let mut policies = std::collections::HashMap::new();
+    let mut blobs = Vec::new();
+    let mut report = CompactReport {
+        dry_run,
+        ..Default::default()
+    };
+    for (doc, repo) in &docs {
+        let policy = policies
+            .entry(repo.clone())
+            .or_insert_with(|| retention_policy(conn, Some(repo)));
+        let drop = versions_to_drop(conn, doc, policy, now)?;
+        if drop.is_empty() {
+            continue;
+        }
+        report.docs += 1;
+        report.versions_deleted += drop.len();
+        if dry_run {
+            continue;
+        }
+        for id in &drop {
+            let blob: String = conn
+                .query_row("SELECT blob_id FROM doc_version WHERE id=?1", params![id], |r| r.get(0))
+                .map_err(|e| e.to_string())?;
+            blobs.push(blob);
+            conn.execute("UPDATE anchor SET version_id=NULL WHERE version_id=?1", params![id])
+                .map_err(|e| e.to_string())?;
+            conn.execute("UPDATE suggestion SET version_id=NULL WHERE version_id=?1", params![id])
+                .map_err(|e| e.to_string())?;
+            conn.execute(
+                "DELETE FROM doc_version_fts WHERE rowid=(SELECT rowid FROM doc_version WHERE id=?1)",
+                params![id],
+            )
+            .map_err(|e| e.to_string())?;
+            conn.execute("DELETE FROM doc_version WHERE id=?1", params![id])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    if !blobs.is_empty() {
+        let (deleted, bytes) = crate::blob::gc_ids(conn, &blobs)?;
+        report.blobs_deleted = deleted;
+        report.bytes_freed = bytes;
+    }
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn versions_compact(
+    repo_id: Option<String>,
+    doc_id: Option<String>,
+    dry_run: Option<bool>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<CompactReport, String> {
+    versions_compact_core(&db, repo_id.as_deref(), doc_id.as_deref(), dry_run.unwrap_or(false))
+}
+
+pub fn versions_compact_core(
+    db: &Db,
+    repo_id: Option<&str>,
+    doc_id: Option<&str>,
+    dry_run: bool,
+) -> Result<CompactReport, String> {
+    let conn = db.0.lock();
+    let now: String = conn
+        .query_row("SELECT datetime('now')", [], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let report = compact_versions(&tx, repo_id, doc_id, &now, dry_run)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    #[test]
+    fn test_compact_applies_buckets_and_keeps_pinned() {
+        let db = db_with_docs("compact", &[("d", "n")]);
+        let conn = db.0.lock();
+        let versions = [
+            ("recent1", "2026-01-31 10:00:00", None),
+            ("recent2", "2026-01-31 09:00:00", None),
+            ("hour_a", "2026-01-20 08:40:00", None),
+            ("hour_b", "2026-01-20 08:10:00", None),
+            ("named", "2026-01-20 08:05:00", Some("release")),
+            ("day_a", "2025-11-01 20:00:00", None),
+            ("day_b", "2025-11-01 07:00:00", None),
+        ];
+        for (id, at, msg) in versions {
+            let blob = crate::blob::put_text(&conn, id).unwrap();
+            conn.execute(
+                "INSERT INTO doc_version(id,doc_id,blob_id,message,created_at,hash) VALUES(?1,'d',?2,?3,?4,'h')",
+                params![id, blob, msg, at],
+            )
+            .unwrap();
+        }
+        conn.execute("UPDATE doc SET current_version_id='recent1'", []).unwrap();
+
+        let now = "2026-01-31 12:00:00";
+        let dry = compact_versions(&conn, Some("r"), None, now, true).unwrap();
+        assert_eq!((dry.versions_deleted, dry.blobs_deleted), (2, 0));
+
+        let report = compact_versions(&conn, Some("r"), None, now, false).unwrap();
+        assert_eq!((report.docs, report.versions_deleted, report.blobs_deleted), (1, 2, 2));
+        assert_eq!(report.bytes_freed, ("hour_b".len() + "day_b".len()) as i64);
+        let left: Vec<String> = conn
+            .prepare("SELECT id FROM doc_version ORDER BY created_at DESC")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(left, vec!["recent1", "recent2", "hour_a", "named", "day_a"]);
+    }
+
+    #[test]
+    fn test_compact_frees_only_its_blobs_and_clears_pointers() {
+        let db = db_with_docs("compact-refs", &[("d", "n"), ("e", "m")]);
+        let stray = {
+            let conn = db.0.lock();
+            let versions = [
+                ("d", "current", "2026-01-31 10:00:00"),
+                ("d", "kept", "2026-01-20 08:40:00"),
+                ("d", "accepted", "2026-01-20 08:30:00"),
+                ("d", "base", "2026-01-20 08:10:00"),
+                ("d", "shared", "2026-01-20 08:05:00"),
+                ("e", "e1", "2026-01-31 10:00:00"),
+            ];
+            for (doc, id, at) in versions {
+                let body = if id == "e1" { "shared\n".to_string() } else { format!("{}\n", id) };
+                let blob = crate::blob::put_text(&conn, &body).unwrap();
+                conn.execute(
+                    "INSERT INTO doc_version(id,doc_id,blob_id,created_at,hash) VALUES(?1,?2,?3,?4,'h')",
+                    params![id, doc, blob, at],
+                )
+                .unwrap();
+            }
+            conn.execute("UPDATE doc SET current_version_id='current' WHERE id='d'", []).unwrap();
+            conn.execute("UPDATE doc SET current_version_id='e1' WHERE id='e'", []).unwrap();
+            conn.execute(
+                "INSERT INTO anchor(id,doc_id,version_id,line_start,line_end) VALUES('a1','d','accepted',1,1)",
+                [],
+            )
+            .unwrap();
+            let res = super::super::create_suggestion(
+                &conn,
+                super::super::SuggestionCreate {
+                    doc_id: "d".into(),
+                    base_version_id: Some("base".into()),
+                    body: Some("based\n".into()),
+                    patch: None,
+                    message: None,
+                    author: None,
+                    source: None,
+                    trace_id: None,
+                },
+            )
+            .unwrap();
+            conn.execute(
+                "UPDATE suggestion SET status='accepted', version_id='accepted' WHERE id=?1",
+                params![res["suggestion_id"].as_str().unwrap()],
+            )
+            .unwrap();
+            // Unreferenced already, e.g. left over from another repo: not this compaction's to free
+            crate::blob::put_text(&conn, "stray").unwrap()
+        };
+
+        let now = "2026-01-31 12:00:00";
+        let report = compact_versions(&db.0.lock(), Some("r"), Some("d"), now, false).unwrap();
+        // `shared`'s blob is still e1's; the suggestion base stays
+        assert_eq!((report.versions_deleted, report.blobs_deleted), (2, 1));
+        assert_eq!(report.bytes_freed, "accepted\n".len() as i64);
+
+        let conn = db.0.lock();
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |r| r.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM doc_version WHERE id IN ('base','kept','current')"), 3);
+        assert_eq!(count(&format!("SELECT COUNT(*) FROM doc_blob WHERE id='{}'", stray)), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM anchor WHERE id='a1' AND version_id IS NULL"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM suggestion WHERE version_id IS NULL AND base_version_id='base'"), 1);
+        let suggestion: String = conn.query_row("SELECT id FROM suggestion", [], |r| r.get(0)).unwrap();
+        drop(conn);
+        let preview = super::super::suggestions_preview_core(&db, &suggestion).unwrap();
+        assert_eq!(preview["suggestion"]["status"], "accepted");
+    }
+}
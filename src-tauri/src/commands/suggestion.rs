@@ -0,0 +1,468 @@
+//! Suggested edits
+//!
+//! A suggestion is a unified diff against the version it was written for, so it can be
+//! reviewed without touching the doc. Accepting one writes a new version through
+//! `docs_update_core` with the suggestion's base as `base_version_id`: when the doc has moved
+//! on, the change is three-way merged onto the current version, and a conflict leaves the
+//! suggestion pending.
+
+use crate::db::Db;
+use crate::merge::{apply_patch, make_patch, merge3, MergeOutcome};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct SuggestionInfo {
+    pub id: String,
+    pub doc_id: String,
+    pub base_version_id: String,
+    pub message: Option<String>,
+    pub author: String,
+    /// `user`, `ai` or `plugin`
+    pub source: String,
+    pub trace_id: Option<String>,
+    /// `pending`, `accepted` or `rejected`
+    pub status: String,
+    pub version_id: Option<String>,
+    pub reason: Option<String>,
+    /// The doc has newer versions than the suggestion's base
+    pub stale: bool,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Deserialize)]
+pub struct SuggestionCreate {
+    pub doc_id: String,
+    /// Defaults to the current version
+    #[serde(default)]
+    pub base_version_id: Option<String>,
+    /// Proposed full body; alternatively `patch`, a unified diff against the base
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub patch: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub trace_id: Option<String>,
+}
+
+const SUGGESTION_COLUMNS: &str = "s.id, s.doc_id, s.base_version_id, s.message, s.author, s.source, s.trace_id, \
+     s.status, s.version_id, s.reason, s.base_version_id != COALESCE(d.current_version_id,''), s.created_at, s.updated_at";
+
+fn suggestion_row(r: &rusqlite::Row) -> rusqlite::Result<SuggestionInfo> {
+    Ok(SuggestionInfo {
+        id: r.get(0)?,
+        doc_id: r.get(1)?,
+        base_version_id: r.get(2)?,
+        message: r.get(3)?,
+        author: r.get(4)?,
+        source: r.get(5)?,
+        trace_id: r.get(6)?,
+        status: r.get(7)?,
+        version_id: r.get(8)?,
+        reason: r.get(9)?,
+        stale: r.get(10)?,
+        created_at: r.get(11)?,
+        updated_at: r.get(12)?,
+    })
+}
+
+fn load(conn: &Connection, suggestion_id: &str) -> Result<(SuggestionInfo, String), String> {
+    conn.query_row(
+        &format!(
+            "SELECT {}, s.patch FROM suggestion s JOIN doc d ON d.id=s.doc_id WHERE s.id=?1 AND d.is_deleted=0",
+            SUGGESTION_COLUMNS
+        ),
+        params![suggestion_id],
+        |r| Ok((suggestion_row(r)?, r.get(13)?)),
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "not_found".to_string())
+}
+
+fn load_pending(conn: &Connection, suggestion_id: &str) -> Result<(SuggestionInfo, String), String> {
+    let (info, patch) = load(conn, suggestion_id)?;
+    if info.status != "pending" {
+        return Err("not_pending".into());
+    }
+    Ok((info, patch))
+}
+
+/// Body the suggestion proposes for its own base version.
+fn proposed_body(conn: &Connection, info: &SuggestionInfo, patch: &str) -> Result<(String, String), String> {
+    let base = super::version_body(conn, &info.base_version_id).map_err(|_| "base_missing".to_string())?;
+    let proposed = apply_patch(&base, patch)?;
+    Ok((base, proposed))
+}
+
+/// Record a suggestion. Errors: `invalid_suggestion` (neither or both of body/patch),
+/// `invalid_source`, `invalid_base_version`, `invalid_patch`, `patch_mismatch`, `no_changes`.
+pub(crate) fn create_suggestion(conn: &Connection, payload: SuggestionCreate) -> Result<serde_json::Value, String> {
+    let source = payload.source.unwrap_or_else(|| "user".into());
+    if !matches!(source.as_str(), "user" | "ai" | "plugin") {
+        return Err("invalid_source".into());
+    }
+    let (doc_id, current): (String, Option<String>) = conn
+        .query_row(
+            "SELECT id, current_version_id FROM doc WHERE (id=?1 OR slug=?1) AND is_deleted=0 LIMIT 1",
+            params![payload.doc_id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?;
+    let base_version_id = payload.base_version_id.or(current).ok_or("not_found")?;
+    let owner: Option<String> = conn
+        .query_row("SELECT doc_id FROM doc_version WHERE id=?1", params![base_version_id], |r| r.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if owner.as_deref() != Some(doc_id.as_str()) {
+        return Err("invalid_base_version".into());
+    }
+    let base = super::version_body(conn, &base_version_id)?;
+    let proposed = match (payload.body, payload.patch) {
+        (Some(body), None) => body,
+        (None, Some(patch)) => apply_patch(&base, &patch)?,
+        _ => return Err("invalid_suggestion".into()),
+    };
+    if proposed == base {
+        return Err("no_changes".into());
+    }
+    // Stored in canonical form whatever the input was
+    let patch = make_patch(&base, &proposed);
+    let diff = super::diff_texts(&base_version_id, "proposed", &base, &proposed, false);
+    let author = payload
+        .author
+        .filter(|a| !a.trim().is_empty())
+        .unwrap_or_else(|| super::current_user(conn));
+    let id = Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO suggestion(id,doc_id,base_version_id,patch,message,author,source,trace_id) VALUES(?1,?2,?3,?4,?5,?6,?7,?8)",
+        params![id, doc_id, base_version_id, patch, payload.message, author, source, payload.trace_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({
+        "suggestion_id": id,
+        "base_version_id": base_version_id,
+        "added": diff.added,
+        "removed": diff.removed,
+    }))
+}
+
+#[tauri::command]
+pub async fn suggestions_create(
+    payload: SuggestionCreate,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    suggestions_create_core(&db, payload)
+}
+
+pub fn suggestions_create_core(db: &Db, payload: SuggestionCreate) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    create_suggestion(&conn, payload)
+}
+
+#[tauri::command]
+pub async fn suggestions_list(
+    doc_id: Option<String>,
+    status: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<SuggestionInfo>, String> {
+    suggestions_list_core(&db, doc_id.as_deref(), status.as_deref())
+}
+
+/// Newest first; `status` filters (`pending`, `accepted`, `rejected`), default all.
+pub fn suggestions_list_core(
+    db: &Db,
+    doc_id: Option<&str>,
+    status: Option<&str>,
+) -> Result<Vec<SuggestionInfo>, String> {
+    let conn = db.0.lock();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM suggestion s JOIN doc d ON d.id=s.doc_id \
+             WHERE d.is_deleted=0 AND (?1 IS NULL OR d.id=?1 OR d.slug=?1) AND (?2 IS NULL OR s.status=?2) \
+             ORDER BY s.created_at DESC, s.rowid DESC",
+            SUGGESTION_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![doc_id, status], suggestion_row)
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn suggestions_preview(
+    suggestion_id: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    suggestions_preview_core(&db, &suggestion_id)
+}
+
+/// The suggestion's patch plus what accepting it now would do: the diff from the current
+/// version (`rebased` when it had to be merged onto newer versions), or the conflicts.
+pub fn suggestions_preview_core(db: &Db, suggestion_id: &str) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let (info, patch) = load(&conn, suggestion_id)?;
+    let (base, proposed) = proposed_body(&conn, &info, &patch)?;
+    let current: Option<String> = conn
+        .query_row("SELECT current_version_id FROM doc WHERE id=?1", params![info.doc_id], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    let current = current.unwrap_or_default();
+    let current_body = super::version_body(&conn, &current).unwrap_or_default();
+    let mut out = serde_json::json!({
+        "current_version_id": current,
+        "patch": patch,
+        "rebased": info.stale,
+    });
+    let result = if info.stale {
+        match merge3(&base, &proposed, &current_body) {
+            MergeOutcome::Clean(merged) => Some(merged),
+            MergeOutcome::Conflicted { conflicts, marked } => {
+                out["conflict"] = serde_json::json!({"conflicts": conflicts, "marked": marked});
+                None
+            }
+        }
+    } else {
+        Some(proposed)
+    };
+    if let Some(result) = result {
+        let diff = super::diff_texts(&current, &format!("suggestion:{}", info.id), &current_body, &result, false);
+        out["diff"] = serde_json::to_value(diff).map_err(|e| e.to_string())?;
+    }
+    out["suggestion"] = serde_json::to_value(info).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+#[tauri::command]
+pub async fn suggestions_accept(
+    suggestion_id: String,
+    message: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    suggestions_accept_core(&db, &suggestion_id, message)
+}
+
+/// Write the suggestion as a new version. Returns the `docs_update_core` result; on
+/// `conflict` the suggestion stays pending. The status flip and the write share one
+/// transaction, so of two concurrent accepts the second gets `not_pending`.
+pub fn suggestions_accept_core(
+    db: &Db,
+    suggestion_id: &str,
+    message: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let mut conn = db.0.lock();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let (info, patch) = load_pending(&tx, suggestion_id)?;
+    let claimed = tx
+        .execute(
+            "UPDATE suggestion SET status='accepted', updated_at=datetime('now') WHERE id=?1 AND status='pending'",
+            params![info.id],
+        )
+        .map_err(|e| e.to_string())?;
+    if claimed != 1 {
+        return Err("not_pending".into());
+    }
+    let (_, proposed) = proposed_body(&tx, &info, &patch)?;
+    let message = message
+        .or_else(|| info.message.clone())
+        .unwrap_or_else(|| format!("Accept suggestion {}", info.id));
+    let (mut res, written) = super::write_update(
+        &tx,
+        super::DocUpdate {
+            doc_id: info.doc_id.clone(),
+            body: proposed,
+            message: Some(message),
+            base_version_id: Some(info.base_version_id.clone()),
+        },
+    )?;
+    res["suggestion_id"] = serde_json::Value::String(info.id.clone());
+    if res.get("conflict").is_some() {
+        // Dropping the transaction puts the suggestion back to pending
+        return Ok(res);
+    }
+    let version_id = res["version_id"].as_str().unwrap_or_default().to_string();
+    tx.execute(
+        "UPDATE suggestion SET version_id=?2 WHERE id=?1",
+        params![info.id, version_id],
+    )
+    .map_err(|e| e.to_string())?;
+    if written.is_some() {
+        tx.execute(
+            "UPDATE doc_version SET author=?2 WHERE id=?1",
+            params![version_id, info.author],
+        )
+        .map_err(|e| e.to_string())?;
+        if info.source != "user" {
+            let meta = serde_json::json!({
+                "suggestion_id": info.id,
+                "author": info.author,
+                "trace_id": info.trace_id,
+            });
+            tx.execute(
+                "INSERT INTO provenance(id,entity_type,entity_id,source,meta) VALUES(?1,'doc_version',?2,?3,?4)",
+                params![Uuid::new_v4().to_string(), version_id, info.source, meta.to_string()],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    drop(conn);
+    if let Some(body) = written {
+        super::after_update(db, &info.doc_id, &body)?;
+    }
+    Ok(res)
+}
+
+#[tauri::command]
+pub async fn suggestions_reject(
+    suggestion_id: String,
+    reason: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    suggestions_reject_core(&db, &suggestion_id, reason)
+}
+
+pub fn suggestions_reject_core(
+    db: &Db,
+    suggestion_id: &str,
+    reason: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    load_pending(&conn, suggestion_id)?;
+    conn.execute(
+        "UPDATE suggestion SET status='rejected', reason=?2, updated_at=datetime('now') WHERE id=?1 AND status='pending'",
+        params![suggestion_id, reason],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"suggestion_id": suggestion_id, "status": "rejected"}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    fn update(db: &Db, body: &str) -> String {
+        let res = super::super::docs_update_core(
+            db,
+            super::super::DocUpdate {
+                doc_id: "d".into(),
+                body: body.into(),
+                message: None,
+                base_version_id: None,
+            },
+        )
+        .unwrap();
+        res["version_id"].as_str().unwrap().to_string()
+    }
+
+    fn suggest(db: &Db, body: &str) -> String {
+        let res = suggestions_create_core(
+            db,
+            SuggestionCreate {
+                doc_id: "d".into(),
+                base_version_id: None,
+                body: Some(body.into()),
+                patch: None,
+                message: Some("tighten".into()),
+                author: Some("bot".into()),
+                source: Some("ai".into()),
+                trace_id: Some("t1".into()),
+            },
+        )
+        .unwrap();
+        res["suggestion_id"].as_str().unwrap().to_string()
+    }
+
+    fn body(db: &Db) -> String {
+        super::super::docs_get_core(db, "d", true).unwrap()["body"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_suggestions_rebase_accept_and_reject() {
+        let db = db_with_docs("suggest", &[("d", "n")]);
+        update(&db, "a\nb\nc\nd\ne\nf\ng\nh\n");
+        let s1 = suggest(&db, "a\nB\nc\nd\ne\nf\ng\nh\n");
+        let s2 = suggest(&db, "a\nb\nc\nd\ne\nf\ng\nH\n");
+        let s3 = suggest(&db, "a\nb\nc\nd\ne\nf\ng\nX\n");
+
+        // The doc moves on: s2 rebases cleanly, s3 touches the same line as s2
+        update(&db, "a\nb\nc\nd\nE\nf\ng\nh\n");
+        let preview = suggestions_preview_core(&db, &s2).unwrap();
+        assert_eq!(preview["rebased"], true);
+        assert!(preview["diff"]["unified"].as_str().unwrap().contains("-h\n+H\n"));
+
+        let res = suggestions_accept_core(&db, &s2, None).unwrap();
+        assert!(res.get("merged_with").is_some());
+        assert_eq!(body(&db), "a\nb\nc\nd\nE\nf\ng\nH\n");
+        assert_eq!(suggestions_accept_core(&db, &s2, None).unwrap_err(), "not_pending");
+        let res = suggestions_accept_core(&db, &s3, None).unwrap();
+        assert!(res.get("conflict").is_some());
+        assert!(suggestions_preview_core(&db, &s3).unwrap().get("conflict").is_some());
+
+        suggestions_reject_core(&db, &s3, Some("superseded".into())).unwrap();
+        assert_eq!(suggestions_accept_core(&db, &s3, None).unwrap_err(), "not_pending");
+        suggestions_accept_core(&db, &s1, None).unwrap();
+        assert_eq!(body(&db), "a\nB\nc\nd\nE\nf\ng\nH\n");
+
+        let list = suggestions_list_core(&db, Some("n"), None).unwrap();
+        let status: Vec<&str> = list.iter().map(|s| s.status.as_str()).collect();
+        assert_eq!(status, vec!["rejected", "accepted", "accepted"]);
+        let conn = db.0.lock();
+        let (author, source): (String, String) = conn
+            .query_row(
+                "SELECT v.author, p.source FROM doc_version v JOIN provenance p ON p.entity_id=v.id WHERE v.id=?1",
+                params![list[2].version_id],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((author.as_str(), source.as_str()), ("bot", "ai"));
+        assert_eq!(
+            create_suggestion(
+                &conn,
+                SuggestionCreate {
+                    doc_id: "d".into(),
+                    base_version_id: None,
+                    body: None,
+                    patch: Some("@@ -1,1 +1,1 @@\n-zzz\n+y\n".into()),
+                    message: None,
+                    author: None,
+                    source: None,
+                    trace_id: None,
+                },
+            )
+            .unwrap_err(),
+            "patch_mismatch"
+        );
+    }
+
+    #[test]
+    fn test_suggestions_on_trashed_doc() {
+        let db = db_with_docs("suggest-trash", &[("d", "n")]);
+        update(&db, "a\nb\n");
+        let s1 = suggest(&db, "a\nB\n");
+        super::super::docs_delete_core(&db, "d").unwrap();
+
+        assert!(suggestions_list_core(&db, None, None).unwrap().is_empty());
+        assert_eq!(suggestions_preview_core(&db, &s1).unwrap_err(), "not_found");
+        assert_eq!(suggestions_accept_core(&db, &s1, None).unwrap_err(), "not_found");
+        assert_eq!(suggestions_reject_core(&db, &s1, None).unwrap_err(), "not_found");
+        let status: String = db
+            .0
+            .lock()
+            .query_row("SELECT status FROM suggestion WHERE id=?1", params![s1], |r| r.get(0))
+            .unwrap();
+        assert_eq!(status, "pending");
+    }
+}
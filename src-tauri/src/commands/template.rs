@@ -0,0 +1,424 @@
+//! Document templates and daily notes
+//!
+//! Templates are ordinary docs under the template folder (`templates` by default). Their bodies
+//! may use `{{date}}`, `{{date:<strftime>}}`, `{{time}}`, `{{title}}`, `{{slug}}`, `{{user}}`
+//! and `{{prompt:<name>}}`; prompts (and any other name) are filled from caller `vars`.
+//! Configuration lives in repo `settings.templates`, falling back to the `templates` app setting:
+//! `{ folder, daily: { folder, slug_pattern, title_pattern, template } }`.
+
+use crate::db::Db;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::State;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DailyConfig {
+    #[serde(default = "default_daily_folder")]
+    pub folder: String,
+    #[serde(default = "default_daily_slug")]
+    pub slug_pattern: String,
+    #[serde(default = "default_daily_title")]
+    pub title_pattern: String,
+    #[serde(default = "default_daily_template")]
+    pub template: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TemplateConfig {
+    #[serde(default = "default_template_folder")]
+    pub folder: String,
+    #[serde(default)]
+    pub daily: DailyConfig,
+}
+
+fn default_template_folder() -> String {
+    "templates".into()
+}
+fn default_daily_folder() -> String {
+    "daily".into()
+}
+fn default_daily_slug() -> String {
+    "daily__{{date}}".into()
+}
+fn default_daily_title() -> String {
+    "{{date}}".into()
+}
+fn default_daily_template() -> String {
+    "daily".into()
+}
+
+impl Default for DailyConfig {
+    fn default() -> Self {
+        Self {
+            folder: default_daily_folder(),
+            slug_pattern: default_daily_slug(),
+            title_pattern: default_daily_title(),
+            template: default_daily_template(),
+        }
+    }
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        Self {
+            folder: default_template_folder(),
+            daily: DailyConfig::default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TemplateInfo {
+    pub doc_id: String,
+    pub name: String,
+    pub slug: String,
+    pub title: String,
+    /// Names used as `{{prompt:<name>}}`, in order of first use
+    pub prompts: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TemplateCreateRequest {
+    pub repo_id: String,
+    pub template: String,
+    pub title: String,
+    #[serde(default)]
+    pub slug: Option<String>,
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+}
+
+fn template_config(conn: &Connection, repo_id: &str) -> TemplateConfig {
+    let repo: Option<String> = conn
+        .query_row(
+            "SELECT json_extract(settings,'$.templates') FROM repo WHERE id=?1 OR name=?1",
+            params![repo_id],
+            |r| r.get(0),
+        )
+        .ok()
+        .flatten();
+    repo.or_else(|| {
+        conn.query_row("SELECT value FROM app_setting WHERE key='templates'", [], |r| r.get(0))
+            .ok()
+    })
+    .and_then(|s| serde_json::from_str(&s).ok())
+    .unwrap_or_default()
+}
+
+/// `{{name}}` / `{{name:arg}}` placeholders in order of appearance.
+pub(crate) fn placeholders(text: &str) -> Vec<(usize, usize, String, Option<String>)> {
+    let mut out = Vec::new();
+    let mut from = 0;
+    while let Some(start) = text[from..].find("{{").map(|i| i + from) {
+        let Some(end) = text[start + 2..].find("}}").map(|i| i + start + 2) else {
+            break;
+        };
+        let inner = text[start + 2..end].trim();
+        let (name, arg) = match inner.split_once(':') {
+            Some((n, a)) => (n.trim().to_string(), Some(a.trim().to_string())),
+            None => (inner.to_string(), None),
+        };
+        out.push((start, end + 2, name, arg));
+        from = end + 2;
+    }
+    out
+}
+
+fn prompt_names(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (_, _, name, arg) in placeholders(text) {
+        if let ("prompt", Some(p)) = (name.as_str(), arg) {
+            if !names.contains(&p) {
+                names.push(p);
+            }
+        }
+    }
+    names
+}
+
+/// Substitute placeholders. `now` is a SQLite datetime used for date/time formatting; unknown
+/// placeholders are left as written. Errors list prompts without a value.
+fn render(
+    conn: &Connection,
+    text: &str,
+    now: &str,
+    vars: &HashMap<String, String>,
+) -> Result<String, Vec<String>> {
+    let strftime = |fmt: &str| -> String {
+        conn.query_row("SELECT strftime(?1, ?2)", params![fmt, now], |r| r.get::<_, Option<String>>(0))
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    };
+    let mut out = String::with_capacity(text.len());
+    let mut missing = Vec::new();
+    let mut last = 0;
+    for (start, end, name, arg) in placeholders(text) {
+        out.push_str(&text[last..start]);
+        last = end;
+        let value = match (name.as_str(), arg.as_deref()) {
+            ("date", None) => Some(strftime("%Y-%m-%d")),
+            ("date", Some(fmt)) => Some(strftime(fmt)),
+            ("time", None) => Some(strftime("%H:%M")),
+            ("prompt", Some(p)) => match vars.get(p) {
+                Some(v) => Some(v.clone()),
+                None => {
+                    if !missing.iter().any(|m| m == p) {
+                        missing.push(p.to_string());
+                    }
+                    Some(String::new())
+                }
+            },
+            (n, None) => vars.get(n).cloned(),
+            _ => None,
+        };
+        match value {
+            Some(v) => out.push_str(&v),
+            None => out.push_str(&text[start..end]),
+        }
+    }
+    out.push_str(&text[last..]);
+    if missing.is_empty() {
+        Ok(out)
+    } else {
+        Err(missing)
+    }
+}
+
+/// Slug the scanner would give `<folder>/<name>.md`.
+fn slug_for(folder: &str, name: &str) -> String {
+    let name = name.trim().replace(['/', '\\'], "-").replace(' ', "-");
+    let folder = super::normalize_folder_path(folder).unwrap_or_default();
+    if folder.is_empty() {
+        name
+    } else {
+        format!("{}__{}", folder.replace('/', "__"), name)
+    }
+}
+
+fn list_templates(conn: &Connection, repo_id: &str, folder: &str) -> Result<Vec<(TemplateInfo, String)>, String> {
+    let folder = super::normalize_folder_path(folder)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT d.id, d.slug, d.title, d.current_version_id FROM doc d JOIN folder f ON f.id=d.folder_id \
+             WHERE d.repo_id=?1 AND d.is_deleted=0 AND (f.path=?2 OR substr(f.path, 1, length(?2) + 1) = ?2 || '/') \
+             ORDER BY d.title",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows: Vec<(String, String, String, Option<String>)> = stmt
+        .query_map(params![repo_id, folder], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    for (doc_id, slug, title, current) in rows {
+        let body = match current {
+            Some(v) => super::version_body(conn, &v)?,
+            None => String::new(),
+        };
+        let name = slug.rsplit("__").next().unwrap_or(&slug).to_string();
+        out.push((
+            TemplateInfo {
+                doc_id,
+                name,
+                slug,
+                title,
+                prompts: prompt_names(&body),
+            },
+            body,
+        ));
+    }
+    Ok(out)
+}
+
+/// Template body by name (last slug segment), title (case-insensitive), slug or doc id.
+fn find_template(conn: &Connection, repo_id: &str, folder: &str, name: &str) -> Result<Option<String>, String> {
+    Ok(list_templates(conn, repo_id, folder)?
+        .into_iter()
+        .find(|(t, _)| {
+            t.name.eq_ignore_ascii_case(name) || t.title.eq_ignore_ascii_case(name) || t.slug == name || t.doc_id == name
+        })
+        .map(|(_, body)| body))
+}
+
+fn local_now(conn: &Connection, date: Option<&str>) -> Result<String, String> {
+    let now: Option<String> = match date {
+        Some(d) => conn.query_row("SELECT datetime(?1)", params![d], |r| r.get(0)),
+        None => conn.query_row("SELECT datetime('now','localtime')", [], |r| r.get(0)),
+    }
+    .map_err(|e| e.to_string())?;
+    now.ok_or_else(|| "invalid_date".to_string())
+}
+
+#[tauri::command]
+pub async fn templates_list(
+    repo_id: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<TemplateInfo>, String> {
+    templates_list_core(&db, &repo_id)
+}
+
+pub fn templates_list_core(db: &Db, repo_id: &str) -> Result<Vec<TemplateInfo>, String> {
+    let conn = db.0.lock();
+    let cfg = template_config(&conn, repo_id);
+    Ok(list_templates(&conn, repo_id, &cfg.folder)?
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect())
+}
+
+#[tauri::command]
+pub async fn docs_create_from_template(
+    payload: TemplateCreateRequest,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    docs_create_from_template_core(&db, payload)
+}
+
+/// Render a template and create a doc from it. When prompts lack values nothing is created
+/// and `{ missing_prompts }` is returned.
+pub fn docs_create_from_template_core(db: &Db, req: TemplateCreateRequest) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let cfg = template_config(&conn, &req.repo_id);
+    let template = find_template(&conn, &req.repo_id, &cfg.folder, &req.template)?.ok_or("template_not_found")?;
+    let folder = req.folder.unwrap_or_default();
+    let slug = req.slug.unwrap_or_else(|| slug_for(&folder, &req.title));
+    let mut vars = req.vars;
+    vars.insert("title".into(), req.title.clone());
+    vars.insert("slug".into(), slug.clone());
+    vars.entry("user".into()).or_insert_with(|| super::current_user(&conn));
+    let now = local_now(&conn, None)?;
+    let body = match render(&conn, &template, &now, &vars) {
+        Ok(b) => b,
+        Err(missing) => return Ok(serde_json::json!({"missing_prompts": missing})),
+    };
+    drop(conn);
+    let mut out = super::docs_create_core(
+        db,
+        super::DocCreate {
+            repo_id: req.repo_id,
+            slug: slug.clone(),
+            title: req.title,
+            body,
+            folder: Some(folder),
+        },
+    )?;
+    out["slug"] = serde_json::Value::String(slug);
+    Ok(out)
+}
+
+#[tauri::command]
+pub async fn docs_daily(
+    repo_id: String,
+    date: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    docs_daily_core(&db, &repo_id, date.as_deref())
+}
+
+/// Open (or create) the daily note for today or `date` (`YYYY-MM-DD`). The body comes from the
+/// configured daily template when one exists.
+pub fn docs_daily_core(db: &Db, repo_id: &str, date: Option<&str>) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let cfg = template_config(&conn, repo_id);
+    let now = local_now(&conn, date)?;
+    let mut vars = HashMap::new();
+    vars.insert("user".to_string(), super::current_user(&conn));
+    let slug = render(&conn, &cfg.daily.slug_pattern, &now, &vars).map_err(|_| "invalid_slug_pattern")?;
+    let title = render(&conn, &cfg.daily.title_pattern, &now, &vars).map_err(|_| "invalid_title_pattern")?;
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT id FROM doc WHERE repo_id=?1 AND slug=?2",
+            params![repo_id, slug],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(doc_id) = existing {
+        return Ok(serde_json::json!({"doc_id": doc_id, "slug": slug, "created": false}));
+    }
+    vars.insert("title".into(), title.clone());
+    vars.insert("slug".into(), slug.clone());
+    let body = match find_template(&conn, repo_id, &cfg.folder, &cfg.daily.template)? {
+        Some(t) => {
+            // Daily notes are created unattended; prompts render empty
+            for p in prompt_names(&t) {
+                vars.entry(p).or_default();
+            }
+            render(&conn, &t, &now, &vars).unwrap_or_default()
+        }
+        None => format!("# {}\n", title),
+    };
+    drop(conn);
+    let mut out = super::docs_create_core(
+        db,
+        super::DocCreate {
+            repo_id: repo_id.to_string(),
+            slug: slug.clone(),
+            title,
+            body,
+            folder: Some(cfg.daily.folder),
+        },
+    )?;
+    out["slug"] = serde_json::Value::String(slug);
+    out["created"] = serde_json::Value::Bool(true);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    #[test]
+    fn test_template_and_daily() {
+        let db = db_with_docs("template", &[]);
+        let create = |slug: &str, title: &str, body: &str| {
+            super::super::docs_create_core(
+                &db,
+                super::super::DocCreate {
+                    repo_id: "r".into(),
+                    slug: slug.into(),
+                    title: title.into(),
+                    body: body.into(),
+                    folder: Some("templates".into()),
+                },
+            )
+            .unwrap();
+        };
+        create("templates__meeting", "Meeting", "# {{title}}\nBy {{user}} on {{date:%d.%m.%Y}}\nWith: {{prompt:attendees}}\n{{unknown}}");
+        create("templates__daily", "Daily", "# {{title}} (day {{date:%w}})\n");
+
+        let list = templates_list_core(&db, "r").unwrap();
+        let meeting = list.iter().find(|t| t.name == "meeting").unwrap();
+        assert_eq!(meeting.prompts, vec!["attendees"]);
+
+        let req = |vars: HashMap<String, String>| TemplateCreateRequest {
+            repo_id: "r".into(),
+            template: "Meeting".into(),
+            title: "Sync 1".into(),
+            slug: None,
+            folder: Some("meetings".into()),
+            vars,
+        };
+        let res = docs_create_from_template_core(&db, req(HashMap::new())).unwrap();
+        assert_eq!(res["missing_prompts"], serde_json::json!(["attendees"]));
+        let vars = HashMap::from([("attendees".to_string(), "Ann".to_string()), ("user".to_string(), "kim".to_string())]);
+        let res = docs_create_from_template_core(&db, req(vars)).unwrap();
+        assert_eq!(res["slug"], "meetings__Sync-1");
+        let doc = super::super::docs_get_core(&db, "meetings__Sync-1", true).unwrap();
+        let body = doc["body"].as_str().unwrap();
+        assert!(body.starts_with("# Sync 1\nBy kim on "), "{}", body);
+        assert!(body.contains("With: Ann\n{{unknown}}"));
+
+        let daily = docs_daily_core(&db, "r", Some("2026-03-02")).unwrap();
+        assert_eq!((daily["slug"].as_str(), daily["created"].as_bool()), (Some("daily__2026-03-02"), Some(true)));
+        let doc = super::super::docs_get_core(&db, "daily__2026-03-02", true).unwrap();
+        assert_eq!(doc["body"], "# 2026-03-02 (day 1)\n");
+        let again = docs_daily_core(&db, "r", Some("2026-03-02")).unwrap();
+        assert_eq!((again["doc_id"].clone(), again["created"].as_bool()), (daily["doc_id"].clone(), Some(false)));
+    }
+}
@@ -0,0 +1,342 @@
+//! Trash commands: soft delete, restore and purge
+//!
+//! Trashing a doc moves its slug to `trashed_slug` (so `UNIQUE(repo_id, slug)` no longer holds
+//! it), drops its search rows and outbound links, and unresolves inbound links. Restoring
+//! reverses that; purging removes the row and its history for good. Trashed docs are purged
+//! automatically `trash_retention_days` (app setting, default 30) after deletion.
+
+use crate::db::Db;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use tauri::State;
+
+const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+
+#[derive(Serialize)]
+pub struct TrashedDoc {
+    pub id: String,
+    pub repo_id: String,
+    pub slug: String,
+    pub title: String,
+    pub deleted_at: String,
+    pub purge_at: String,
+}
+
+fn trash_retention_days(conn: &Connection) -> i64 {
+    conn.query_row("SELECT value FROM app_setting WHERE key='trash_retention_days'", [], |r| {
+        r.get::<_, String>(0)
+    })
+    .ok()
+    .and_then(|s| serde_json::from_str::<i64>(&s).ok())
+    .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS)
+}
+
+/// Move a live doc (id or slug) to the trash. Returns false when no live doc matched.
+pub(crate) fn trash_doc(conn: &Connection, doc: &str) -> Result<bool, String> {
+    let id: Option<String> = conn
+        .query_row(
+            "SELECT id FROM doc WHERE (id=?1 OR slug=?1) AND is_deleted=0 LIMIT 1",
+            params![doc],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let Some(id) = id else {
+        return Ok(false);
+    };
+    crate::fts::remove_doc(conn, &id)?;
+    conn.execute(
+        "UPDATE doc SET is_deleted=1, deleted_at=datetime('now'), trashed_slug=slug, slug='.trash/'||id WHERE id=?1",
+        params![id],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM link WHERE from_doc_id=?1", params![id])
+        .map_err(|e| e.to_string())?;
+    conn.execute("UPDATE link SET to_doc_id=NULL WHERE to_doc_id=?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Hard-delete a trashed doc with its versions and search rows (anchors cascade).
+fn purge_doc(conn: &Connection, doc_id: &str) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM doc_version_fts WHERE rowid IN (SELECT rowid FROM doc_version WHERE doc_id=?1)",
+        params![doc_id],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM provenance WHERE entity_type='doc' AND entity_id=?1",
+        params![doc_id],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM doc WHERE id=?1", params![doc_id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn docs_delete(
+    doc_id: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    docs_delete_core(&db, &doc_id)
+}
+
+pub fn docs_delete_core(db: &Db, doc_id: &str) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let deleted = trash_doc(&tx, doc_id)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"deleted": deleted}))
+}
+
+#[tauri::command]
+pub async fn docs_trash_list(
+    repo_id: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<TrashedDoc>, String> {
+    docs_trash_list_core(&db, repo_id.as_deref())
+}
+
+/// Trashed docs, most recently deleted first, with their original slugs.
+pub fn docs_trash_list_core(db: &Db, repo_id: Option<&str>) -> Result<Vec<TrashedDoc>, String> {
+    let conn = db.0.lock();
+    let days = trash_retention_days(&conn);
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, repo_id, COALESCE(trashed_slug, slug), title, COALESCE(deleted_at, updated_at), \
+             datetime(COALESCE(deleted_at, updated_at), '+' || ?2 || ' days') \
+             FROM doc WHERE is_deleted=1 AND (?1 IS NULL OR repo_id=?1) ORDER BY deleted_at DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![repo_id, days], |r| {
+            Ok(TrashedDoc {
+                id: r.get(0)?,
+                repo_id: r.get(1)?,
+                slug: r.get(2)?,
+                title: r.get(3)?,
+                deleted_at: r.get(4)?,
+                purge_at: r.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn docs_restore_deleted(
+    doc_id: String,
+    slug: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    docs_restore_deleted_core(&db, &doc_id, slug)
+}
+
+/// Bring a doc back under its original slug (or `slug` when given). Errors with `slug_taken`
+/// when a live doc now uses it.
+pub fn docs_restore_deleted_core(
+    db: &Db,
+    doc_id: &str,
+    slug: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let row: Option<(String, String, Option<String>, Option<String>)> = conn
+        .query_row(
+            "SELECT id, repo_id, trashed_slug, current_version_id FROM doc \
+             WHERE (id=?1 OR trashed_slug=?1) AND is_deleted=1 ORDER BY deleted_at DESC LIMIT 1",
+            params![doc_id],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let (id, repo_id, trashed_slug, current) = row.ok_or("not_found")?;
+    let slug = slug.or(trashed_slug).ok_or("slug_required")?;
+    let taken: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM doc WHERE repo_id=?1 AND slug=?2)",
+            params![repo_id, slug],
+            |r| r.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if taken {
+        return Err("slug_taken".into());
+    }
+    let body = match current.as_deref() {
+        Some(v) => super::version_body(&conn, v)?,
+        None => String::new(),
+    };
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE doc SET is_deleted=0, deleted_at=NULL, trashed_slug=NULL, slug=?2, updated_at=datetime('now') WHERE id=?1",
+        params![id, slug],
+    )
+    .map_err(|e| e.to_string())?;
+    crate::fts::insert_doc(&tx, &id, &body)?;
+    crate::graph::update_links_for_doc(&tx, &id, &body)?;
+    // Links written while the doc was away point at its slug but never resolved
+    tx.execute(
+        "UPDATE link SET to_doc_id=?1 WHERE repo_id=?2 AND to_slug=?3 AND to_doc_id IS NULL",
+        params![id, repo_id, slug],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    drop(conn);
+    super::refresh_embeddings(db, &id, &body);
+    Ok(serde_json::json!({"restored": true, "doc_id": id, "slug": slug}))
+}
+
+#[tauri::command]
+pub async fn docs_purge(
+    doc_id: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    docs_purge_core(&db, &doc_id)
+}
+
+/// Permanently delete a trashed doc (`not_in_trash` for live docs), then GC its blobs.
+pub fn docs_purge_core(db: &Db, doc_id: &str) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    // A slug may name both a live doc and a trashed one; prefer the trashed match
+    let row: Option<(String, bool)> = conn
+        .query_row(
+            "SELECT id, is_deleted FROM doc WHERE id=?1 OR trashed_slug=?1 OR slug=?1 \
+             ORDER BY is_deleted DESC LIMIT 1",
+            params![doc_id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let (id, is_deleted) = row.ok_or("not_found")?;
+    if !is_deleted {
+        return Err("not_in_trash".into());
+    }
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    purge_doc(&tx, &id)?;
+    let (blobs_deleted, bytes_freed) = crate::blob::gc(&tx)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"purged": 1, "blobs_deleted": blobs_deleted, "bytes_freed": bytes_freed}))
+}
+
+/// Purge docs trashed longer than the retention period. Run at startup.
+pub fn purge_expired_trash(db: &Db) -> Result<usize, String> {
+    let conn = db.0.lock();
+    let days = trash_retention_days(&conn);
+    let expired: Vec<String> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id FROM doc WHERE is_deleted=1 \
+                 AND datetime(COALESCE(deleted_at, updated_at), '+' || ?1 || ' days') <= datetime('now')",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![days], |r| r.get(0))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    if expired.is_empty() {
+        return Ok(0);
+    }
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    for id in &expired {
+        purge_doc(&tx, id)?;
+    }
+    crate::blob::gc(&tx)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(expired.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    fn create(db: &Db, id: &str, slug: &str, body: &str) {
+        let conn = db.0.lock();
+        conn.execute(
+            "INSERT INTO doc(id,repo_id,folder_id,slug,title) VALUES(?1,'r','f',?2,?2)",
+            params![id, slug],
+        )
+        .unwrap();
+        let blob = crate::blob::put_text(&conn, body).unwrap();
+        conn.execute(
+            "INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES(?1,?1,?2,'h')",
+            params![id, blob],
+        )
+        .unwrap();
+        conn.execute("UPDATE doc SET current_version_id=id WHERE id=?1", params![id]).unwrap();
+        crate::fts::insert_doc(&conn, id, body).unwrap();
+        crate::graph::update_links_for_doc(&conn, id, body).unwrap();
+    }
+
+    fn count(db: &Db, sql: &str) -> i64 {
+        db.0.lock().query_row(sql, [], |r| r.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_trash_restore_and_purge() {
+        let db = db_with_docs("trash", &[]);
+        create(&db, "a", "alpha", "alpha body zebra");
+        create(&db, "b", "beta", "links to [[alpha]]");
+
+        assert_eq!(docs_delete_core(&db, "alpha").unwrap()["deleted"], true);
+        assert_eq!(docs_delete_core(&db, "a").unwrap()["deleted"], false);
+        assert_eq!(super::super::docs_get_core(&db, "a", false).unwrap_err(), "not_found");
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM doc_fts_tri WHERE doc_fts_tri MATCH 'zebra'"), 0);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM link WHERE to_doc_id='a'"), 0);
+        let trash = docs_trash_list_core(&db, Some("r")).unwrap();
+        assert_eq!((trash.len(), trash[0].slug.as_str()), (1, "alpha"));
+
+        // The slug is free again; restoring onto it then needs another slug
+        create(&db, "a2", "alpha", "replacement");
+        assert_eq!(docs_restore_deleted_core(&db, "a", None).unwrap_err(), "slug_taken");
+        let res = docs_restore_deleted_core(&db, "a", Some("alpha-old".into())).unwrap();
+        assert_eq!(res["slug"], "alpha-old");
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM doc_fts_tri WHERE doc_fts_tri MATCH 'zebra'"), 1);
+
+        assert_eq!(docs_purge_core(&db, "a").unwrap_err(), "not_in_trash");
+        docs_delete_core(&db, "a").unwrap();
+        let purged = docs_purge_core(&db, "a").unwrap();
+        assert_eq!(purged["blobs_deleted"], 1);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM doc WHERE id='a'"), 0);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM doc_version WHERE doc_id='a'"), 0);
+
+        // Expired trash goes at startup
+        docs_delete_core(&db, "b").unwrap();
+        assert_eq!(purge_expired_trash(&db).unwrap(), 0);
+        db.0.lock()
+            .execute("UPDATE doc SET deleted_at=datetime('now','-31 days') WHERE id='b'", [])
+            .unwrap();
+        assert_eq!(purge_expired_trash(&db).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_trashed_doc_not_updated_or_found() {
+        let db = db_with_docs("trash-update", &[]);
+        create(&db, "a", "alpha", "alpha body");
+        docs_delete_core(&db, "a").unwrap();
+        let update = super::super::DocUpdate {
+            doc_id: "a".into(),
+            body: "quokka sightings".into(),
+            message: None,
+            base_version_id: None,
+        };
+        assert_eq!(super::super::docs_update_core(&db, update).unwrap_err(), "not_found");
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM doc_version WHERE doc_id='a'"), 1);
+
+        // Stale index rows for a trashed doc never surface
+        crate::fts::reindex_doc(&db.0.lock(), "a", "quokka sightings").unwrap();
+        for mode in ["fts", "substring", "fuzzy"] {
+            for query in ["quokka", "qu"] {
+                let req = super::super::SearchRequest {
+                    repo_id: None,
+                    query: query.into(),
+                    limit: None,
+                    offset: None,
+                    mode: Some(mode.into()),
+                };
+                assert!(super::super::search_core(&db, req).unwrap().is_empty(), "{mode} {query}");
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Version history commands: list, fetch, diff and restore
+
+use crate::db::Db;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use tauri::State;
+
+#[derive(Serialize)]
+pub struct VersionInfo {
+    pub id: String,
+    pub doc_id: String,
+    pub author: Option<String>,
+    pub message: Option<String>,
+    pub created_at: String,
+    pub hash: String,
+    pub size_bytes: i64,
+    pub is_current: bool,
+}
+
+#[derive(Serialize)]
+pub struct DiffLine {
+    /// `equal`, `insert` or `delete`
+    pub tag: &'static str,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct DocDiff {
+    pub from: String,
+    pub to: String,
+    pub added: usize,
+    pub removed: usize,
+    pub unified: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<Vec<DiffLine>>,
+}
+
+/// Body text of a version, read from its blob.
+pub(crate) fn version_body(conn: &Connection, version_id: &str) -> Result<String, String> {
+    let blob_id: Option<String> = conn
+        .query_row(
+            "SELECT blob_id FROM doc_version WHERE id=?1",
+            params![version_id],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    crate::blob::get_text(conn, &blob_id.ok_or("not_found")?)
+}
+
+/// Resolve a doc id or slug to `(id, current_version_id)`; trashed docs are `not_found`.
+fn resolve_doc(conn: &Connection, doc: &str) -> Result<(String, Option<String>), String> {
+    conn.query_row(
+        "SELECT id, current_version_id FROM doc WHERE (id=?1 OR slug=?1) AND is_deleted=0 LIMIT 1",
+        params![doc],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "not_found".into())
+}
+
+const VERSION_COLS: &str = "v.id, v.doc_id, v.author, v.message, v.created_at, v.hash, b.size_bytes, \
+     v.id = d.current_version_id \
+     FROM doc_version v JOIN doc d ON d.id=v.doc_id JOIN doc_blob b ON b.id=v.blob_id";
+
+fn version_row(r: &rusqlite::Row) -> rusqlite::Result<VersionInfo> {
+    Ok(VersionInfo {
+        id: r.get(0)?,
+        doc_id: r.get(1)?,
+        author: r.get(2)?,
+        message: r.get(3)?,
+        created_at: r.get(4)?,
+        hash: r.get(5)?,
+        size_bytes: r.get(6)?,
+        is_current: r.get(7)?,
+    })
+}
+
+#[tauri::command]
+pub async fn docs_versions_list(
+    doc_id: String,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<Vec<VersionInfo>, String> {
+    docs_versions_list_core(&db, &doc_id, limit, offset)
+}
+
+/// Versions of a doc, newest first.
+pub fn docs_versions_list_core(
+    db: &Db,
+    doc_id: &str,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<VersionInfo>, String> {
+    let conn = db.0.lock();
+    let (doc_id, _) = resolve_doc(&conn, doc_id)?;
+    let sql = format!(
+        "SELECT {} WHERE v.doc_id=?1 ORDER BY v.created_at DESC, v.rowid DESC LIMIT ?2 OFFSET ?3",
+        VERSION_COLS
+    );
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(
+            params![doc_id, limit.unwrap_or(100), offset.unwrap_or(0)],
+            version_row,
+        )
+        .map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    for r in rows {
+        out.push(r.map_err(|e| e.to_string())?)
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub async fn docs_version_get(
+    version_id: String,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    docs_version_get_core(&db, &version_id)
+}
+
+/// Version metadata plus its body.
+pub fn docs_version_get_core(db: &Db, version_id: &str) -> Result<serde_json::Value, String> {
+    let conn = db.0.lock();
+    let info = conn
+        .query_row(
+            &format!("SELECT {} WHERE v.id=?1 AND d.is_deleted=0", VERSION_COLS),
+            params![version_id],
+            version_row,
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or("not_found")?;
+    let body = version_body(&conn, version_id)?;
+    let mut out = serde_json::to_value(info).map_err(|e| e.to_string())?;
+    out["body"] = serde_json::Value::String(body);
+    Ok(out)
+}
+
+#[tauri::command]
+pub async fn docs_diff(
+    doc_id: String,
+    from: String,
+    to: Option<String>,
+    format: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<DocDiff, String> {
+    docs_diff_core(&db, &doc_id, &from, to.as_deref(), format.as_deref())
+}
+
+/// Diff two versions of a doc; `to` defaults to the current version.
+/// `format`: `unified` (default) or `lines` (also returns per-line changes).
+pub fn docs_diff_core(
+    db: &Db,
+    doc_id: &str,
+    from: &str,
+    to: Option<&str>,
+    format: Option<&str>,
+) -> Result<DocDiff, String> {
+    let with_lines = match format.unwrap_or("unified") {
+        "unified" => false,
+        "lines" => true,
+        _ => return Err("invalid_format".into()),
+    };
+    let conn = db.0.lock();
+    let (doc_id, current) = resolve_doc(&conn, doc_id)?;
+    let to = match to {
+        Some(t) => t.to_string(),
+        None => current.ok_or("not_found")?,
+    };
+    for v in [from, to.as_str()] {
+        let owner: Option<String> = conn
+            .query_row("SELECT doc_id FROM doc_version WHERE id=?1", params![v], |r| r.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if owner.as_deref() != Some(doc_id.as_str()) {
+            return Err("not_found".into());
+        }
+    }
+    let old = version_body(&conn, from)?;
+    let new = version_body(&conn, &to)?;
+    Ok(diff_texts(from, &to, &old, &new, with_lines))
+}
+
+pub(crate) fn diff_texts(from: &str, to: &str, old: &str, new: &str, with_lines: bool) -> DocDiff {
+    let diff = TextDiff::from_lines(old, new);
+    let unified = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(from, to)
+        .to_string();
+    let (mut added, mut removed) = (0, 0);
+    let mut lines = Vec::new();
+    for change in diff.iter_all_changes() {
+        let tag = match change.tag() {
+            ChangeTag::Equal => "equal",
+            ChangeTag::Insert => {
+                added += 1;
+                "insert"
+            }
+            ChangeTag::Delete => {
+                removed += 1;
+                "delete"
+            }
+        };
+        if with_lines {
+            lines.push(DiffLine {
+                tag,
+                old_line: change.old_index().map(|i| i + 1),
+                new_line: change.new_index().map(|i| i + 1),
+                text: change.value().trim_end_matches('\n').to_string(),
+            });
+        }
+    }
+    DocDiff {
+        from: from.to_string(),
+        to: to.to_string(),
+        added,
+        removed,
+        unified,
+        lines: with_lines.then_some(lines),
+    }
+}
+
+#[tauri::command]
+pub async fn docs_restore(
+    doc_id: String,
+    version_id: String,
+    message: Option<String>,
+    db: State<'_, std::sync::Arc<Db>>,
+) -> Result<serde_json::Value, String> {
+    docs_restore_core(&db, &doc_id, &version_id, message)
+}
+
+/// Append a new version whose body is that of an older one; history is never rewritten.
+pub fn docs_restore_core(
+    db: &Db,
+    doc_id: &str,
+    version_id: &str,
+    message: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let (doc_id, body) = {
+        let conn = db.0.lock();
+        let (doc_id, _) = resolve_doc(&conn, doc_id)?;
+        let owner: Option<String> = conn
+            .query_row("SELECT doc_id FROM doc_version WHERE id=?1", params![version_id], |r| r.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if owner.as_deref() != Some(doc_id.as_str()) {
+            return Err("not_found".into());
+        }
+        (doc_id.clone(), version_body(&conn, version_id)?)
+    };
+    let mut res = super::docs_update_core(
+        db,
+        super::DocUpdate {
+            doc_id,
+            body,
+            message: Some(message.unwrap_or_else(|| format!("Restore version {}", version_id))),
+            base_version_id: None,
+        },
+    )?;
+    res["restored_from"] = serde_json::Value::String(version_id.to_string());
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::db_with_docs;
+
+    fn update(db: &Db, body: &str) -> serde_json::Value {
+        update_from(db, body, None)
+    }
+
+    fn update_from(db: &Db, body: &str, base: Option<&str>) -> serde_json::Value {
+        super::super::docs_update_core(
+            db,
+            super::super::DocUpdate {
+                doc_id: "d".into(),
+                body: body.into(),
+                message: None,
+                base_version_id: base.map(String::from),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_restore_appends_repeated_body() {
+        let db = db_with_docs("version-test", &[("d", "note")]);
+        let v1 = update(&db, "alpha\n")["version_id"].as_str().unwrap().to_string();
+        update(&db, "beta\n");
+        let diff = docs_diff_core(&db, "note", &v1, None, None).unwrap();
+        assert_eq!((diff.added, diff.removed), (1, 1));
+        let restored = docs_restore_core(&db, "d", &v1, None).unwrap();
+        assert_eq!(restored["restored_from"], v1.as_str());
+        let versions = docs_versions_list_core(&db, "d", None, None).unwrap();
+        assert_eq!(versions.len(), 3);
+        assert!(versions[0].is_current);
+        assert_eq!(versions[0].message.as_deref(), Some(format!("Restore version {}", v1).as_str()));
+        let current = docs_version_get_core(&db, &versions[0].id).unwrap();
+        assert_eq!(current["body"], "alpha\n");
+        assert_eq!(docs_diff_core(&db, "d", "missing", None, None).err().as_deref(), Some("not_found"));
+    }
+
+    #[test]
+    fn test_history_of_trashed_doc_not_found() {
+        let db = db_with_docs("version-trash", &[("d", "note")]);
+        let v1 = update(&db, "alpha\n")["version_id"].as_str().unwrap().to_string();
+        let v2 = update(&db, "beta\n")["version_id"].as_str().unwrap().to_string();
+        super::super::docs_delete_core(&db, "d").unwrap();
+        assert_eq!(docs_versions_list_core(&db, "d", None, None).err().as_deref(), Some("not_found"));
+        assert_eq!(docs_version_get_core(&db, &v1).unwrap_err(), "not_found");
+        assert_eq!(docs_diff_core(&db, "d", &v1, None, None).err().as_deref(), Some("not_found"));
+        assert_eq!(docs_restore_core(&db, "d", &v1, None).unwrap_err(), "not_found");
+        let current: String = db
+            .0
+            .lock()
+            .query_row("SELECT current_version_id FROM doc WHERE id='d'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(current, v2);
+    }
+
+    #[test]
+    fn test_stale_base_merges_or_conflicts() {
+        let db = db_with_docs("merge-test", &[("d", "note")]);
+        let base = update(&db, "title\n\nintro\n\nend\n")["version_id"].as_str().unwrap().to_string();
+        // Another writer changes the end
+        update(&db, "title\n\nintro\n\nEND\n");
+        // Editor still on `base` changes the intro: merged, both edits kept
+        let res = update_from(&db, "title\n\nINTRO\n\nend\n", Some(&base));
+        assert!(res["merged_with"].is_string());
+        assert_eq!(res["body"], "title\n\nINTRO\n\nEND\n");
+        // Editor edits the same line from the stale base: conflict, nothing written
+        let before = docs_versions_list_core(&db, "d", None, None).unwrap().len();
+        let res = update_from(&db, "title\n\nintro\n\nFin\n", Some(&base));
+        assert_eq!(res["conflict"]["conflicts"][0]["ours"][0], "Fin");
+        assert_eq!(res["conflict"]["conflicts"][0]["theirs"][0], "END");
+        assert!(res.get("version_id").is_none());
+        assert_eq!(docs_versions_list_core(&db, "d", None, None).unwrap().len(), before);
+    }
+
+    #[test]
+    fn test_diff_texts_counts_and_lines() {
+        let d = diff_texts("a", "b", "one\ntwo\nthree\n", "one\n2\nthree\nfour\n", true);
+        assert_eq!((d.added, d.removed), (2, 1));
+        assert!(d.unified.starts_with("--- a\n+++ b\n"));
+        assert!(d.unified.contains("-two\n+2\n"));
+        let lines = d.lines.unwrap();
+        assert_eq!(lines.len(), 5);
+        assert_eq!((lines[1].tag, lines[1].old_line, lines[1].new_line), ("delete", Some(2), None));
+        assert_eq!((lines[4].tag, lines[4].new_line, lines[4].text.as_str()), ("insert", Some(4), "four"));
+        assert!(diff_texts("a", "b", "x\n", "x\n", false).lines.is_none());
+    }
+}
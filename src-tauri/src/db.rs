@@ -14,7 +14,8 @@
 use parking_lot::Mutex;
 use rusqlite::Connection;
 
-pub struct Db(pub Mutex<Connection>);
+/// The connection, plus the queue of embedding refreshes waiting to run against it.
+pub struct Db(pub Mutex<Connection>, pub crate::ai::embed::RefreshQueue);
 
 pub fn open_db(path: &std::path::Path) -> Result<Db, Box<dyn std::error::Error>> {
     std::fs::create_dir_all(path.parent().unwrap())?;
@@ -23,15 +24,102 @@ pub fn open_db(path: &std::path::Path) -> Result<Db, Box<dyn std::error::Error>>
     conn.pragma_update(None, "journal_mode", &"WAL")?;
     conn.pragma_update(None, "synchronous", &"NORMAL")?;
     conn.pragma_update(None, "foreign_keys", &true)?;
+    // Tables added after release that need a one-time fill from existing docs
+    let had_doc_tag: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='doc_tag')",
+        [],
+        |r| r.get(0),
+    )?;
+    migrate_version_hash(&conn)?;
     // DDL
     conn.execute_batch(include_str!("../schema.sql"))?;
+    migrate_doc_trash(&conn)?;
     // Seed providers (privacy defaults)
     seed_providers(&mut conn)?;
     // Ensure app-controlled FTS updates: drop any leftover triggers that try to sync body from blobs
     let _ = conn.execute("DROP TRIGGER IF EXISTS doc_version_ai", []);
     let _ = conn.execute("DROP TRIGGER IF EXISTS doc_ai", []);
     let _ = conn.execute("DROP TRIGGER IF EXISTS doc_au", []);
-    Ok(Db(Mutex::new(conn)))
+    // Folders used to be a flat list; link them into a tree
+    crate::commands::repair_folder_tree(&conn)?;
+    // Anchors used to be provenance rows holding only a line
+    crate::commands::migrate_provenance_anchors(&conn)?;
+    // Older databases predate the trigram index; fill it from current versions once
+    crate::fts::backfill_trigram(&conn)?;
+    if !had_doc_tag {
+        crate::fts::backfill_tags(&conn)?;
+    }
+    // Blobs written before content addressing used random ids; re-key them once
+    let tx = conn.unchecked_transaction()?;
+    crate::blob::migrate_to_content_ids(&tx)?;
+    tx.commit()?;
+    Ok(Db(Mutex::new(conn), Default::default()))
+}
+
+/// Early databases declared `doc_version.hash` UNIQUE, which rejects reverting a doc to an
+/// earlier body (restore, undo in the editor, `git checkout` + rescan). Rebuild without it,
+/// keeping rowids (the history index is keyed by them).
+fn migrate_version_hash(conn: &Connection) -> Result<(), Box<dyn std::error::Error>> {
+    let sql: Option<String> = conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type='table' AND name='doc_version'",
+            [],
+            |r| r.get(0),
+        )
+        .ok();
+    if !sql.map(|s| s.contains("hash TEXT NOT NULL UNIQUE")).unwrap_or(false) {
+        return Ok(());
+    }
+    conn.pragma_update(None, "foreign_keys", false)?;
+    conn.execute_batch(
+        "BEGIN;
+         CREATE TABLE doc_version_new (
+           id TEXT PRIMARY KEY,
+           doc_id TEXT NOT NULL REFERENCES doc(id) ON DELETE CASCADE,
+           blob_id TEXT NOT NULL REFERENCES doc_blob(id) ON DELETE RESTRICT,
+           author TEXT,
+           message TEXT,
+           created_at TEXT NOT NULL DEFAULT (datetime('now')),
+           hash TEXT NOT NULL
+         );
+         INSERT INTO doc_version_new(rowid,id,doc_id,blob_id,author,message,created_at,hash)
+           SELECT rowid,id,doc_id,blob_id,author,message,created_at,hash FROM doc_version;
+         DROP TABLE doc_version;
+         ALTER TABLE doc_version_new RENAME TO doc_version;
+         COMMIT;",
+    )?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    Ok(())
+}
+
+/// Add the trash columns and move docs deleted before the trash existed into it, freeing
+/// their slugs and dropping their search rows and links.
+fn migrate_doc_trash(conn: &Connection) -> Result<(), Box<dyn std::error::Error>> {
+    let has_column: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('doc') WHERE name='trashed_slug')",
+        [],
+        |r| r.get(0),
+    )?;
+    if has_column {
+        return Ok(());
+    }
+    conn.execute_batch(
+        "ALTER TABLE doc ADD COLUMN deleted_at TEXT;
+         ALTER TABLE doc ADD COLUMN trashed_slug TEXT;",
+    )?;
+    let deleted: Vec<String> = conn
+        .prepare("SELECT id FROM doc WHERE is_deleted=1")?
+        .query_map([], |r| r.get(0))?
+        .collect::<Result<_, _>>()?;
+    for id in &deleted {
+        crate::fts::remove_doc(conn, id)?;
+    }
+    conn.execute_batch(
+        "UPDATE link SET to_doc_id=NULL WHERE to_doc_id IN (SELECT id FROM doc WHERE is_deleted=1);
+         DELETE FROM link WHERE from_doc_id IN (SELECT id FROM doc WHERE is_deleted=1);
+         UPDATE doc SET deleted_at=updated_at, trashed_slug=slug, slug='.trash/'||id WHERE is_deleted=1;",
+    )?;
+    Ok(())
 }
 
 fn seed_providers(conn: &mut Connection) -> Result<(), Box<dyn std::error::Error>> {
@@ -51,3 +139,32 @@ fn seed_providers(conn: &mut Connection) -> Result<(), Box<dyn std::error::Error
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_version_hash_drops_unique() {
+        let p = std::env::temp_dir().join(format!("ae-migrate-test-{}.db", uuid::Uuid::new_v4()));
+        {
+            let conn = Connection::open(&p).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE doc_version (id TEXT PRIMARY KEY, doc_id TEXT NOT NULL, blob_id TEXT NOT NULL, \
+                 author TEXT, message TEXT, created_at TEXT NOT NULL DEFAULT (datetime('now')), hash TEXT NOT NULL UNIQUE);
+                 INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES('v1','d','b','d:h');",
+            )
+            .unwrap();
+        }
+        let db = open_db(&p).expect("open db");
+        let conn = db.0.lock();
+        // The fixture has no doc/blob rows; only the hash constraint is under test
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        conn.execute("INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES('v2','d','b','d:h')", [])
+            .expect("duplicate hash allowed after migration");
+        let rowid: i64 = conn
+            .query_row("SELECT rowid FROM doc_version WHERE id='v1'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(rowid, 1);
+    }
+}
@@ -0,0 +1,415 @@
+//! Full-text index maintenance and fuzzy matching helpers.
+//!
+//! Two FTS5 tables are kept in lockstep with the current doc body:
+//! - `doc_fts` (unicode61, external content) for whole-token bm25 search
+//! - `doc_fts_tri` (trigram, stores its own text) for substring and typo-tolerant search
+//!
+//! Writers should go through `insert_doc`/`reindex_doc` so both stay consistent; they also
+//! refresh the doc's `doc_tag` rows used for search facets.
+//!
+//! `doc_version_fts` is the opt-in history index (app setting `history_index`): every
+//! version body written while it is enabled goes through `index_version`.
+
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+
+/// Minimum normalized similarity for a fuzzy term to count as a hit.
+pub const FUZZY_MIN_SIMILARITY: f64 = 0.7;
+
+/// Reciprocal-rank-fusion damping constant.
+const RRF_K: f64 = 60.0;
+
+/// Index a freshly inserted doc (no previous FTS rows).
+pub fn insert_doc(conn: &Connection, doc_id: &str, body: &str) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO doc_fts(rowid,title,body,slug,repo_id) SELECT d.rowid,d.title,?1,d.slug,d.repo_id FROM doc d WHERE d.id=?2",
+        params![body, doc_id],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO doc_fts_tri(rowid,title,body,slug) SELECT d.rowid,d.title,?1,d.slug FROM doc d WHERE d.id=?2",
+        params![body, doc_id],
+    )
+    .map_err(|e| e.to_string())?;
+    write_tags(conn, doc_id, body)
+}
+
+fn write_tags(conn: &Connection, doc_id: &str, body: &str) -> Result<(), String> {
+    conn.execute("DELETE FROM doc_tag WHERE doc_id=?1", params![doc_id])
+        .map_err(|e| e.to_string())?;
+    for tag in crate::graph::extract_tags(body) {
+        conn.execute(
+            "INSERT OR IGNORE INTO doc_tag(doc_id,tag) VALUES(?1,?2)",
+            params![doc_id, tag],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Derive `doc_tag` rows from current versions (databases created before tags were tracked).
+pub fn backfill_tags(conn: &Connection) -> Result<usize, String> {
+    let docs = crate::blob::current_bodies(conn, None)?;
+    for (doc_id, body) in &docs {
+        write_tags(conn, doc_id, body)?;
+    }
+    Ok(docs.len())
+}
+
+/// Drop a doc's FTS and tag rows (trash, purge, reindex).
+pub fn remove_doc(conn: &Connection, doc_id: &str) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO doc_fts(doc_fts,rowid) VALUES('delete',(SELECT rowid FROM doc WHERE id=?1))",
+        params![doc_id],
+    )
+    .ok();
+    conn.execute(
+        "DELETE FROM doc_fts_tri WHERE rowid=(SELECT rowid FROM doc WHERE id=?1)",
+        params![doc_id],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM doc_tag WHERE doc_id=?1", params![doc_id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Replace the FTS rows of an existing doc (delete+insert).
+pub fn reindex_doc(conn: &Connection, doc_id: &str, body: &str) -> Result<(), String> {
+    remove_doc(conn, doc_id)?;
+    insert_doc(conn, doc_id, body)
+}
+
+/// Populate `doc_fts_tri` from current versions when the table is empty (older databases).
+pub fn backfill_trigram(conn: &Connection) -> Result<usize, String> {
+    let existing: i64 = conn
+        .query_row("SELECT COUNT(*) FROM doc_fts_tri", [], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    if existing > 0 {
+        return Ok(0);
+    }
+    let docs = crate::blob::current_bodies(conn, None)?;
+    for (doc_id, body) in &docs {
+        conn.execute(
+            "INSERT INTO doc_fts_tri(rowid,title,body,slug) SELECT d.rowid,d.title,?1,d.slug FROM doc d WHERE d.id=?2",
+            params![body, doc_id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(docs.len())
+}
+
+/// Whether the version history index is enabled (app setting `history_index`, default off).
+pub fn history_enabled(conn: &Connection) -> bool {
+    conn.query_row("SELECT value FROM app_setting WHERE key='history_index'", [], |r| r.get::<_, String>(0))
+        .ok()
+        .and_then(|v| serde_json::from_str::<serde_json::Value>(&v).ok())
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Add a version body to the history index when enabled. Call after the `doc_version` row exists.
+pub fn index_version(conn: &Connection, version_id: &str, body: &str) -> Result<(), String> {
+    if !history_enabled(conn) {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO doc_version_fts(rowid,body) SELECT rowid, ?1 FROM doc_version WHERE id=?2",
+        params![body, version_id],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+/// Index every version not yet in the history index; returns the number added.
+pub fn backfill_history(conn: &Connection) -> Result<usize, String> {
+    let pending: Vec<(i64, Vec<u8>, String)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT v.rowid, b.content, COALESCE(b.encoding,'utf8') FROM doc_version v JOIN doc_blob b ON b.id=v.blob_id \
+                 WHERE v.rowid NOT IN (SELECT rowid FROM doc_version_fts)",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .map_err(|e| e.to_string())?;
+        rows.filter_map(|r| r.ok()).collect()
+    };
+    for (rowid, stored, encoding) in &pending {
+        let body = crate::blob::decode_text(stored.clone(), encoding)?;
+        conn.execute(
+            "INSERT INTO doc_version_fts(rowid,body) VALUES(?1,?2)",
+            params![rowid, body],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(pending.len())
+}
+
+/// Drop all history index rows (used when the index is switched off).
+pub fn clear_history(conn: &Connection) -> Result<(), String> {
+    conn.execute("DELETE FROM doc_version_fts", [])
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Lowercased alphanumeric terms of a free-text query.
+pub fn query_terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+fn quote(term: &str) -> String {
+    format!("\"{}\"", term.replace('"', "\"\""))
+}
+
+/// Trigram MATCH expression requiring every term of 3+ chars as a substring; shorter terms
+/// can't be matched through trigrams and are left to [`short_term_patterns`].
+pub fn substring_match_expr(query: &str) -> Option<String> {
+    let parts: Vec<String> = query
+        .split_whitespace()
+        .filter(|t| t.chars().count() >= 3)
+        .map(quote)
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" AND "))
+    }
+}
+
+/// LIKE patterns (escaped with `\`) requiring each whitespace-separated term under 3 chars
+/// as a substring.
+pub fn short_term_patterns(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .filter(|t| t.chars().count() < 3)
+        .map(|t| format!("%{}%", t.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")))
+        .collect()
+}
+
+/// Trigram MATCH expression that ORs every trigram of every term; used to gather fuzzy candidates.
+pub fn fuzzy_match_expr(query: &str) -> Option<String> {
+    let mut grams: Vec<String> = Vec::new();
+    for term in query_terms(query) {
+        let chars: Vec<char> = term.chars().collect();
+        for w in chars.windows(3) {
+            let g: String = w.iter().collect();
+            if !grams.contains(&g) {
+                grams.push(g);
+            }
+        }
+    }
+    if grams.is_empty() {
+        None
+    } else {
+        Some(grams.iter().map(|g| quote(g)).collect::<Vec<_>>().join(" OR "))
+    }
+}
+
+/// Levenshtein edit distance over chars.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        cur[0] = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            cur[j] = (prev[j] + 1).min(cur[j - 1] + 1).min(prev[j - 1] + cost);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+fn term_similarity(term: &str, word: &str) -> f64 {
+    if word.contains(term) {
+        return 1.0;
+    }
+    let max_len = term.chars().count().max(word.chars().count());
+    if max_len == 0 {
+        return 0.0;
+    }
+    1.0 - levenshtein(term, word) as f64 / max_len as f64
+}
+
+/// Average best-word similarity of the query terms against `text` (0.0..=1.0).
+pub fn fuzzy_score(terms: &[String], text: &str) -> f64 {
+    if terms.is_empty() {
+        return 0.0;
+    }
+    let words: Vec<String> = query_terms(text);
+    let mut total = 0.0;
+    for term in terms {
+        let tlen = term.chars().count();
+        let best = words
+            .iter()
+            .filter(|w| w.chars().count() + 3 >= tlen)
+            .map(|w| term_similarity(term, w))
+            .fold(0.0_f64, f64::max);
+        total += best;
+    }
+    total / terms.len() as f64
+}
+
+/// Reciprocal rank fusion of several ranked id lists; highest fused score first.
+pub fn rrf_merge(lists: &[Vec<String>]) -> Vec<(String, f64)> {
+    let mut scores: HashMap<String, f64> = HashMap::new();
+    let mut first_seen: Vec<String> = Vec::new();
+    for list in lists {
+        for (pos, id) in list.iter().enumerate() {
+            let e = scores.entry(id.clone()).or_insert_with(|| {
+                first_seen.push(id.clone());
+                0.0
+            });
+            *e += 1.0 / (RRF_K + pos as f64 + 1.0);
+        }
+    }
+    let mut out: Vec<(String, f64)> = first_seen
+        .into_iter()
+        .map(|id| {
+            let s = scores[&id];
+            (id, s)
+        })
+        .collect();
+    out.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open_db;
+    use crate::test_util::db_with_docs;
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("kubernetes", "kuberntes"), 1);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("same", "same"), 0);
+    }
+
+    #[test]
+    fn test_fuzzy_score_typo_and_prefix() {
+        let terms = query_terms("Kuberntes");
+        assert!(fuzzy_score(&terms, "Deploying to kubernetes clusters") >= FUZZY_MIN_SIMILARITY);
+        let terms = query_terms("kubern");
+        assert_eq!(fuzzy_score(&terms, "kubernetes"), 1.0);
+        let terms = query_terms("postgres");
+        assert!(fuzzy_score(&terms, "kubernetes") < FUZZY_MIN_SIMILARITY);
+    }
+
+    #[test]
+    fn test_match_exprs() {
+        assert_eq!(substring_match_expr("ab kubern").as_deref(), Some("\"kubern\""));
+        assert_eq!(substring_match_expr("a b"), None);
+        assert_eq!(short_term_patterns("ab kubern %"), vec!["%ab%", "%\\%%"]);
+        assert_eq!(fuzzy_match_expr("abcd").as_deref(), Some("\"abc\" OR \"bcd\""));
+    }
+
+    #[test]
+    fn test_rrf_merge_prefers_docs_in_both_lists() {
+        let merged = rrf_merge(&[
+            vec!["a".into(), "b".into()],
+            vec!["c".into(), "b".into()],
+        ]);
+        assert_eq!(merged[0].0, "b");
+        assert_eq!(merged.len(), 3);
+    }
+
+    #[test]
+    fn test_trigram_index_substring_match() {
+        let db = db_with_docs("fts-test", &[("d", "k8s")]);
+        let conn = db.0.lock();
+        reindex_doc(&conn, "d", "Notes on kubernetes operators").unwrap();
+        let expr = substring_match_expr("kubern").unwrap();
+        let n: i64 = conn
+            .query_row("SELECT COUNT(*) FROM doc_fts_tri WHERE doc_fts_tri MATCH ?1", params![expr], |r| r.get(0))
+            .unwrap();
+        assert_eq!(n, 1);
+        reindex_doc(&conn, "d", "nothing relevant").unwrap();
+        let n: i64 = conn
+            .query_row("SELECT COUNT(*) FROM doc_fts_tri WHERE doc_fts_tri MATCH ?1", params![expr], |r| r.get(0))
+            .unwrap();
+        assert_eq!(n, 0);
+    }
+
+    #[test]
+    fn test_history_index_opt_in() {
+        let db = db_with_docs("fts-hist", &[("d", "n")]);
+        let conn = db.0.lock();
+        for (v, body) in [("v1", "old paragraph about pricing"), ("v2", "rewritten without it")] {
+            conn.execute("INSERT INTO doc_blob(id,content,size_bytes) VALUES(?1,?2,?3)", params![v, body.as_bytes(), body.len() as i64]).unwrap();
+            conn.execute("INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES(?1,'d',?1,?1)", params![v]).unwrap();
+        }
+        let count = |c: &Connection| -> i64 {
+            c.query_row("SELECT COUNT(*) FROM doc_version_fts WHERE doc_version_fts MATCH 'pricing'", [], |r| r.get(0)).unwrap()
+        };
+        // Disabled: writes are ignored
+        index_version(&conn, "v1", "old paragraph about pricing").unwrap();
+        assert_eq!(count(&conn), 0);
+        conn.execute("INSERT INTO app_setting(key,value) VALUES('history_index','true')", []).unwrap();
+        assert!(history_enabled(&conn));
+        assert_eq!(backfill_history(&conn).unwrap(), 2);
+        assert_eq!(backfill_history(&conn).unwrap(), 0);
+        assert_eq!(count(&conn), 1);
+        clear_history(&conn).unwrap();
+        assert_eq!(count(&conn), 0);
+    }
+
+    #[test]
+    fn test_backfill_skips_trashed_docs() {
+        let p = std::env::temp_dir().join(format!("ae-fts-trash-{}.db", uuid::Uuid::new_v4()));
+        {
+            let db = open_db(&p).expect("open db");
+            {
+                let conn = db.0.lock();
+                conn.execute("INSERT INTO repo(id,name,path) VALUES('r','r','/r')", []).unwrap();
+                conn.execute("INSERT INTO folder(id,repo_id,path,slug) VALUES('f','r','','')", []).unwrap();
+                for (id, slug) in [("a", "live"), ("b", "gone")] {
+                    conn.execute(
+                        "INSERT INTO doc(id,repo_id,folder_id,slug,title) VALUES(?1,'r','f',?2,?2)",
+                        params![id, slug],
+                    )
+                    .unwrap();
+                    insert_doc(&conn, id, "").unwrap();
+                }
+            }
+            for id in ["a", "b"] {
+                crate::commands::docs_update_core(
+                    &db,
+                    crate::commands::DocUpdate {
+                        doc_id: id.into(),
+                        body: "notes on kubernetes #ops".into(),
+                        message: None,
+                        base_version_id: None,
+                    },
+                )
+                .unwrap();
+            }
+            crate::commands::docs_delete_core(&db, "b").unwrap();
+            // As in a database from before the trigram index and tags
+            db.0.lock().execute_batch("DELETE FROM doc_fts_tri; DELETE FROM doc_tag;").unwrap();
+        }
+        let db = open_db(&p).expect("reopen db");
+        let hits = crate::commands::search_core(
+            &db,
+            crate::commands::SearchRequest {
+                repo_id: None,
+                query: "kubern".into(),
+                limit: None,
+                offset: None,
+                mode: Some("substring".into()),
+            },
+        )
+        .unwrap();
+        let ids: Vec<&str> = hits.iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, vec!["a"]);
+        let tagged: i64 = db.0.lock().query_row("SELECT COUNT(*) FROM doc_tag WHERE doc_id='b'", [], |r| r.get(0)).unwrap();
+        assert_eq!(tagged, 0);
+    }
+}
@@ -76,6 +76,71 @@ fn extract_wikilinks(content: &str) -> Vec<(String, i64, i64)> {
     res
 }
 
+/// Lowercased, deduplicated tags from frontmatter `tags:` and inline `#tag` tokens (outside code).
+pub fn extract_tags(content: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut push = |t: &str| {
+        let t = t.trim().trim_matches(|c| c == '"' || c == '\'' || c == '#').to_lowercase();
+        if !t.is_empty() && !tags.contains(&t) {
+            tags.push(t);
+        }
+    };
+    let mut lines = content.lines().peekable();
+    // YAML frontmatter: `tags: [a, b]`, `tags: a, b` or a `- item` list
+    if lines.peek().map(|l| l.trim_end() == "---").unwrap_or(false) {
+        lines.next();
+        let mut in_tags = false;
+        for line in lines.by_ref() {
+            let line = line.trim_end();
+            if line == "---" {
+                break;
+            }
+            if let Some(rest) = line.strip_prefix("tags:") {
+                let rest = rest.trim().trim_start_matches('[').trim_end_matches(']');
+                rest.split(',').for_each(&mut push);
+                in_tags = rest.trim().is_empty();
+            } else if in_tags && line.trim_start().starts_with("- ") {
+                push(&line.trim_start()[2..]);
+            } else {
+                in_tags = false;
+            }
+        }
+    }
+    let mut in_fence = false;
+    for line in lines {
+        let line = line.trim_end();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let mut in_inline = false;
+        let mut prev = ' ';
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, ch)) = chars.next() {
+            if ch == '`' {
+                in_inline = !in_inline;
+            } else if !in_inline && ch == '#' && prev.is_whitespace() {
+                let rest = &line[i + 1..];
+                let end = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-' || c == '/'))
+                    .unwrap_or(rest.len());
+                // Headings (`# x`) and numbers (`#12`) are not tags
+                if rest.chars().next().map(|c| c.is_alphabetic()).unwrap_or(false) {
+                    push(&rest[..end]);
+                    while chars.peek().map(|(j, _)| *j <= i + end).unwrap_or(false) {
+                        chars.next();
+                    }
+                }
+            }
+            prev = ch;
+        }
+    }
+    tags
+}
+
 fn split_slug_alias(inner: &str) -> Option<(String, Option<String>)> {
     // Split only on the first '|', alias may contain additional '|'
     let mut iter = inner.splitn(2, '|');
@@ -93,6 +158,14 @@ fn slug_before_heading(slug: String) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_extract_tags_frontmatter_and_inline() {
+        let md = "---\ntitle: x\ntags: [Rust, \"notes\"]\naliases:\n  - a\n---\n# Heading\nSee #todo and #Rust, not #12 or `#code`.\n```\n#fenced\n```\nissue#3 #area/db";
+        assert_eq!(extract_tags(md), vec!["rust", "notes", "todo", "area/db"]);
+        let md = "---\ntags:\n  - one\n  - two\n---\nbody";
+        assert_eq!(extract_tags(md), vec!["one", "two"]);
+    }
+
     #[test]
     fn test_extract_wikilinks_basic() {
         let md = "Line1 [[Alpha|A]] and [[Beta]]\nNext [[Gamma#Section]] end";
@@ -5,9 +5,14 @@ mod commands;
 mod api;
 mod scan;
 mod graph;
+mod blob;
+mod fts;
+mod merge;
 mod secrets;
 mod ai;
 mod plugins;
+#[cfg(test)]
+mod test_util;
 
 use std::path::PathBuf;
 use tauri::Manager;
@@ -23,6 +28,10 @@ fn main() {
             p
         });
     let db_state = std::sync::Arc::new(db::open_db(&db_path).expect("open db"));
+    ai::embed::start_refresh_worker(&db_state);
+    if let Err(e) = commands::purge_expired_trash(&db_state) {
+        eprintln!("[trash] purge failed: {}", e);
+    }
 
     tauri::Builder::default()
         .manage(db_state)
@@ -37,6 +46,7 @@ fn main() {
             commands::repos_info,
             commands::repos_remove,
             commands::repos_set_default_provider,
+            commands::repos_set_fallback_providers,
             commands::app_settings_get,
             commands::app_settings_set,
             commands::scan_repo,
@@ -44,11 +54,59 @@ fn main() {
             commands::docs_update,
             commands::docs_get,
             commands::docs_delete,
+            commands::templates_list,
+            commands::docs_create_from_template,
+            commands::docs_daily,
+            commands::folders_children,
+            commands::folders_create,
+            commands::folders_rename,
+            commands::folders_move,
+            commands::folders_delete,
+            commands::docs_trash_list,
+            commands::docs_restore_deleted,
+            commands::docs_purge,
             commands::export_docs,
             commands::export_db,
             commands::import_docs,
             commands::search,
+            commands::search_semantic,
+            commands::search_history,
+            commands::history_index_set,
+            commands::search_grep,
+            commands::search_faceted,
+            commands::saved_search_save,
+            commands::saved_search_list,
+            commands::saved_search_delete,
+            commands::saved_search_run,
+            commands::docs_versions_list,
+            commands::docs_version_get,
+            commands::docs_diff,
+            commands::docs_restore,
+            commands::gc_blobs,
+            commands::retention_policy_get,
+            commands::retention_policy_set,
+            commands::versions_compact,
+            commands::embeddings_reindex,
+            commands::embeddings_status,
             commands::ai_run,
+            commands::ai_run_stream,
+            commands::ai_run_cancel,
+            commands::ai_chat_send,
+            commands::ai_conversations_list,
+            commands::ai_conversations_get,
+            commands::ai_conversations_fork,
+            commands::ai_conversations_delete,
+            commands::ai_usage_report,
+            commands::ai_context_get,
+            commands::ai_context_set,
+            commands::ai_templates_list,
+            commands::ai_templates_upsert,
+            commands::ai_templates_delete,
+            commands::ai_templates_export,
+            commands::ai_templates_import,
+            commands::ai_limits_get,
+            commands::ai_limits_set,
+            commands::ai_limits_status,
             commands::ai_providers_list,
             commands::ai_providers_enable,
             commands::ai_providers_disable,
@@ -57,6 +115,8 @@ fn main() {
             commands::ai_provider_resolve,
             commands::ai_provider_model_get,
             commands::ai_provider_model_set,
+            commands::ai_provider_config_get,
+            commands::ai_provider_config_set,
             commands::ai_provider_test,
             commands::plugins_list,
             commands::plugins_info,
@@ -71,6 +131,16 @@ fn main() {
             commands::anchors_upsert,
             commands::anchors_list,
             commands::anchors_delete,
+            commands::comments_threads_list,
+            commands::comments_thread_create,
+            commands::comments_reply,
+            commands::comments_resolve,
+            commands::comments_thread_delete,
+            commands::suggestions_create,
+            commands::suggestions_list,
+            commands::suggestions_preview,
+            commands::suggestions_accept,
+            commands::suggestions_reject,
             api::serve_api_start,
         ])
         .run(ctx)
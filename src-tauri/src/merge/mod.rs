@@ -0,0 +1,168 @@
+//! Three-way line merge (diff3) used when a write was based on an older version.
+//!
+//! `ours` is the incoming body, `theirs` the current version and `base` their common
+//! ancestor. Regions changed on only one side are taken from that side; regions changed
+//! identically on both sides are taken once; anything else is a conflict.
+
+use serde::Serialize;
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
+mod patch;
+pub use patch::{apply_patch, make_patch};
+
+/// A region both sides changed differently. Line numbers are 1-based in `base`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub base_start: usize,
+    pub base_end: usize,
+    pub base: Vec<String>,
+    pub ours: Vec<String>,
+    pub theirs: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum MergeOutcome {
+    Clean(String),
+    /// Conflicting regions plus the full text with git-style conflict markers.
+    Conflicted {
+        conflicts: Vec<Conflict>,
+        marked: String,
+    },
+}
+
+fn split_lines(s: &str) -> Vec<&str> {
+    s.split_inclusive('\n').collect()
+}
+
+/// For each base line, the index of the matching line in `other` (if unchanged there).
+fn matches(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut out = vec![None; base.len()];
+    for op in capture_diff_slices(Algorithm::Myers, base, other) {
+        if let DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } = op
+        {
+            for k in 0..len {
+                out[old_index + k] = Some(new_index + k);
+            }
+        }
+    }
+    out
+}
+
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> MergeOutcome {
+    let (b, o, t) = (split_lines(base), split_lines(ours), split_lines(theirs));
+    let (mo, mt) = (matches(&b, &o), matches(&b, &t));
+    let mut out = String::new();
+    let mut marked = String::new();
+    let mut conflicts = Vec::new();
+    let (mut ib, mut io, mut it) = (0, 0, 0);
+    loop {
+        // Next base line unchanged on both sides, at or after the cursors
+        let stable = (ib..b.len()).find_map(|k| match (mo[k], mt[k]) {
+            (Some(x), Some(y)) if x >= io && y >= it => Some((k, x, y)),
+            _ => None,
+        });
+        let (kb, ko, kt) = stable.unwrap_or((b.len(), o.len(), t.len()));
+        if (kb, ko, kt) != (ib, io, it) {
+            let (rb, ro, rt) = (&b[ib..kb], &o[io..ko], &t[it..kt]);
+            let take: Option<&[&str]> = if ro == rb {
+                Some(rt)
+            } else if rt == rb || ro == rt {
+                Some(ro)
+            } else {
+                None
+            };
+            match take {
+                Some(lines) => {
+                    out.extend(lines.iter().copied());
+                    marked.extend(lines.iter().copied());
+                }
+                None => {
+                    let own = |ls: &[&str]| ls.iter().map(|l| l.trim_end_matches('\n').to_string()).collect();
+                    conflicts.push(Conflict {
+                        base_start: ib + 1,
+                        base_end: kb,
+                        base: own(rb),
+                        ours: own(ro),
+                        theirs: own(rt),
+                    });
+                    marked.push_str("<<<<<<< ours\n");
+                    push_block(&mut marked, ro);
+                    marked.push_str("||||||| base\n");
+                    push_block(&mut marked, rb);
+                    marked.push_str("=======\n");
+                    push_block(&mut marked, rt);
+                    marked.push_str(">>>>>>> theirs\n");
+                }
+            }
+        }
+        if stable.is_none() {
+            break;
+        }
+        out.push_str(b[kb]);
+        marked.push_str(b[kb]);
+        (ib, io, it) = (kb + 1, ko + 1, kt + 1);
+    }
+    if conflicts.is_empty() {
+        MergeOutcome::Clean(out)
+    } else {
+        MergeOutcome::Conflicted { conflicts, marked }
+    }
+}
+
+fn push_block(out: &mut String, lines: &[&str]) {
+    for l in lines {
+        out.push_str(l);
+        if !l.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(base: &str, ours: &str, theirs: &str) -> String {
+        match merge3(base, ours, theirs) {
+            MergeOutcome::Clean(s) => s,
+            other => panic!("expected clean merge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_merge_disjoint_edits() {
+        let base = "a\nb\nc\nd\ne\n";
+        assert_eq!(clean(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\n"), "A\nb\nc\nd\nE\n");
+        // Insert on one side, delete on the other
+        assert_eq!(clean(base, "a\nb\nx\nc\nd\ne\n", "a\nb\nc\nd\n"), "a\nb\nx\nc\nd\n");
+        // Same change on both sides is taken once
+        assert_eq!(clean(base, "a\nB\nc\nd\ne\n", "a\nB\nc\nd\ne\n"), "a\nB\nc\nd\ne\n");
+    }
+
+    #[test]
+    fn test_merge_conflict_regions() {
+        match merge3("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc\n") {
+            MergeOutcome::Conflicted { conflicts, marked } => {
+                assert_eq!(
+                    conflicts,
+                    vec![Conflict {
+                        base_start: 2,
+                        base_end: 2,
+                        base: vec!["b".into()],
+                        ours: vec!["ours".into()],
+                        theirs: vec!["theirs".into()],
+                    }]
+                );
+                assert_eq!(
+                    marked,
+                    "a\n<<<<<<< ours\nours\n||||||| base\nb\n=======\ntheirs\n>>>>>>> theirs\nc\n"
+                );
+            }
+            other => panic!("expected conflict, got {:?}", other),
+        }
+    }
+}
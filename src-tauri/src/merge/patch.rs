@@ -0,0 +1,146 @@
+//! Unified diffs: producing them for a pair of bodies and applying them back.
+//!
+//! Hunks are applied in order. A hunk goes where its header says when the removed and
+//! context lines match there, otherwise at the nearest later position where they do;
+//! a hunk that matches nowhere fails the whole patch.
+
+use similar::TextDiff;
+
+struct Hunk {
+    old_start: usize,
+    /// Context and removed lines, with their line endings
+    old: Vec<String>,
+    /// Context and added lines, with their line endings
+    new: Vec<String>,
+}
+
+/// Unified diff (3 lines of context) turning `old` into `new`.
+pub fn make_patch(old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header("a", "b")
+        .to_string()
+}
+
+fn parse_range_start(range: &str) -> Option<usize> {
+    range.split(',').next()?.parse().ok()
+}
+
+fn parse(patch: &str) -> Result<Vec<Hunk>, String> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    // Which side(s) the previous line went to, for "\ No newline at end of file"
+    let mut last: Option<(bool, bool)> = None;
+    // Not `lines()`: that would also strip a `\r`, which belongs to the text of a CRLF doc
+    for line in patch.split_inclusive('\n') {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        if let Some(header) = line.strip_prefix("@@ ") {
+            let mut parts = header.split_whitespace();
+            let old = parts.next().and_then(|p| p.strip_prefix('-'));
+            let old_start = old.and_then(parse_range_start).ok_or("invalid_patch")?;
+            hunks.push(Hunk {
+                old_start,
+                old: Vec::new(),
+                new: Vec::new(),
+            });
+            last = None;
+            continue;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            // Headers (---/+++, diff --git, index ...) before the first hunk
+            continue;
+        };
+        if line.starts_with('\\') {
+            let (in_old, in_new) = last.ok_or("invalid_patch")?;
+            if let Some(l) = hunk.old.last_mut().filter(|_| in_old) {
+                l.pop();
+            }
+            if let Some(l) = hunk.new.last_mut().filter(|_| in_new) {
+                l.pop();
+            }
+            continue;
+        }
+        let (tag, text) = match line.chars().next() {
+            Some(c @ (' ' | '-' | '+')) => (c, &line[1..]),
+            // Some tools drop the space of empty context lines
+            None => (' ', ""),
+            Some('\r') if line.len() == 1 => (' ', line),
+            _ => return Err("invalid_patch".into()),
+        };
+        let text = format!("{}\n", text);
+        let (in_old, in_new) = (tag != '+', tag != '-');
+        if in_old {
+            hunk.old.push(text.clone());
+        }
+        if in_new {
+            hunk.new.push(text);
+        }
+        last = Some((in_old, in_new));
+    }
+    if hunks.is_empty() {
+        return Err("invalid_patch".into());
+    }
+    Ok(hunks)
+}
+
+fn matches_at(base: &[&str], at: usize, old: &[String]) -> bool {
+    at + old.len() <= base.len()
+        && old
+            .iter()
+            .zip(&base[at..])
+            .all(|(o, b)| o.trim_end_matches(['\n', '\r']) == b.trim_end_matches(['\n', '\r']))
+}
+
+/// Apply a unified diff to `base`. Errors: `invalid_patch` (unparseable) and
+/// `patch_mismatch` (a hunk's context is not found).
+pub fn apply_patch(base: &str, patch: &str) -> Result<String, String> {
+    let lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let mut out = String::new();
+    let mut cursor = 0;
+    for hunk in parse(patch)? {
+        // `-0,0` (and pure insertions in general) name the line *after* which to insert
+        let expected = if hunk.old.is_empty() {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        }
+        .max(cursor);
+        let at = (expected..=lines.len())
+            .chain((cursor..expected).rev())
+            .find(|&at| matches_at(&lines, at, &hunk.old))
+            .ok_or("patch_mismatch")?;
+        lines[cursor..at].iter().for_each(|l| out.push_str(l));
+        hunk.new.iter().for_each(|l| out.push_str(l));
+        cursor = at + hunk.old.len();
+    }
+    lines[cursor..].iter().for_each(|l| out.push_str(l));
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_round_trip_and_offsets() {
+        let old = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+        let new = "one\n2\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\neleven";
+        let patch = make_patch(old, new);
+        assert_eq!(apply_patch(old, &patch).unwrap(), new);
+
+        // Lines added above: hunks still land on their context
+        let shifted = format!("zero\nzero\n{}", old);
+        assert_eq!(apply_patch(&shifted, &patch).unwrap(), format!("zero\nzero\n{}", new));
+
+        // Into an empty body
+        assert_eq!(apply_patch("", &make_patch("", "a\nb\n")).unwrap(), "a\nb\n");
+
+        // CRLF bodies keep their line endings
+        let (old, new) = ("one\r\ntwo\r\n\r\nthree\r\n", "one\r\n2\r\n\r\nthree\r\n");
+        assert_eq!(apply_patch(old, &make_patch(old, new)).unwrap(), new);
+        assert_eq!(apply_patch(old, "@@ -1,3 +1,3 @@\n one\r\n-two\r\n+2\r\n\r\n").unwrap(), new);
+
+        assert_eq!(apply_patch("x\ny\n", &patch).unwrap_err(), "patch_mismatch");
+        assert_eq!(apply_patch(old, "not a diff").unwrap_err(), "invalid_patch");
+    }
+}
@@ -6,7 +6,12 @@ use std::{fs, path::{Path, PathBuf}};
 use uuid::Uuid;
 
 #[derive(Default, Debug, Clone)]
-pub struct ScanStats { pub files_scanned: i64, pub docs_added: i64, pub errors: i64 }
+pub struct ScanStats { pub files_scanned: i64, pub docs_added: i64, pub errors: i64, pub collisions: Vec<ScanCollision> }
+
+/// A file found at a trashed doc's path with a body that differs from the trashed version;
+/// it was scanned in as a new doc (`doc_id`) and the trashed one is left in the trash.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScanCollision { pub path: String, pub slug: String, pub doc_id: String, pub trashed_doc_id: String }
 
 pub fn scan_once(db: &Db, repo_path: &str, include: &[String], exclude: &[String]) -> Result<ScanStats, String> {
     let mut stats = ScanStats::default();
@@ -31,7 +36,10 @@ pub fn scan_once(db: &Db, repo_path: &str, include: &[String], exclude: &[String
                 if path.extension().and_then(|s| s.to_str()).unwrap_or("") != "md" { continue; }
                 stats.files_scanned += 1;
                 match upsert_doc(&db, &repo_path, path) {
-                    Ok(added) => if added { stats.docs_added += 1; },
+                    Ok((added, collision)) => {
+                        if added { stats.docs_added += 1; }
+                        stats.collisions.extend(collision);
+                    },
                     Err(e) => { if debug { eprintln!("[scan] upsert error for {}: {}", path.display(), e); } stats.errors += 1; },
                 }
             }
@@ -42,15 +50,15 @@ pub fn scan_once(db: &Db, repo_path: &str, include: &[String], exclude: &[String
 }
 
 /// Scan a single file path (absolute) under a given repo_root (absolute).
-pub fn scan_one_file(db: &Db, repo_root: &str, file_path: &str) -> Result<bool, String> {
+pub fn scan_one_file(db: &Db, repo_root: &str, file_path: &str) -> Result<(bool, Option<ScanCollision>), String> {
     let root = PathBuf::from(repo_root);
     let fp = PathBuf::from(file_path);
     if !fp.exists() { return Err("file not found".into()); }
-    if fp.extension().and_then(|s| s.to_str()).unwrap_or("") != "md" { return Ok(false); }
+    if fp.extension().and_then(|s| s.to_str()).unwrap_or("") != "md" { return Ok((false, None)); }
     upsert_doc(db, &root, &fp)
 }
 
-fn upsert_doc(db: &Db, repo_root: &Path, file_path: &Path) -> Result<bool, String> {
+fn upsert_doc(db: &Db, repo_root: &Path, file_path: &Path) -> Result<(bool, Option<ScanCollision>), String> {
     let content = fs::read_to_string(file_path).map_err(|e| e.to_string())?;
     let content_hash = blake3::hash(content.as_bytes()).to_hex().to_string();
     let slug = make_slug(repo_root, file_path);
@@ -71,19 +79,32 @@ fn upsert_doc(db: &Db, repo_root: &Path, file_path: &Path) -> Result<bool, Strin
     // Ensure folder
     let rel = file_path.strip_prefix(repo_root).unwrap_or(file_path);
     let folder_path = rel.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|| "".into());
-    let folder_id: String = {
-        // find existing folder
-        let fid: Option<String> = tx.query_row("SELECT id FROM folder WHERE repo_id=?1 AND path=?2", params![repo_id, folder_path], |r| r.get(0)).optional().unwrap_or(None);
-        if let Some(fid) = fid { fid } else {
-            let fid = Uuid::new_v4().to_string();
-            let fslug = folder_slug(&folder_path);
-            tx.execute("INSERT INTO folder(id,repo_id,path,slug) VALUES(?,?,?,?)", params![fid, repo_id, folder_path, fslug]).map_err(|e| e.to_string())?;
-            fid
-        }
-    };
+    let folder_id = crate::commands::ensure_folder(&tx, &repo_id, &folder_path)?;
 
     // Upsert doc by (repo_id, slug)
     let doc_id_opt: Option<String> = tx.query_row("SELECT id FROM doc WHERE repo_id=?1 AND slug=?2", params![repo_id, slug], |r| r.get(0)).optional().map_err(|e| e.to_string())?;
+
+    // A trashed doc keeps its file on disk: skip the file while it still holds the trashed
+    // body, but a different body at that path is a new doc (reported as a collision)
+    let mut trashed_doc_id = None;
+    if doc_id_opt.is_none() {
+        let trashed: Vec<(String, Option<String>)> = {
+            let mut stmt = tx.prepare("SELECT d.id, v.hash FROM doc d LEFT JOIN doc_version v ON v.id=d.current_version_id WHERE d.repo_id=?1 AND d.trashed_slug=?2 ORDER BY d.deleted_at DESC").map_err(|e| e.to_string())?;
+            let rows = stmt.query_map(params![repo_id, slug], |r| Ok((r.get(0)?, r.get(1)?))).map_err(|e| e.to_string())?;
+            rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+        };
+        if trashed.iter().any(|(id, hash)| hash.as_deref() == Some(format!("{}:{}", id, content_hash).as_str())) {
+            tx.commit().map_err(|e| e.to_string())?;
+            return Ok((false, None));
+        }
+        trashed_doc_id = trashed.into_iter().next().map(|(id, _)| id);
+    }
+
+    // The file's directory is where the doc lives, whatever the folder tree was reshaped to
+    if let Some(id) = &doc_id_opt {
+        tx.execute("UPDATE doc SET folder_id=?2 WHERE id=?1 AND folder_id IS NOT ?2", params![id, folder_id]).map_err(|e| e.to_string())?;
+    }
+
     let (doc_id, is_new_doc) = if let Some(id) = doc_id_opt { (id, false) } else {
         let id = Uuid::new_v4().to_string();
         let title = rel.file_stem().and_then(|s| s.to_str()).unwrap_or("");
@@ -107,14 +128,14 @@ fn upsert_doc(db: &Db, repo_root: &Path, file_path: &Path) -> Result<bool, Strin
 
     if changed {
         // Append version
-        let blob_id = Uuid::new_v4().to_string();
+        let blob_id = crate::blob::put_text(&tx, &content)?;
         let version_id = Uuid::new_v4().to_string();
-        tx.execute("INSERT INTO doc_blob(id,content,size_bytes) VALUES(?,?,?)", params![blob_id, content.as_bytes(), size]).map_err(|e| e.to_string())?;
         tx.execute("INSERT INTO doc_version(id,doc_id,blob_id,hash) VALUES(?,?,?,?)", params![version_id, doc_id, blob_id, version_hash]).map_err(|e| e.to_string())?;
         tx.execute("UPDATE doc SET current_version_id=?1, size_bytes=?2, line_count=?3, updated_at=datetime('now') WHERE id=?4", params![version_id, size, lines, doc_id]).map_err(|e| e.to_string())?;
         // Update FTS
-    tx.execute("INSERT INTO doc_fts(doc_fts,rowid) VALUES('delete',(SELECT rowid FROM doc WHERE id=?1))", params![doc_id]).ok();
-    tx.execute("INSERT INTO doc_fts(rowid,title,body,slug,repo_id) SELECT d.rowid,d.title,?1,d.slug,d.repo_id FROM doc d WHERE d.id=?2", params![content, doc_id]).map_err(|e| e.to_string())?;
+    crate::fts::reindex_doc(&tx, &doc_id, &content)?;
+    crate::fts::index_version(&tx, &version_id, &content)?;
+    crate::commands::reanchor_doc(&tx, &doc_id, &version_id, &content)?;
     }
 
     tx.commit().map_err(|e| e.to_string())?;
@@ -123,8 +144,10 @@ fn upsert_doc(db: &Db, repo_root: &Path, file_path: &Path) -> Result<bool, Strin
     // update links only if new or changed
     if changed || is_new_doc {
         crate::graph::update_links_for_doc(&db.0.lock(), &doc_id, &content)?;
+        crate::commands::refresh_embeddings(db, &doc_id, &content);
     }
-    Ok(is_new_doc || changed)
+    let collision = trashed_doc_id.map(|trashed_doc_id| ScanCollision { path: file_path.to_string_lossy().to_string(), slug, doc_id, trashed_doc_id });
+    Ok((is_new_doc || changed, collision))
 }
 
 fn make_slug(repo_root: &Path, file_path: &Path) -> String {
@@ -135,13 +158,6 @@ fn make_slug(repo_root: &Path, file_path: &Path) -> String {
     s
 }
 
-fn folder_slug(path: &str) -> String {
-    if path.is_empty() { return String::from("") }
-    let p = Path::new(path);
-    let last = p.file_name().and_then(|s| s.to_str()).unwrap_or(path);
-    last.replace(' ', "-")
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -155,6 +171,48 @@ mod tests {
         // relative path without extension, separators replaced with '__', spaces to '-'
         assert_eq!(slug, "notes__Deep-Topic");
     }
+
+    #[test]
+    fn test_rescan_trashed_path() {
+        let dir = std::env::temp_dir().join(format!("ae-scan-{}", Uuid::new_v4()));
+        let root = dir.join("repo");
+        fs::create_dir_all(root.join("notes")).unwrap();
+        let file = root.join("notes/a.md");
+        fs::write(&file, "old body\n").unwrap();
+        let db = crate::db::open_db(&dir.join("t.db")).expect("open db");
+        let root_str = root.to_string_lossy().to_string();
+        assert_eq!(scan_once(&db, &root_str, &[], &[]).unwrap().docs_added, 1);
+        let old_id: String = db.0.lock().query_row("SELECT id FROM doc WHERE slug='notes__a'", [], |r| r.get(0)).unwrap();
+        crate::commands::docs_delete_core(&db, &old_id).unwrap();
+
+        // Same body as the trashed version: still skipped, and the folder insert sticks
+        db.0.lock().execute_batch("UPDATE doc SET folder_id=(SELECT id FROM folder WHERE path=''); DELETE FROM folder WHERE path='notes'").unwrap();
+        let stats = scan_once(&db, &root_str, &[], &[]).unwrap();
+        assert_eq!((stats.docs_added, stats.collisions.len()), (0, 0));
+        let live: i64 = db.0.lock().query_row("SELECT COUNT(*) FROM doc WHERE is_deleted=0", [], |r| r.get(0)).unwrap();
+        let folders: i64 = db.0.lock().query_row("SELECT COUNT(*) FROM folder WHERE path='notes'", [], |r| r.get(0)).unwrap();
+        assert_eq!((live, folders), (0, 1));
+
+        // A new body at that path is a new doc, reported as a collision
+        fs::write(&file, "new body\n").unwrap();
+        let (added, collision) = scan_one_file(&db, &root_str, &file.to_string_lossy()).unwrap();
+        let c = collision.unwrap();
+        assert!(added);
+        assert_eq!((c.slug.as_str(), c.trashed_doc_id.as_str()), ("notes__a", old_id.as_str()));
+        assert_ne!(c.doc_id, old_id);
+
+        // Later rescans update the new doc without reporting it again
+        let stats = scan_once(&db, &root_str, &[], &[]).unwrap();
+        assert_eq!((stats.docs_added, stats.collisions.len()), (0, 0));
+
+        // A folder renamed in the app: the rescan puts the doc back under its file's folder
+        let repo_id: String = db.0.lock().query_row("SELECT id FROM repo", [], |r| r.get(0)).unwrap();
+        crate::commands::folders_rename_core(&db, &repo_id, "notes", "renamed").unwrap();
+        scan_once(&db, &root_str, &[], &[]).unwrap();
+        let folder: String = db.0.lock().query_row("SELECT f.path FROM doc d JOIN folder f ON f.id=d.folder_id WHERE d.id=?1", params![c.doc_id], |r| r.get(0)).unwrap();
+        assert_eq!(folder, "notes");
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
 
 // Watch filesystem for changes under repo_path and rescan modified markdown files.
@@ -186,7 +244,10 @@ pub fn watch_repo(db: std::sync::Arc<Db>, repo_path: String, include: Vec<String
                         if !ovm.matched(&p, false).is_whitelist() { continue; }
                     }
                     // Rescan one file
-                    let _ = upsert_doc(&db, Path::new(&repo_path), &p);
+                    if let Ok((changed, collision)) = upsert_doc(&db, Path::new(&repo_path), &p) {
+                        if let Some(c) = collision { let _ = app.emit("progress.scan", serde_json::json!({"event": "collision", "collision": c})); }
+                        if changed { crate::commands::notify_saved_searches(&db, &app); }
+                    }
                     let _ = app.emit("progress.scan", serde_json::json!({
                         "event": match evt.kind { EventKind::Create(_) => "create", EventKind::Modify(_) => "modify", EventKind::Remove(_) => "remove", _ => "other" },
                         "path": p.to_string_lossy(),
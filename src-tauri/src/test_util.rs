@@ -0,0 +1,30 @@
+//! Fixtures shared by the unit tests.
+
+use crate::db::{open_db, Db};
+use rusqlite::params;
+
+/// A fresh database under the temp dir (`ae-<name>-<uuid>.db`).
+pub fn temp_db(name: &str) -> Db {
+    let p = std::env::temp_dir().join(format!("ae-{}-{}.db", name, uuid::Uuid::new_v4()));
+    open_db(&p).expect("open db")
+}
+
+/// A fresh database with repo `r` (path `/r`), its root folder `f` and `docs`, `(id, slug)`
+/// each, titled with the upper-cased slug and indexed with an empty body.
+pub fn db_with_docs(name: &str, docs: &[(&str, &str)]) -> Db {
+    let db = temp_db(name);
+    {
+        let conn = db.0.lock();
+        conn.execute("INSERT INTO repo(id,name,path) VALUES('r','r','/r')", []).unwrap();
+        conn.execute("INSERT INTO folder(id,repo_id,path,slug) VALUES('f','r','','')", []).unwrap();
+        for (id, slug) in docs {
+            conn.execute(
+                "INSERT INTO doc(id,repo_id,folder_id,slug,title) VALUES(?1,'r','f',?2,upper(?2))",
+                params![id, slug],
+            )
+            .unwrap();
+            crate::fts::insert_doc(&conn, id, "").unwrap();
+        }
+    }
+    db
+}
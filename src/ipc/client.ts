@@ -63,8 +63,9 @@ async function safeInvoke<T>(cmd: string, args?: any): Promise<T> {
     case 'anchors_delete':
       return { deleted: true } as any as T
     case 'ai_run': {
-      const provider = (args?.provider === 'default') ? 'openrouter' : (args?.provider || 'local')
-      return { trace_id: 'stub-trace', text: `[${provider}]\nPrompt: ${args?.prompt || ''}`, provider, model: provider === 'openrouter' ? 'openrouter/auto' : '' } as any as T
+      const req = args?.payload || {}
+      const provider = (req.provider === 'default') ? 'openrouter' : (req.provider || 'local')
+      return { trace_id: 'stub-trace', text: `[${provider}]\nPrompt: ${req.prompt || ''}`, provider, model: provider === 'openrouter' ? 'openrouter/auto' : '' } as any as T
     }
     case 'export_docs':
       return [
@@ -107,7 +108,7 @@ export const scanRepo = (
   filters?: { include?: string[]; exclude?: string[] },
   watch?: boolean,
   debounce?: number,
-) => safeInvoke<{ job_id: string; files_scanned: number; docs_added: number; errors: number }>('scan_repo', { repoPath, filters, watch, debounce })
+) => safeInvoke<{ job_id: string; files_scanned: number; docs_added: number; errors: number; collisions: { path: string; slug: string; doc_id: string; trashed_doc_id: string }[] }>('scan_repo', { repoPath, filters, watch, debounce })
 
 export const docsCreate = (repo_id: string, slug: string, title: string, body: string) =>
   safeInvoke<{ doc_id: string }>('docs_create', { payload: { repo_id, slug, title, body } })
@@ -139,8 +140,8 @@ export const graphPath = (start_id: string, end_id: string) => {
 }
 
 export type AiRunResponse = { trace_id: string; text: string; provider?: string; model?: string }
-export const aiRun = (provider: string, doc_id: string, prompt: string, anchor_id?: string) =>
-  safeInvoke<AiRunResponse>('ai_run', { provider, docId: doc_id, anchorId: anchor_id, prompt })
+export const aiRun = (provider: string, doc_id: string, prompt: string, anchor_id?: string, template?: string) =>
+  safeInvoke<AiRunResponse>('ai_run', { payload: { provider, doc_id, anchor_id, prompt, template } })
 
 export const anchorsUpsert = (doc_id: string, anchor_id: string, line: number) =>
   safeInvoke<{ ok: boolean }>('anchors_upsert', { docId: doc_id, anchorId: anchor_id, line })